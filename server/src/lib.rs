@@ -1,5 +1,6 @@
 use spacetimedb::{
-    reducer, table, view, Identity, ReducerContext, SpacetimeType, Table, Timestamp, ViewContext,
+    reducer, table, view, Identity, ReducerContext, ScheduleAt, SpacetimeType, Table, TimeDuration,
+    Timestamp, ViewContext,
 };
 
 // --- Custom Types ---
@@ -77,6 +78,198 @@ pub struct CurrentClip {
     encrypted_data: Vec<u8>,
     size_bytes: u64,
     updated_at: Timestamp,
+    #[default(0)]
+    stored_bytes: u64,
+    /// Device name the sender meant the clip for. Informational only: every
+    /// device of the user can still decrypt it.
+    #[default(None)]
+    intended_device: Option<String>,
+    /// When the clip stops being served, if it was synced with a TTL. A
+    /// `clip_expiry` sweep deletes the row at this time.
+    #[default(None)]
+    expires_at: Option<Timestamp>,
+    /// App the clip was copied from (e.g. an X11 window class), if the sender
+    /// shares it. Informational only, like `intended_device`.
+    #[default(None)]
+    source_app: Option<String>,
+}
+
+/// Scheduled deletion of an expiring current clip. The sweep only deletes the
+/// clip if it is still expired when it runs, so a newer clip survives it.
+#[table(accessor = clip_expiry, scheduled(expire_clip))]
+pub struct ClipExpiry {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+    user_id: u64,
+}
+
+/// The last clip each device synced, kept after another device's clip
+/// replaces it as the current clip so an overwritten clip can be restored.
+/// One row per device. Clips synced with a TTL aren't kept, and a cleared or
+/// consumed clip is dropped here too.
+#[table(accessor = device_clip)]
+pub struct DeviceClip {
+    #[primary_key]
+    #[auto_inc]
+    id: u64,
+    #[index(btree)]
+    user_id: u64,
+    sender_device_id: String,
+    content_type: ClipContentType,
+    encrypted_data: Vec<u8>,
+    size_bytes: u64,
+    stored_bytes: u64,
+    updated_at: Timestamp,
+}
+
+/// Return type for the `my_recent_by_device` view: a `DeviceClip` without its
+/// data, which is only ever restored server-side.
+#[derive(SpacetimeType, Clone, Debug)]
+pub struct DeviceClipView {
+    pub sender_device_id: String,
+    pub content_type: ClipContentType,
+    pub size_bytes: u64,
+    pub updated_at: Timestamp,
+}
+
+/// The last clip taken by `consume_current_clip`, keyed by user. The consume
+/// reducer moves the clip here (removing it from `current_clip`) in one
+/// transaction; the consuming client reads it back via `my_consumed_clip`,
+/// matching on the `request_id` it supplied.
+#[table(accessor = consumed_clip)]
+pub struct ConsumedClip {
+    #[primary_key]
+    user_id: u64,
+    request_id: String,
+    sender_device_id: String,
+    content_type: ClipContentType,
+    encrypted_data: Vec<u8>,
+    size_bytes: u64,
+    consumed_at: Timestamp,
+    #[default(0)]
+    stored_bytes: u64,
+}
+
+/// A device's confirmation that it applied the user's current clip, named by
+/// the clip's `updated_at`. Cleared whenever a new clip is stored, so it only
+/// ever holds acks for the current clip.
+#[table(accessor = clip_ack)]
+pub struct ClipAck {
+    #[primary_key]
+    #[auto_inc]
+    id: u64,
+    #[index(btree)]
+    user_id: u64,
+    device_id: String,
+    clip_updated_at: Timestamp,
+    acked_at: Timestamp,
+}
+
+/// Singleton holding the module's own identity, so clients can pin the server
+/// they expect to talk to (`server_fingerprint` in the client config).
+#[table(accessor = server_info)]
+pub struct ServerInfo {
+    #[primary_key]
+    id: u32,
+    module_identity: Identity,
+}
+
+/// Singleton claimed by the first signup, which becomes the admin. Claiming it
+/// is a primary-key insert, so at most one registration can ever win it.
+#[table(accessor = bootstrap)]
+pub struct Bootstrap {
+    #[primary_key]
+    id: u32,
+    claimed_at: Timestamp,
+}
+
+/// A shared clipboard whose clips are encrypted to every member's public key.
+#[table(accessor = team)]
+pub struct Team {
+    #[primary_key]
+    #[auto_inc]
+    id: u64,
+    #[unique]
+    name: String,
+    owner_id: u64,
+    /// Bumped on every membership change so stale clients can't encrypt to an
+    /// outdated member list (e.g. one that still includes a removed member).
+    members_version: u64,
+    created_at: Timestamp,
+}
+
+#[table(accessor = team_member)]
+pub struct TeamMember {
+    #[primary_key]
+    #[auto_inc]
+    id: u64,
+    #[index(btree)]
+    team_id: u64,
+    #[index(btree)]
+    user_id: u64,
+    added_at: Timestamp,
+}
+
+#[table(accessor = team_clip)]
+pub struct TeamClip {
+    #[primary_key]
+    team_id: u64,
+    sender_user_id: u64,
+    sender_device_id: String,
+    content_type: ClipContentType,
+    encrypted_data: Vec<u8>,
+    size_bytes: u64,
+    updated_at: Timestamp,
+    #[default(0)]
+    stored_bytes: u64,
+}
+
+/// Return type for the `my_teams` view.
+#[derive(SpacetimeType, Clone, Debug)]
+pub struct TeamView {
+    pub team_id: u64,
+    pub name: String,
+    pub owner_id: u64,
+    pub members_version: u64,
+}
+
+/// Return type for the `my_team_members` view. Exposes each member's public
+/// key so clients can encrypt team clips to all of them.
+#[derive(SpacetimeType, Clone, Debug)]
+pub struct TeamMemberView {
+    pub team_id: u64,
+    pub user_id: u64,
+    pub username: String,
+    pub public_key: Vec<u8>,
+}
+
+/// Return type for the admin-only `server_stats` view. Aggregates only; no
+/// per-user data is exposed.
+#[derive(SpacetimeType, Clone, Debug)]
+pub struct ServerStats {
+    pub total_users: u64,
+    pub total_devices: u64,
+    pub outstanding_invite_codes: u64,
+    pub total_teams: u64,
+    /// Sum of `size_bytes` (plaintext size) over every stored clip
+    /// (personal and team)
+    pub total_clip_bytes: u64,
+    /// Sum of `stored_bytes` (encrypted size) over every stored clip
+    pub total_stored_bytes: u64,
+}
+
+/// Optional details of a synced clip; all None is a plain clip.
+#[derive(SpacetimeType, Clone, Debug, Default)]
+pub struct ClipOptions {
+    /// Device name the clip is meant for. Informational: every device can
+    /// still decrypt it
+    pub intended_device: Option<String>,
+    /// Delete the clip this many seconds after it is stored
+    pub ttl_secs: Option<u64>,
+    /// App the clip was copied from (informational)
+    pub source_app: Option<String>,
 }
 
 #[table(accessor = invite_code, private)]
@@ -88,6 +281,7 @@ pub struct InviteCode {
     expires_at: Timestamp,
 }
 
+#[derive(Clone)]
 #[table(accessor = failed_login)]
 pub struct FailedLogin {
     #[primary_key]
@@ -97,6 +291,43 @@ pub struct FailedLogin {
     locked_until: Timestamp,
 }
 
+/// Outcome of the caller's last password-checked reducer, keyed by identity.
+/// A wrong password has to commit its `failed_login` update to count toward
+/// the lockout, so those reducers return Ok and report the rejection here;
+/// clients read it back through `my_auth_result`.
+#[table(accessor = auth_result)]
+pub struct AuthResult {
+    #[primary_key]
+    identity: Identity,
+    /// Why the attempt was refused, or None if it succeeded
+    error: Option<String>,
+    reported_at: Timestamp,
+}
+
+/// The page of devices the caller last asked for with `get_devices_page`,
+/// keyed by identity. Reducers can't return data and views can't take
+/// arguments, so clients read the page back through `my_device_page`.
+#[table(accessor = device_page)]
+pub struct DevicePage {
+    #[primary_key]
+    identity: Identity,
+    /// Devices on the account, of which `devices` is one page
+    total: u64,
+    devices: Vec<DeviceView>,
+}
+
+/// Recurring sweep of stale `failed_login` rows, and of `auth_result` rows
+/// whose connection went away without `client_disconnected` running. Failed
+/// attempts against usernames that never log in would otherwise stay forever.
+/// Scheduled when the first failed attempt is stored.
+#[table(accessor = failed_login_sweep, scheduled(sweep_failed_logins))]
+pub struct FailedLoginSweep {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+}
+
 // --- Constants ---
 
 const MAX_ENCRYPTED_SIZE: usize = 55 * 1024 * 1024;
@@ -105,28 +336,106 @@ const LOCKOUT_DURATION_MICROS: i64 = 15 * 60 * 1_000_000; // 15 minutes
 const ATTEMPT_WINDOW_MICROS: i64 = 15 * 60 * 1_000_000; // 15 minutes
 const INVITE_CODE_TTL_MICROS: i64 = 24 * 60 * 60 * 1_000_000; // 24 hours
 const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_CLIP_TTL_SECS: u64 = 7 * 24 * 60 * 60; // 7 days
 const MIN_INVITE_CODE_LENGTH: usize = 32;
 const MIN_INVITE_CODE_UNIQUE_CHARS: usize = 16;
+const MAX_TEAM_NAME_LENGTH: usize = 64;
+const MAX_DEVICES_PER_USER: usize = 10;
+const MAX_DEVICES_PER_ADMIN: usize = 50;
+const MAX_PAGE_SIZE: u64 = 100;
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+/// Clients generate UUIDs (36 characters); leave some slack for other formats.
+const MAX_DEVICE_ID_LENGTH: usize = 64;
+const MAX_DEVICE_NAME_LENGTH: usize = 128;
+const MAX_SOURCE_APP_LENGTH: usize = 128;
+/// Clients refuse to serialize payloads over 64 MB.
+const MAX_PLAINTEXT_SIZE: u64 = 64 * 1024 * 1024;
+/// Fixed allowance for age headers (one stanza per recipient) and zstd framing
+/// when checking a reported plaintext size against the encrypted size.
+const ENCRYPTION_OVERHEAD_SLACK: u64 = 64 * 1024;
+/// age X25519 recipients are bech32 strings: "age1" followed by 58 data characters.
+const AGE_RECIPIENT_PREFIX: &str = "age1";
+const AGE_RECIPIENT_LENGTH: usize = 62;
+const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
 
 // --- Lifecycle Reducers ---
 
 #[reducer(init)]
-pub fn init(_ctx: &ReducerContext) {
+pub fn init(ctx: &ReducerContext) {
+    ensure_server_info(ctx);
     log::info!("clipsync module initialized");
 }
 
 #[reducer(client_connected)]
 pub fn client_connected(ctx: &ReducerContext) {
+    // Modules published before `server_info` existed never ran `init` with it
+    ensure_server_info(ctx);
     log::info!("Client connected: {:?}", ctx.sender());
 }
 
 #[reducer(client_disconnected)]
 pub fn client_disconnected(ctx: &ReducerContext) {
+    // Only the connection that made the call reads its outcome or page
+    ctx.db.auth_result().identity().delete(ctx.sender());
+    ctx.db.device_page().identity().delete(ctx.sender());
     log::info!("Client disconnected: {:?}", ctx.sender());
 }
 
 // --- Helper ---
 
+/// Store a new failed login record, making sure the sweep that expires it is
+/// scheduled.
+fn insert_failed_login(ctx: &ReducerContext, record: FailedLogin) {
+    ensure_failed_login_sweep(ctx);
+    ctx.db.failed_login().insert(record);
+}
+
+fn ensure_failed_login_sweep(ctx: &ReducerContext) {
+    if ctx.db.failed_login_sweep().count() == 0 {
+        ctx.db.failed_login_sweep().insert(FailedLoginSweep {
+            scheduled_id: 0,
+            scheduled_at: ScheduleAt::Interval(TimeDuration::from_micros(ATTEMPT_WINDOW_MICROS)),
+        });
+    }
+}
+
+fn device_view(device: &Device) -> DeviceView {
+    DeviceView {
+        id: device.id,
+        device_id: device.device_id.clone(),
+        device_name: device.device_name.clone(),
+        registered_at: device.registered_at,
+    }
+}
+
+fn ensure_server_info(ctx: &ReducerContext) {
+    if ctx.db.server_info().id().find(&0).is_none() {
+        ctx.db.server_info().insert(ServerInfo {
+            id: 0,
+            module_identity: ctx.identity(),
+        });
+    }
+}
+
+/// Try to claim the bootstrap row for a new signup. Returns whether this
+/// signup is the first user (and so the admin). Modules that had users
+/// before the row existed have it claimed on the next signup instead.
+fn claim_bootstrap(ctx: &ReducerContext) -> bool {
+    if ctx.db.bootstrap().id().find(&0).is_some() {
+        return false;
+    }
+    let had_users = ctx.db.user().count() > 0;
+    let claimed = ctx
+        .db
+        .bootstrap()
+        .try_insert(Bootstrap {
+            id: 0,
+            claimed_at: ctx.timestamp,
+        })
+        .is_ok();
+    claimed && !had_users
+}
+
 fn get_user_id(ctx: &ReducerContext) -> Result<u64, String> {
     ctx.db
         .user_identity()
@@ -136,7 +445,52 @@ fn get_user_id(ctx: &ReducerContext) -> Result<u64, String> {
         .ok_or_else(|| "Not logged in. Run `clipsync setup` first.".to_string())
 }
 
-fn upsert_device(ctx: &ReducerContext, user_id: u64, device_id: &str, device_name: &str) {
+fn is_team_member(ctx: &ReducerContext, team_id: u64, user_id: u64) -> bool {
+    ctx.db
+        .team_member()
+        .team_id()
+        .filter(&team_id)
+        .any(|m| m.user_id == user_id)
+}
+
+/// Look up a team and check that the caller is its owner or an admin.
+fn get_managed_team(ctx: &ReducerContext, team_name: &str) -> Result<(Team, u64), String> {
+    let user_id = get_user_id(ctx)?;
+    let team = ctx
+        .db
+        .team()
+        .name()
+        .find(&team_name.to_string())
+        .ok_or_else(|| format!("Team not found: {}", team_name))?;
+    let is_admin = ctx
+        .db
+        .user()
+        .id()
+        .find(&user_id)
+        .map(|u| u.is_admin)
+        .unwrap_or(false);
+    if team.owner_id != user_id && !is_admin {
+        return Err("Only the team owner or an admin can manage this team".to_string());
+    }
+    Ok((team, user_id))
+}
+
+fn bump_members_version(ctx: &ReducerContext, team: Team) {
+    ctx.db.team().id().update(Team {
+        members_version: team.members_version + 1,
+        ..team
+    });
+}
+
+/// Update an existing device, or register a new one if the user is under their
+/// device cap.
+fn upsert_device(
+    ctx: &ReducerContext,
+    user_id: u64,
+    device_id: &str,
+    device_name: &str,
+) -> Result<(), String> {
+    let mut device_count = 0;
     for existing in ctx.db.device().user_id().filter(&user_id) {
         if existing.device_id == device_id {
             ctx.db.device().id().update(Device {
@@ -144,8 +498,28 @@ fn upsert_device(ctx: &ReducerContext, user_id: u64, device_id: &str, device_nam
                 registered_at: ctx.timestamp,
                 ..existing
             });
-            return;
+            return Ok(());
         }
+        device_count += 1;
+    }
+
+    let is_admin = ctx
+        .db
+        .user()
+        .id()
+        .find(&user_id)
+        .map(|u| u.is_admin)
+        .unwrap_or(false);
+    let limit = if is_admin {
+        MAX_DEVICES_PER_ADMIN
+    } else {
+        MAX_DEVICES_PER_USER
+    };
+    if device_count >= limit {
+        return Err(format!(
+            "Device limit reached ({} devices). Unregister an old device first.",
+            limit
+        ));
     }
 
     ctx.db.device().insert(Device {
@@ -155,6 +529,100 @@ fn upsert_device(ctx: &ReducerContext, user_id: u64, device_id: &str, device_nam
         device_name: device_name.to_string(),
         registered_at: ctx.timestamp,
     });
+    Ok(())
+}
+
+/// Check that a device id is short and UUID-like (ASCII letters, digits, `-`, `_`).
+fn validate_device_id(device_id: &str) -> Result<(), String> {
+    if device_id.is_empty() || device_id.len() > MAX_DEVICE_ID_LENGTH {
+        return Err(format!(
+            "Device ID must be between 1 and {} characters",
+            MAX_DEVICE_ID_LENGTH
+        ));
+    }
+    if !device_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("Device ID may only contain letters, digits, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
+/// Check that a device name is of reasonable length and printable.
+fn validate_device_name(device_name: &str) -> Result<(), String> {
+    if device_name.chars().count() > MAX_DEVICE_NAME_LENGTH {
+        return Err(format!(
+            "Device name must be at most {} characters",
+            MAX_DEVICE_NAME_LENGTH
+        ));
+    }
+    if device_name.chars().any(char::is_control) {
+        return Err("Device name cannot contain control characters".to_string());
+    }
+    Ok(())
+}
+
+fn validate_source_app(source_app: &str) -> Result<(), String> {
+    if source_app.is_empty() {
+        return Err("Source app cannot be empty".to_string());
+    }
+    if source_app.chars().count() > MAX_SOURCE_APP_LENGTH {
+        return Err(format!(
+            "Source app must be at most {} characters",
+            MAX_SOURCE_APP_LENGTH
+        ));
+    }
+    if source_app.chars().any(char::is_control) {
+        return Err("Source app cannot contain control characters".to_string());
+    }
+    Ok(())
+}
+
+/// Check an uploaded clip's size and return its stored (encrypted) size. The
+/// client-reported plaintext `size_bytes` can't be verified exactly, but it
+/// must be within the client's payload limit and large enough to account for
+/// the ciphertext: compression only shrinks data, and encryption adds a small
+/// per-chunk overhead on top.
+fn validate_clip_size(encrypted_data: &[u8], size_bytes: u64) -> Result<u64, String> {
+    if encrypted_data.len() > MAX_ENCRYPTED_SIZE {
+        return Err(format!(
+            "Encrypted data too large: {} bytes (max {})",
+            encrypted_data.len(),
+            MAX_ENCRYPTED_SIZE
+        ));
+    }
+    let stored_bytes = encrypted_data.len() as u64;
+    if size_bytes > MAX_PLAINTEXT_SIZE {
+        return Err(format!(
+            "Clip too large: {} bytes (max {})",
+            size_bytes, MAX_PLAINTEXT_SIZE
+        ));
+    }
+    if stored_bytes > size_bytes + size_bytes / 64 + ENCRYPTION_OVERHEAD_SLACK {
+        return Err(format!(
+            "Reported size {} bytes is implausible for {} bytes of encrypted data",
+            size_bytes, stored_bytes
+        ));
+    }
+    Ok(stored_bytes)
+}
+
+/// Check that a public key is the canonical (lowercase bech32) string form of an
+/// age X25519 recipient. The module can't depend on the age crate, so this checks
+/// shape only; it still catches garbage that would break encryption for every device.
+fn validate_public_key(public_key: &[u8]) -> Result<(), String> {
+    let key = std::str::from_utf8(public_key).map_err(|_| "Invalid public key".to_string())?;
+    if key.len() != AGE_RECIPIENT_LENGTH || !key.starts_with(AGE_RECIPIENT_PREFIX) {
+        return Err("Invalid public key: expected an age1... recipient".to_string());
+    }
+    if !key[AGE_RECIPIENT_PREFIX.len()..]
+        .chars()
+        .all(|c| BECH32_CHARSET.contains(c))
+    {
+        return Err("Invalid public key: not lowercase bech32".to_string());
+    }
+    Ok(())
 }
 
 /// Hash a password with Argon2id using the provided RNG for salt generation.
@@ -188,140 +656,339 @@ fn verify_password_argon2(password: &str, hash_str: &str) -> Result<(), String>
         .map_err(|_| "Authentication failed".to_string())
 }
 
-/// Record a failed login attempt and return an error.
-/// Implements brute force protection with account lockout.
-fn record_failed_login(ctx: &ReducerContext, username: &String) -> String {
-    let now = ctx.timestamp;
-    let lockout_until = Timestamp::from_micros_since_unix_epoch(
-        now.to_micros_since_unix_epoch() + LOCKOUT_DURATION_MICROS,
-    );
+// --- Time helpers ---
+//
+// Pure functions of `now` so the lockout and expiry math can be tested without
+// a `ReducerContext`.
 
-    if let Some(existing) = ctx.db.failed_login().username().find(username) {
-        let new_count = existing.attempt_count + 1;
-        let locked_until = if new_count >= MAX_FAILED_ATTEMPTS {
-            lockout_until
-        } else {
-            existing.locked_until
-        };
-        ctx.db.failed_login().username().update(FailedLogin {
-            attempt_count: new_count,
-            locked_until,
-            ..existing
-        });
+fn add_micros(t: Timestamp, micros: i64) -> Timestamp {
+    Timestamp::from_micros_since_unix_epoch(t.to_micros_since_unix_epoch() + micros)
+}
+
+/// When an account should be locked until after its `attempt_count`th failure.
+fn locked_until_after_failure(
+    attempt_count: u32,
+    previous: Timestamp,
+    now: Timestamp,
+) -> Timestamp {
+    if attempt_count >= MAX_FAILED_ATTEMPTS {
+        add_micros(now, LOCKOUT_DURATION_MICROS)
     } else {
-        ctx.db.failed_login().insert(FailedLogin {
-            username: username.clone(),
-            attempt_count: 1,
-            first_attempt_at: now,
-            locked_until: Timestamp::UNIX_EPOCH,
-        });
+        previous
     }
-
-    "Authentication failed".to_string()
 }
 
-/// Clear failed login attempts on successful authentication.
-fn clear_failed_logins(ctx: &ReducerContext, username: &String) {
-    ctx.db
-        .failed_login()
-        .username()
-        .delete(username);
+fn is_locked_out(record: &FailedLogin, now: Timestamp) -> bool {
+    record.locked_until > now
 }
 
-/// Check if the account is locked due to too many failed attempts.
-/// Also resets the counter if the attempt window has expired.
-fn check_brute_force_lockout(ctx: &ReducerContext, username: &String) -> Result<(), String> {
-    let now = ctx.timestamp;
-    if let Some(record) = ctx.db.failed_login().username().find(username) {
-        // Check if currently locked out
-        if record.locked_until > now {
-            return Err("Authentication failed".to_string());
-        }
+/// Whether the window that failed attempts are counted in has passed, so the
+/// counter can start over.
+fn attempt_window_expired(record: &FailedLogin, now: Timestamp) -> bool {
+    now > add_micros(record.first_attempt_at, ATTEMPT_WINDOW_MICROS)
+}
 
-        // Check if the attempt window has expired; if so, reset the counter
-        let window_end = Timestamp::from_micros_since_unix_epoch(
-            record.first_attempt_at.to_micros_since_unix_epoch() + ATTEMPT_WINDOW_MICROS,
-        );
-        if now > window_end {
-            // Window expired, reset the record
-            ctx.db
-                .failed_login()
-                .username()
-                .delete(username);
-        }
-    }
-    Ok(())
+/// Whether an auth result is too old for its caller to still be waiting on.
+fn auth_result_stale(result: &AuthResult, now: Timestamp) -> bool {
+    now > add_micros(result.reported_at, ATTEMPT_WINDOW_MICROS)
 }
 
-// --- Reducers ---
+/// Whether a failed login record no longer affects anything: its lockout is
+/// over and its attempts have aged out of the window.
+fn failed_login_stale(record: &FailedLogin, now: Timestamp) -> bool {
+    !is_locked_out(record, now) && attempt_window_expired(record, now)
+}
 
-/// Authenticate a user. Creates a new account if the username doesn't exist,
-/// or logs in if it does. Either way, links this connection's identity to the
-/// user and registers the device.
-///
-/// The first user created becomes admin and does not need an invite code.
-/// All subsequent registrations require a valid, unused invite code.
-#[reducer]
-pub fn authenticate(
-    ctx: &ReducerContext,
-    username: String,
-    password: String,
-    encrypted_private_key: Vec<u8>,
-    public_key: Vec<u8>,
-    device_id: String,
-    device_name: String,
-    invite_code: String,
-) -> Result<(), String> {
-    if username.is_empty() {
-        return Err("Username cannot be empty".to_string());
-    }
-    if password.len() < MIN_PASSWORD_LENGTH {
-        return Err("Authentication failed".to_string());
-    }
-    if device_id.is_empty() {
-        return Err("Device ID cannot be empty".to_string());
+/// Expiry time for a clip synced at `now` with a TTL of `ttl_secs`.
+fn clip_expires_at(now: Timestamp, ttl_secs: u64) -> Result<Timestamp, String> {
+    if ttl_secs == 0 || ttl_secs > MAX_CLIP_TTL_SECS {
+        return Err(format!(
+            "Clip TTL must be between 1 and {} seconds",
+            MAX_CLIP_TTL_SECS
+        ));
     }
+    Ok(add_micros(now, ttl_secs as i64 * 1_000_000))
+}
 
-    // Check if username already exists
-    let user = ctx.db.user().username().find(&username);
+/// Whether a clip with the given expiry should no longer be served.
+fn clip_expired(expires_at: Option<Timestamp>, now: Timestamp) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= now)
+}
 
-    let user_id = if let Some(existing_user) = user {
-        // Login: check brute force lockout before attempting password verification
-        check_brute_force_lockout(ctx, &username)?;
+fn invite_expires_at(created_at: Timestamp) -> Timestamp {
+    add_micros(created_at, INVITE_CODE_TTL_MICROS)
+}
 
-        // Verify password with Argon2id
-        if verify_password_argon2(&password, &existing_user.password_hash).is_err() {
-            return Err(record_failed_login(ctx, &username));
-        }
+fn invite_expired(invite: &InviteCode, now: Timestamp) -> bool {
+    invite.expires_at < now
+}
 
-        // Successful login: clear any failed login records
-        clear_failed_logins(ctx, &username);
+/// Why an invite code can't be used for a signup.
+#[derive(Debug, PartialEq, Eq)]
+enum InviteRejection {
+    /// No such code (including an empty one)
+    Unknown,
+    /// The code exists but is past its expiry, so it should be deleted
+    Expired,
+}
 
-        existing_user.id
-    } else {
-        // Signup: check brute force lockout (prevents invite code guessing)
-        check_brute_force_lockout(ctx, &username)?;
+/// Check the invite code a signup presented against the stored `invite`
+/// found under it, if any.
+fn check_invite(invite: Option<&InviteCode>, now: Timestamp) -> Result<(), InviteRejection> {
+    match invite {
+        None => Err(InviteRejection::Unknown),
+        Some(invite) if invite_expired(invite, now) => Err(InviteRejection::Expired),
+        Some(_) => Ok(()),
+    }
+}
 
-        let is_first_user = ctx.db.user().iter().next().is_none();
+/// Gate a login attempt at `now` on the username's failed login `record`.
+/// Fails while locked out; otherwise returns whether the record's attempt
+/// window has expired, so it should be dropped and the count start over.
+fn check_lockout(record: Option<&FailedLogin>, now: Timestamp) -> Result<bool, String> {
+    match record {
+        Some(record) if is_locked_out(record, now) => Err("Authentication failed".to_string()),
+        Some(record) => Ok(attempt_window_expired(record, now)),
+        None => Ok(false),
+    }
+}
+
+/// The failed login record after another failed attempt at `now`, locking
+/// the account once it reaches `MAX_FAILED_ATTEMPTS`.
+fn failed_login_after_failure(
+    record: Option<FailedLogin>,
+    username: &str,
+    now: Timestamp,
+) -> FailedLogin {
+    match record {
+        Some(existing) => {
+            let attempt_count = existing.attempt_count + 1;
+            let locked_until =
+                locked_until_after_failure(attempt_count, existing.locked_until, now);
+            FailedLogin {
+                attempt_count,
+                locked_until,
+                ..existing
+            }
+        }
+        None => FailedLogin {
+            username: username.to_string(),
+            attempt_count: 1,
+            first_attempt_at: now,
+            locked_until: Timestamp::UNIX_EPOCH,
+        },
+    }
+}
+
+/// Whether a password attempt got in. A rejected attempt only counts toward
+/// the lockout if the calling reducer commits (see `reject_attempt`).
+#[derive(Debug, PartialEq, Eq)]
+enum PasswordCheck {
+    Accepted,
+    Rejected,
+}
+
+/// Check a password attempt at `now` against the username's failed login
+/// `record`, returning the record to store afterwards (None to delete it).
+/// Refused with an error while locked out, without running `verify` or
+/// counting the attempt; a wrong password counts toward the lockout.
+fn attempt_password(
+    record: Option<FailedLogin>,
+    username: &str,
+    now: Timestamp,
+    verify: impl FnOnce() -> bool,
+) -> Result<(PasswordCheck, Option<FailedLogin>), String> {
+    let record = if check_lockout(record.as_ref(), now)? {
+        None
+    } else {
+        record
+    };
+    if verify() {
+        Ok((PasswordCheck::Accepted, None))
+    } else {
+        let record = failed_login_after_failure(record, username, now);
+        Ok((PasswordCheck::Rejected, Some(record)))
+    }
+}
+
+/// Check `password` against `user`'s account behind the brute force lockout,
+/// storing the updated failed login record.
+fn check_password(
+    ctx: &ReducerContext,
+    user: &User,
+    password: &str,
+) -> Result<PasswordCheck, String> {
+    let existing = ctx.db.failed_login().username().find(&user.username);
+    let had_record = existing.is_some();
+    let (check, record) = attempt_password(existing, &user.username, ctx.timestamp, || {
+        verify_password_argon2(password, &user.password_hash).is_ok()
+    })?;
+    match record {
+        Some(record) if had_record => {
+            ctx.db.failed_login().username().update(record);
+        }
+        Some(record) => {
+            insert_failed_login(ctx, record);
+        }
+        None => {
+            ctx.db.failed_login().username().delete(&user.username);
+        }
+    }
+    Ok(check)
+}
+
+/// Record a failed login attempt, for brute force protection with account
+/// lockout. A reducer returning Err rolls this back, so callers refuse the
+/// attempt with `reject_attempt` instead.
+fn record_failed_login(ctx: &ReducerContext, username: &String) {
+    let existing = ctx.db.failed_login().username().find(username);
+    let exists = existing.is_some();
+    let record = failed_login_after_failure(existing, username, ctx.timestamp);
+    if exists {
+        ctx.db.failed_login().username().update(record);
+    } else {
+        insert_failed_login(ctx, record);
+    }
+}
+
+/// Store the outcome of the caller's password-checked reducer for
+/// `my_auth_result`. Every Ok return of such a reducer reports one, so the
+/// row never describes an earlier call.
+fn report_auth_result(ctx: &ReducerContext, error: Option<String>) {
+    let result = AuthResult {
+        identity: ctx.sender(),
+        error,
+        reported_at: ctx.timestamp,
+    };
+    if ctx.db.auth_result().identity().find(ctx.sender()).is_some() {
+        ctx.db.auth_result().identity().update(result);
+    } else {
+        ctx.db.auth_result().insert(result);
+    }
+}
+
+/// Refuse the caller after a failed attempt was recorded. Returns Ok so the
+/// attempt commits; the client sees the refusal in `my_auth_result`.
+fn reject_attempt(ctx: &ReducerContext) -> Result<(), String> {
+    report_auth_result(ctx, Some("Authentication failed".to_string()));
+    Ok(())
+}
+
+/// Clear failed login attempts on successful authentication.
+fn clear_failed_logins(ctx: &ReducerContext, username: &String) {
+    ctx.db.failed_login().username().delete(username);
+}
+
+/// Scheduled sweep deleting stale `failed_login` and `auth_result` rows.
+#[reducer]
+pub fn sweep_failed_logins(ctx: &ReducerContext, _sweep: FailedLoginSweep) -> Result<(), String> {
+    if ctx.sender() != ctx.identity() {
+        return Err("sweep_failed_logins can only be run by the scheduler".to_string());
+    }
+    let stale: Vec<String> = ctx
+        .db
+        .failed_login()
+        .iter()
+        .filter(|record| failed_login_stale(record, ctx.timestamp))
+        .map(|record| record.username)
+        .collect();
+    for username in &stale {
+        ctx.db.failed_login().username().delete(username);
+    }
+    if !stale.is_empty() {
+        log::info!("Swept {} stale failed login records", stale.len());
+    }
+
+    let stale_results: Vec<Identity> = ctx
+        .db
+        .auth_result()
+        .iter()
+        .filter(|result| auth_result_stale(result, ctx.timestamp))
+        .map(|result| result.identity)
+        .collect();
+    for identity in &stale_results {
+        ctx.db.auth_result().identity().delete(identity);
+    }
+    Ok(())
+}
+
+/// Check if the account is locked due to too many failed attempts.
+/// Also resets the counter if the attempt window has expired.
+fn check_brute_force_lockout(ctx: &ReducerContext, username: &String) -> Result<(), String> {
+    let record = ctx.db.failed_login().username().find(username);
+    if check_lockout(record.as_ref(), ctx.timestamp)? {
+        // Window expired, reset the record
+        ctx.db.failed_login().username().delete(username);
+    }
+    Ok(())
+}
+
+// --- Reducers ---
+
+/// Authenticate a user. Creates a new account if the username doesn't exist,
+/// or logs in if it does. Either way, links this connection's identity to the
+/// user and registers the device.
+///
+/// The first user created becomes admin and does not need an invite code.
+/// All subsequent registrations require a valid, unused invite code.
+#[reducer]
+pub fn authenticate(
+    ctx: &ReducerContext,
+    username: String,
+    password: String,
+    encrypted_private_key: Vec<u8>,
+    public_key: Vec<u8>,
+    device_id: String,
+    device_name: String,
+    invite_code: String,
+) -> Result<(), String> {
+    if username.is_empty() {
+        return Err("Username cannot be empty".to_string());
+    }
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err("Authentication failed".to_string());
+    }
+    validate_device_id(&device_id)?;
+    validate_device_name(&device_name)?;
+
+    // Check if username already exists
+    let user = ctx.db.user().username().find(&username);
+
+    let user_id = if let Some(existing_user) = user {
+        // Login: verify the password with Argon2id, unless locked out
+        if check_password(ctx, &existing_user, &password)? == PasswordCheck::Rejected {
+            return reject_attempt(ctx);
+        }
+
+        existing_user.id
+    } else {
+        // Signup: check brute force lockout (prevents invite code guessing)
+        check_brute_force_lockout(ctx, &username)?;
+
+        validate_public_key(&public_key)?;
+
+        let is_first_user = claim_bootstrap(ctx);
 
         if !is_first_user {
             // Require and validate invite code
-            if invite_code.is_empty() {
-                return Err(record_failed_login(ctx, &username));
-            }
-            let invite = match ctx.db.invite_code().code().find(&invite_code) {
-                Some(inv) => inv,
-                None => return Err(record_failed_login(ctx, &username)),
+            let invite = if invite_code.is_empty() {
+                None
+            } else {
+                ctx.db.invite_code().code().find(&invite_code)
             };
-
-            // Check expiration
-            if invite.expires_at < ctx.timestamp {
-                ctx.db.invite_code().code().delete(&invite.code);
-                return Err(record_failed_login(ctx, &username));
+            match check_invite(invite.as_ref(), ctx.timestamp) {
+                Ok(()) => {}
+                Err(rejection) => {
+                    if rejection == InviteRejection::Expired {
+                        ctx.db.invite_code().code().delete(&invite_code);
+                    }
+                    record_failed_login(ctx, &username);
+                    return reject_attempt(ctx);
+                }
             }
 
             // Consume the invite code
-            ctx.db.invite_code().code().delete(&invite.code);
+            ctx.db.invite_code().code().delete(&invite_code);
         }
 
         // Hash the password with Argon2id
@@ -365,12 +1032,104 @@ pub fn authenticate(
     }
 
     // Register or update device
-    upsert_device(ctx, user_id, &device_id, &device_name);
+    upsert_device(ctx, user_id, &device_id, &device_name)?;
 
+    report_auth_result(ctx, None);
     log::info!("User '{}' authenticated, device '{}'", username, device_id);
     Ok(())
 }
 
+/// Rename the current user. The password is re-checked (with the same lockout
+/// as login) but needs no re-hashing: the Argon2 hash is salted randomly and
+/// doesn't depend on the username. Keys, devices and team memberships are
+/// keyed by user id and stay as they are.
+#[reducer]
+pub fn rename_user(
+    ctx: &ReducerContext,
+    new_username: String,
+    password: String,
+) -> Result<(), String> {
+    if new_username.is_empty() {
+        return Err("Username cannot be empty".to_string());
+    }
+
+    let user_id = get_user_id(ctx)?;
+    let user = ctx
+        .db
+        .user()
+        .id()
+        .find(&user_id)
+        .ok_or_else(|| "User not found".to_string())?;
+    if user.username == new_username {
+        return Err(format!("Already named '{}'", new_username));
+    }
+
+    if check_password(ctx, &user, &password)? == PasswordCheck::Rejected {
+        return reject_attempt(ctx);
+    }
+
+    if ctx.db.user().username().find(&new_username).is_some() {
+        return Err(format!("Username '{}' is already taken", new_username));
+    }
+
+    let old_username = user.username.clone();
+    ctx.db.user().id().update(User {
+        username: new_username.clone(),
+        ..user
+    });
+    // Lockout is keyed by username: drop the old name's record, and any
+    // attempts made against the new name before this account took it
+    clear_failed_logins(ctx, &old_username);
+    clear_failed_logins(ctx, &new_username);
+    report_auth_result(ctx, None);
+
+    log::info!(
+        "User {} renamed '{}' -> '{}'",
+        user_id,
+        old_username,
+        new_username
+    );
+    Ok(())
+}
+
+/// Unlink every identity (i.e. every device's token) from the current user,
+/// including the caller's own. Each device's next reducer call fails with
+/// "Not logged in" until it re-runs `clipsync setup`. The password is
+/// re-checked so a leaked token alone can't lock the owner out.
+#[reducer]
+pub fn revoke_all_identities(ctx: &ReducerContext, password: String) -> Result<(), String> {
+    let user_id = get_user_id(ctx)?;
+    let user = ctx
+        .db
+        .user()
+        .id()
+        .find(&user_id)
+        .ok_or_else(|| "User not found".to_string())?;
+
+    if check_password(ctx, &user, &password)? == PasswordCheck::Rejected {
+        return reject_attempt(ctx);
+    }
+
+    let identities: Vec<Identity> = ctx
+        .db
+        .user_identity()
+        .iter()
+        .filter(|ui| ui.user_id == user_id)
+        .map(|ui| ui.identity)
+        .collect();
+    for identity in &identities {
+        ctx.db.user_identity().identity().delete(identity);
+    }
+    report_auth_result(ctx, None);
+
+    log::info!(
+        "Revoked {} identities for user {}",
+        identities.len(),
+        user_id
+    );
+    Ok(())
+}
+
 /// Create a single-use invite code. Only admins can create invite codes.
 #[reducer]
 pub fn create_invite_code(ctx: &ReducerContext, code: String) -> Result<(), String> {
@@ -407,9 +1166,7 @@ pub fn create_invite_code(ctx: &ReducerContext, code: String) -> Result<(), Stri
         code,
         created_by: user_id,
         created_at: ctx.timestamp,
-        expires_at: Timestamp::from_micros_since_unix_epoch(
-            ctx.timestamp.to_micros_since_unix_epoch() + INVITE_CODE_TTL_MICROS,
-        ),
+        expires_at: invite_expires_at(ctx.timestamp),
     });
 
     log::info!("Invite code created by admin user_id={}", user_id);
@@ -422,13 +1179,11 @@ pub fn register_device(
     device_id: String,
     device_name: String,
 ) -> Result<(), String> {
-    if device_id.is_empty() {
-        return Err("device_id cannot be empty".to_string());
-    }
+    validate_device_id(&device_id)?;
+    validate_device_name(&device_name)?;
 
     let user_id = get_user_id(ctx)?;
-    upsert_device(ctx, user_id, &device_id, &device_name);
-    Ok(())
+    upsert_device(ctx, user_id, &device_id, &device_name)
 }
 
 #[reducer]
@@ -438,6 +1193,7 @@ pub fn unregister_device(ctx: &ReducerContext, device_id: String) -> Result<(),
     for existing in ctx.db.device().user_id().filter(&user_id) {
         if existing.device_id == device_id {
             ctx.db.device().id().delete(&existing.id);
+            delete_device_clips(ctx, user_id, &device_id);
             log::info!("Device unregistered: {} for user {}", device_id, user_id);
             return Ok(());
         }
@@ -445,6 +1201,141 @@ pub fn unregister_device(ctx: &ReducerContext, device_id: String) -> Result<(),
     Err(format!("Device not found: {}", device_id))
 }
 
+/// Fold a duplicate device (e.g. left behind by a reinstall that lost the
+/// `device_id` file) into the one that replaced it. Clips it sent are
+/// re-attributed to `new_device_id`, then its row is deleted. Both devices
+/// must belong to the caller.
+#[reducer]
+pub fn merge_device(
+    ctx: &ReducerContext,
+    old_device_id: String,
+    new_device_id: String,
+) -> Result<(), String> {
+    let user_id = get_user_id(ctx)?;
+    if old_device_id == new_device_id {
+        return Err("Can't merge a device into itself".to_string());
+    }
+
+    let mut old = None;
+    let mut new = None;
+    for device in ctx.db.device().user_id().filter(&user_id) {
+        if device.device_id == old_device_id {
+            old = Some(device);
+        } else if device.device_id == new_device_id {
+            new = Some(device);
+        }
+    }
+    let old = old.ok_or_else(|| format!("Device not found: {}", old_device_id))?;
+    let new = new.ok_or_else(|| format!("Device not found: {}", new_device_id))?;
+
+    let sent_by_old = |sender_device_id: &str| sender_device_id == old_device_id;
+    if let Some(clip) = ctx
+        .db
+        .current_clip()
+        .user_id()
+        .find(&user_id)
+        .filter(|clip| sent_by_old(&clip.sender_device_id))
+    {
+        ctx.db.current_clip().user_id().update(CurrentClip {
+            sender_device_id: new_device_id.clone(),
+            ..clip
+        });
+    }
+    if let Some(clip) = ctx
+        .db
+        .consumed_clip()
+        .user_id()
+        .find(&user_id)
+        .filter(|clip| sent_by_old(&clip.sender_device_id))
+    {
+        ctx.db.consumed_clip().user_id().update(ConsumedClip {
+            sender_device_id: new_device_id.clone(),
+            ..clip
+        });
+    }
+    for membership in ctx.db.team_member().user_id().filter(&user_id) {
+        if let Some(clip) = ctx
+            .db
+            .team_clip()
+            .team_id()
+            .find(&membership.team_id)
+            .filter(|clip| clip.sender_user_id == user_id && sent_by_old(&clip.sender_device_id))
+        {
+            ctx.db.team_clip().team_id().update(TeamClip {
+                sender_device_id: new_device_id.clone(),
+                ..clip
+            });
+        }
+    }
+
+    // The new device acks the clip itself if it hasn't already
+    let old_acks: Vec<u64> = ctx
+        .db
+        .clip_ack()
+        .user_id()
+        .filter(&user_id)
+        .filter(|ack| sent_by_old(&ack.device_id))
+        .map(|ack| ack.id)
+        .collect();
+    for id in old_acks {
+        ctx.db.clip_ack().id().delete(&id);
+    }
+
+    delete_device_clips(ctx, user_id, &old_device_id);
+    ctx.db.device().id().delete(&old.id);
+    log::info!(
+        "Device {} merged into {} ('{}') for user {}",
+        old_device_id,
+        new_device_id,
+        new.device_name,
+        user_id
+    );
+    Ok(())
+}
+
+/// Store `limit` of the caller's devices, ordered by id and starting at
+/// `offset`, for `my_device_page`.
+#[reducer]
+pub fn get_devices_page(ctx: &ReducerContext, offset: u64, limit: u64) -> Result<(), String> {
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(format!(
+            "Page size must be from 1 to {} (got {})",
+            MAX_PAGE_SIZE, limit
+        ));
+    }
+    let user_id = get_user_id(ctx)?;
+
+    let mut devices: Vec<Device> = ctx.db.device().user_id().filter(&user_id).collect();
+    devices.sort_by_key(|d| d.id);
+    let page = DevicePage {
+        identity: ctx.sender(),
+        total: devices.len() as u64,
+        devices: devices
+            .iter()
+            .skip(usize::try_from(offset).unwrap_or(usize::MAX))
+            .take(limit as usize)
+            .map(device_view)
+            .collect(),
+    };
+    if ctx.db.device_page().identity().find(ctx.sender()).is_some() {
+        ctx.db.device_page().identity().update(page);
+    } else {
+        ctx.db.device_page().insert(page);
+    }
+    Ok(())
+}
+
+/// A clip as a device uploads it, before it is stored.
+struct ClipUpload {
+    device_id: String,
+    content_type: ClipContentType,
+    encrypted_data: Vec<u8>,
+    size_bytes: u64,
+}
+
+/// Replace the caller's current clip. `size_bytes` is the plaintext size as
+/// reported by the client; the stored (encrypted) size is recorded separately
+/// from `encrypted_data` itself.
 #[reducer]
 pub fn sync_clip(
     ctx: &ReducerContext,
@@ -452,16 +1343,98 @@ pub fn sync_clip(
     content_type: ClipContentType,
     encrypted_data: Vec<u8>,
     size_bytes: u64,
+    options: ClipOptions,
 ) -> Result<(), String> {
-    if encrypted_data.len() > MAX_ENCRYPTED_SIZE {
-        return Err(format!(
-            "Encrypted data too large: {} bytes (max {})",
-            encrypted_data.len(),
-            MAX_ENCRYPTED_SIZE
-        ));
+    let upload = ClipUpload {
+        device_id,
+        content_type,
+        encrypted_data,
+        size_bytes,
+    };
+    store_current_clip(ctx, upload, options)
+}
+
+fn validate_intended_device(intended_device: Option<&str>) -> Result<(), String> {
+    if let Some(name) = intended_device {
+        if name.is_empty() {
+            return Err("Target device name cannot be empty".to_string());
+        }
+        validate_device_name(name)?;
+    }
+    Ok(())
+}
+
+/// Schedule the `expire_clip` sweep for a clip stored with a TTL.
+fn schedule_clip_expiry(ctx: &ReducerContext, user_id: u64, expires_at: Timestamp) {
+    ctx.db.clip_expiry().insert(ClipExpiry {
+        scheduled_id: 0,
+        scheduled_at: ScheduleAt::Time(expires_at),
+        user_id,
+    });
+}
+
+/// Scheduled sweep for clips synced with a TTL. Deletes the user's clip only if
+/// it has expired by now; a clip synced since then (with or without a TTL)
+/// is left alone.
+#[reducer]
+pub fn expire_clip(ctx: &ReducerContext, expiry: ClipExpiry) -> Result<(), String> {
+    if ctx.sender() != ctx.identity() {
+        return Err("expire_clip can only be run by the scheduler".to_string());
+    }
+    let expired = ctx
+        .db
+        .current_clip()
+        .user_id()
+        .find(&expiry.user_id)
+        .is_some_and(|clip| clip_expired(clip.expires_at, ctx.timestamp));
+    if expired {
+        ctx.db.current_clip().user_id().delete(&expiry.user_id);
+        log::info!("Expired clip deleted for user {}", expiry.user_id);
+    }
+    Ok(())
+}
+
+fn store_current_clip(
+    ctx: &ReducerContext,
+    upload: ClipUpload,
+    options: ClipOptions,
+) -> Result<(), String> {
+    let ClipUpload {
+        device_id,
+        content_type,
+        encrypted_data,
+        size_bytes,
+    } = upload;
+    let ClipOptions {
+        intended_device,
+        ttl_secs,
+        source_app,
+    } = options;
+    validate_device_id(&device_id)?;
+    let stored_bytes = validate_clip_size(&encrypted_data, size_bytes)?;
+    validate_intended_device(intended_device.as_deref())?;
+    if let Some(source_app) = &source_app {
+        validate_source_app(source_app)?;
     }
+    let expires_at = match ttl_secs {
+        Some(ttl_secs) => Some(clip_expires_at(ctx.timestamp, ttl_secs)?),
+        None => None,
+    };
 
     let user_id = get_user_id(ctx)?;
+    ensure_device_registered(ctx, user_id, &device_id)?;
+    clear_clip_acks(ctx, user_id);
+    if expires_at.is_none() {
+        remember_device_clip(
+            ctx,
+            user_id,
+            &device_id,
+            content_type.clone(),
+            encrypted_data.clone(),
+            size_bytes,
+            stored_bytes,
+        );
+    }
 
     if let Some(existing) = ctx.db.current_clip().user_id().find(&user_id) {
         ctx.db.current_clip().user_id().update(CurrentClip {
@@ -470,6 +1443,10 @@ pub fn sync_clip(
             encrypted_data,
             size_bytes,
             updated_at: ctx.timestamp,
+            stored_bytes,
+            intended_device,
+            expires_at,
+            source_app,
             ..existing
         });
     } else {
@@ -480,28 +1457,431 @@ pub fn sync_clip(
             encrypted_data,
             size_bytes,
             updated_at: ctx.timestamp,
+            stored_bytes,
+            intended_device,
+            expires_at,
+            source_app,
         });
     }
 
+    if let Some(expires_at) = expires_at {
+        schedule_clip_expiry(ctx, user_id, expires_at);
+    }
+
     log::info!("Clip synced for user {}", user_id);
     Ok(())
 }
 
-// --- Views ---
-
-/// Returns the current user's profile. Clients use this to get their own
-/// user info (including encrypted_private_key) without the user table being public.
-#[view(accessor = my_profile, public)]
-fn my_profile(ctx: &ViewContext) -> Option<UserProfile> {
-    let ui = ctx.db.user_identity().identity().find(ctx.sender())?;
-    let user = ctx.db.user().id().find(&ui.user_id)?;
-    Some(UserProfile {
-        user_id: user.id,
-        username: user.username.clone(),
-        public_key: user.public_key.clone(),
-        encrypted_private_key: user.encrypted_private_key.clone(),
-        is_admin: user.is_admin,
-    })
+/// Register a device that syncs without a `Device` row (unregistered, or the
+/// database was reset), so the device list matches what is actually syncing.
+/// It is named after its `device_id` until the client next registers itself
+/// on connect. Fails like `register_device` if the user is at the device cap.
+fn ensure_device_registered(
+    ctx: &ReducerContext,
+    user_id: u64,
+    device_id: &str,
+) -> Result<(), String> {
+    let registered = ctx
+        .db
+        .device()
+        .user_id()
+        .filter(&user_id)
+        .any(|device| device.device_id == device_id);
+    if registered {
+        return Ok(());
+    }
+    upsert_device(ctx, user_id, device_id, device_id)?;
+    log::info!(
+        "Device {} synced without being registered, registered it for user {}",
+        device_id,
+        user_id
+    );
+    Ok(())
+}
+
+/// Record the clip as `device_id`'s latest, replacing its previous one.
+fn remember_device_clip(
+    ctx: &ReducerContext,
+    user_id: u64,
+    device_id: &str,
+    content_type: ClipContentType,
+    encrypted_data: Vec<u8>,
+    size_bytes: u64,
+    stored_bytes: u64,
+) {
+    let existing = ctx
+        .db
+        .device_clip()
+        .user_id()
+        .filter(&user_id)
+        .find(|clip| clip.sender_device_id == device_id);
+    let clip = DeviceClip {
+        id: existing.as_ref().map_or(0, |clip| clip.id),
+        user_id,
+        sender_device_id: device_id.to_string(),
+        content_type,
+        encrypted_data,
+        size_bytes,
+        stored_bytes,
+        updated_at: ctx.timestamp,
+    };
+    if existing.is_some() {
+        ctx.db.device_clip().id().update(clip);
+    } else {
+        ctx.db.device_clip().insert(clip);
+    }
+}
+
+/// Drop the kept copy of `clip` (if it is still its sender's latest), so a
+/// cleared or consumed clip can't be restored.
+fn forget_device_clip(ctx: &ReducerContext, clip: &CurrentClip) {
+    let kept: Vec<u64> = ctx
+        .db
+        .device_clip()
+        .user_id()
+        .filter(&clip.user_id)
+        .filter(|kept| {
+            kept.sender_device_id == clip.sender_device_id && kept.updated_at == clip.updated_at
+        })
+        .map(|kept| kept.id)
+        .collect();
+    for id in kept {
+        ctx.db.device_clip().id().delete(&id);
+    }
+}
+
+/// Drop every clip kept for `device_id`, when the device goes away.
+fn delete_device_clips(ctx: &ReducerContext, user_id: u64, device_id: &str) {
+    let kept: Vec<u64> = ctx
+        .db
+        .device_clip()
+        .user_id()
+        .filter(&user_id)
+        .filter(|kept| kept.sender_device_id == device_id)
+        .map(|kept| kept.id)
+        .collect();
+    for id in kept {
+        ctx.db.device_clip().id().delete(&id);
+    }
+}
+
+/// Make `sender_device_id`'s last kept clip the current clip again, e.g. after
+/// another device's clip overwrote it. It keeps its original sender, so every
+/// other device applies it like a new clip.
+#[reducer]
+pub fn restore_device_clip(ctx: &ReducerContext, sender_device_id: String) -> Result<(), String> {
+    let user_id = get_user_id(ctx)?;
+    let clip = ctx
+        .db
+        .device_clip()
+        .user_id()
+        .filter(&user_id)
+        .find(|clip| clip.sender_device_id == sender_device_id)
+        .ok_or_else(|| format!("No recent clip from device {}", sender_device_id))?;
+    let upload = ClipUpload {
+        device_id: clip.sender_device_id,
+        content_type: clip.content_type,
+        encrypted_data: clip.encrypted_data,
+        size_bytes: clip.size_bytes,
+    };
+    store_current_clip(ctx, upload, ClipOptions::default())
+}
+
+/// Drop the user's acks, which all refer to the clip being replaced.
+fn clear_clip_acks(ctx: &ReducerContext, user_id: u64) {
+    let acks: Vec<u64> = ctx
+        .db
+        .clip_ack()
+        .user_id()
+        .filter(&user_id)
+        .map(|ack| ack.id)
+        .collect();
+    for id in acks {
+        ctx.db.clip_ack().id().delete(&id);
+    }
+}
+
+/// Record that `device_id` applied the current clip, whose `updated_at` the
+/// device passes as `clip_updated_at`. Fails if the clip has changed since,
+/// and is a no-op if the device already acked it. The sender can't ack its
+/// own clip.
+#[reducer]
+pub fn ack_clip(
+    ctx: &ReducerContext,
+    device_id: String,
+    clip_updated_at: Timestamp,
+) -> Result<(), String> {
+    validate_device_id(&device_id)?;
+    let user_id = get_user_id(ctx)?;
+    if !ctx
+        .db
+        .device()
+        .user_id()
+        .filter(&user_id)
+        .any(|device| device.device_id == device_id)
+    {
+        return Err(format!("Device not found: {}", device_id));
+    }
+
+    let clip = ctx
+        .db
+        .current_clip()
+        .user_id()
+        .find(&user_id)
+        .filter(|clip| clip.updated_at == clip_updated_at)
+        .ok_or_else(|| "The clip has changed since it was received".to_string())?;
+    if clip.sender_device_id == device_id {
+        return Err("A device can't acknowledge its own clip".to_string());
+    }
+    if ctx
+        .db
+        .clip_ack()
+        .user_id()
+        .filter(&user_id)
+        .any(|ack| ack.device_id == device_id)
+    {
+        return Ok(());
+    }
+
+    ctx.db.clip_ack().insert(ClipAck {
+        id: 0,
+        user_id,
+        device_id,
+        clip_updated_at,
+        acked_at: ctx.timestamp,
+    });
+    Ok(())
+}
+
+/// Create a team clipboard. Only admins can create teams; the creator becomes
+/// the owner and first member.
+#[reducer]
+pub fn create_team(ctx: &ReducerContext, name: String) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_TEAM_NAME_LENGTH {
+        return Err(format!(
+            "Team name must be between 1 and {} characters",
+            MAX_TEAM_NAME_LENGTH
+        ));
+    }
+
+    let user_id = get_user_id(ctx)?;
+    let user = ctx
+        .db
+        .user()
+        .id()
+        .find(&user_id)
+        .ok_or_else(|| "User not found".to_string())?;
+    if !user.is_admin {
+        return Err("Only admins can create teams".to_string());
+    }
+
+    if ctx.db.team().name().find(&name).is_some() {
+        return Err(format!("Team already exists: {}", name));
+    }
+
+    let team = ctx.db.team().insert(Team {
+        id: 0,
+        name: name.clone(),
+        owner_id: user_id,
+        members_version: 0,
+        created_at: ctx.timestamp,
+    });
+    ctx.db.team_member().insert(TeamMember {
+        id: 0,
+        team_id: team.id,
+        user_id,
+        added_at: ctx.timestamp,
+    });
+
+    log::info!("Team '{}' created by user_id={}", name, user_id);
+    Ok(())
+}
+
+/// Add a user to a team. Only the team owner or an admin can add members.
+#[reducer]
+pub fn add_team_member(
+    ctx: &ReducerContext,
+    team_name: String,
+    username: String,
+) -> Result<(), String> {
+    let (team, _) = get_managed_team(ctx, &team_name)?;
+    let member = ctx
+        .db
+        .user()
+        .username()
+        .find(&username)
+        .ok_or_else(|| format!("User not found: {}", username))?;
+
+    if is_team_member(ctx, team.id, member.id) {
+        return Err(format!("{} is already a member of {}", username, team_name));
+    }
+
+    ctx.db.team_member().insert(TeamMember {
+        id: 0,
+        team_id: team.id,
+        user_id: member.id,
+        added_at: ctx.timestamp,
+    });
+    bump_members_version(ctx, team);
+
+    log::info!("User {} added to team '{}'", member.id, team_name);
+    Ok(())
+}
+
+/// Remove a user from a team. The removed member loses access to the team
+/// clip immediately, and future clips are no longer encrypted to their key.
+#[reducer]
+pub fn remove_team_member(
+    ctx: &ReducerContext,
+    team_name: String,
+    username: String,
+) -> Result<(), String> {
+    let (team, _) = get_managed_team(ctx, &team_name)?;
+    let member = ctx
+        .db
+        .user()
+        .username()
+        .find(&username)
+        .ok_or_else(|| format!("User not found: {}", username))?;
+
+    if member.id == team.owner_id {
+        return Err("The team owner cannot be removed".to_string());
+    }
+
+    let membership = ctx
+        .db
+        .team_member()
+        .team_id()
+        .filter(&team.id)
+        .find(|m| m.user_id == member.id)
+        .ok_or_else(|| format!("{} is not a member of {}", username, team_name))?;
+    ctx.db.team_member().id().delete(&membership.id);
+    bump_members_version(ctx, team);
+
+    log::info!("User {} removed from team '{}'", member.id, team_name);
+    Ok(())
+}
+
+/// Replace a team's clip. `members_version` must match the team's current
+/// value, proving the client encrypted to the current member list.
+#[reducer]
+pub fn sync_team_clip(
+    ctx: &ReducerContext,
+    team_id: u64,
+    members_version: u64,
+    device_id: String,
+    content_type: ClipContentType,
+    encrypted_data: Vec<u8>,
+    size_bytes: u64,
+) -> Result<(), String> {
+    validate_device_id(&device_id)?;
+    let stored_bytes = validate_clip_size(&encrypted_data, size_bytes)?;
+
+    let user_id = get_user_id(ctx)?;
+    let team = ctx
+        .db
+        .team()
+        .id()
+        .find(&team_id)
+        .ok_or_else(|| "Team not found".to_string())?;
+    if !is_team_member(ctx, team_id, user_id) {
+        return Err("Not a member of this team".to_string());
+    }
+    if team.members_version != members_version {
+        return Err("Team membership changed; re-encrypt to the current members".to_string());
+    }
+
+    let clip = TeamClip {
+        team_id,
+        sender_user_id: user_id,
+        sender_device_id: device_id,
+        content_type,
+        encrypted_data,
+        size_bytes,
+        updated_at: ctx.timestamp,
+        stored_bytes,
+    };
+    if ctx.db.team_clip().team_id().find(&team_id).is_some() {
+        ctx.db.team_clip().team_id().update(clip);
+    } else {
+        ctx.db.team_clip().insert(clip);
+    }
+
+    log::info!("Team clip synced for team {} by user {}", team_id, user_id);
+    Ok(())
+}
+
+/// Remove the current user's clip, e.g. when a copy finds the clipboard empty
+/// and the client asked to mirror that. A no-op if there is no clip.
+#[reducer]
+pub fn clear_current_clip(ctx: &ReducerContext) -> Result<(), String> {
+    let user_id = get_user_id(ctx)?;
+    if let Some(clip) = ctx.db.current_clip().user_id().find(&user_id) {
+        forget_device_clip(ctx, &clip);
+        ctx.db.current_clip().user_id().delete(&user_id);
+        log::info!("Clip cleared for user {}", user_id);
+    }
+    Ok(())
+}
+
+/// Take the current user's clip for a one-time paste. The clip is removed
+/// from `current_clip` and stored in `consumed_clip` under `request_id` in the
+/// same transaction, so only one caller can ever consume a given clip.
+#[reducer]
+pub fn consume_current_clip(ctx: &ReducerContext, request_id: String) -> Result<(), String> {
+    if request_id.is_empty() || request_id.len() > MAX_REQUEST_ID_LENGTH {
+        return Err("Invalid request id".to_string());
+    }
+
+    let user_id = get_user_id(ctx)?;
+    let clip = ctx
+        .db
+        .current_clip()
+        .user_id()
+        .find(&user_id)
+        .ok_or_else(|| "No clip to consume".to_string())?;
+    // The sweep may not have run yet; an expired clip is as good as gone
+    if clip_expired(clip.expires_at, ctx.timestamp) {
+        return Err("No clip to consume".to_string());
+    }
+    forget_device_clip(ctx, &clip);
+    ctx.db.current_clip().user_id().delete(&user_id);
+
+    let consumed = ConsumedClip {
+        user_id,
+        request_id,
+        sender_device_id: clip.sender_device_id,
+        content_type: clip.content_type,
+        encrypted_data: clip.encrypted_data,
+        size_bytes: clip.size_bytes,
+        consumed_at: ctx.timestamp,
+        stored_bytes: clip.stored_bytes,
+    };
+    if ctx.db.consumed_clip().user_id().find(&user_id).is_some() {
+        ctx.db.consumed_clip().user_id().update(consumed);
+    } else {
+        ctx.db.consumed_clip().insert(consumed);
+    }
+
+    log::info!("Clip consumed for user {}", user_id);
+    Ok(())
+}
+
+// --- Views ---
+
+/// Returns the current user's profile. Clients use this to get their own
+/// user info (including encrypted_private_key) without the user table being public.
+#[view(accessor = my_profile, public)]
+fn my_profile(ctx: &ViewContext) -> Option<UserProfile> {
+    let ui = ctx.db.user_identity().identity().find(ctx.sender())?;
+    let user = ctx.db.user().id().find(&ui.user_id)?;
+    Some(UserProfile {
+        user_id: user.id,
+        username: user.username.clone(),
+        public_key: user.public_key.clone(),
+        encrypted_private_key: user.encrypted_private_key.clone(),
+        is_admin: user.is_admin,
+    })
 }
 
 /// Returns the current user's devices.
@@ -514,18 +1894,405 @@ fn my_devices(ctx: &ViewContext) -> Vec<DeviceView> {
         .device()
         .user_id()
         .filter(&ui.user_id)
-        .map(|d| DeviceView {
-            id: d.id,
-            device_id: d.device_id.clone(),
-            device_name: d.device_name.clone(),
-            registered_at: d.registered_at,
-        })
+        .map(|d| device_view(&d))
         .collect()
 }
 
-/// Returns the current user's current clipboard content.
+/// Returns the page of devices this identity last asked for.
+#[view(accessor = my_device_page, public)]
+fn my_device_page(ctx: &ViewContext) -> Option<DevicePage> {
+    ctx.db.device_page().identity().find(ctx.sender())
+}
+
+/// Returns the current user's current clipboard content. Expiring clips stay
+/// visible until their `clip_expiry` sweep runs, so clients also check
+/// `expires_at` themselves.
 #[view(accessor = my_current_clip, public)]
 fn my_current_clip(ctx: &ViewContext) -> Option<CurrentClip> {
     let ui = ctx.db.user_identity().identity().find(ctx.sender())?;
     ctx.db.current_clip().user_id().find(&ui.user_id)
 }
+
+/// Returns the clip most recently taken by the current user's `consume_current_clip`.
+#[view(accessor = my_consumed_clip, public)]
+fn my_consumed_clip(ctx: &ViewContext) -> Option<ConsumedClip> {
+    let ui = ctx.db.user_identity().identity().find(ctx.sender())?;
+    ctx.db.consumed_clip().user_id().find(&ui.user_id)
+}
+
+/// Returns the devices that acknowledged the current user's current clip.
+#[view(accessor = my_clip_acks, public)]
+fn my_clip_acks(ctx: &ViewContext) -> Vec<ClipAck> {
+    let Some(ui) = ctx.db.user_identity().identity().find(ctx.sender()) else {
+        return vec![];
+    };
+    ctx.db.clip_ack().user_id().filter(&ui.user_id).collect()
+}
+
+/// Returns the last clip each of the current user's devices synced, without
+/// the data, so an overwritten clip can be found and restored.
+#[view(accessor = my_recent_by_device, public)]
+fn my_recent_by_device(ctx: &ViewContext) -> Vec<DeviceClipView> {
+    let Some(ui) = ctx.db.user_identity().identity().find(ctx.sender()) else {
+        return vec![];
+    };
+    ctx.db
+        .device_clip()
+        .user_id()
+        .filter(&ui.user_id)
+        .map(|clip| DeviceClipView {
+            sender_device_id: clip.sender_device_id.clone(),
+            content_type: clip.content_type.clone(),
+            size_bytes: clip.size_bytes,
+            updated_at: clip.updated_at,
+        })
+        .collect()
+}
+
+/// Returns the failed login record for the current user's username, so owners
+/// can see attempts against their own account. Cleared on a successful login.
+#[view(accessor = my_failed_login, public)]
+fn my_failed_login(ctx: &ViewContext) -> Option<FailedLogin> {
+    let ui = ctx.db.user_identity().identity().find(ctx.sender())?;
+    let user = ctx.db.user().id().find(&ui.user_id)?;
+    ctx.db.failed_login().username().find(&user.username)
+}
+
+/// Returns the outcome of this identity's last password-checked reducer.
+#[view(accessor = my_auth_result, public)]
+fn my_auth_result(ctx: &ViewContext) -> Option<AuthResult> {
+    ctx.db.auth_result().identity().find(ctx.sender())
+}
+
+/// Returns the module's identity, for clients that pin `server_fingerprint`.
+#[view(accessor = server_identity, public)]
+fn server_identity(ctx: &ViewContext) -> Option<ServerInfo> {
+    ctx.db.server_info().id().find(&0)
+}
+
+/// Returns the teams the current user belongs to.
+#[view(accessor = my_teams, public)]
+fn my_teams(ctx: &ViewContext) -> Vec<TeamView> {
+    let Some(ui) = ctx.db.user_identity().identity().find(ctx.sender()) else {
+        return vec![];
+    };
+    ctx.db
+        .team_member()
+        .user_id()
+        .filter(&ui.user_id)
+        .filter_map(|m| ctx.db.team().id().find(&m.team_id))
+        .map(|t| TeamView {
+            team_id: t.id,
+            name: t.name.clone(),
+            owner_id: t.owner_id,
+            members_version: t.members_version,
+        })
+        .collect()
+}
+
+/// Returns the members (with public keys) of every team the current user belongs to.
+#[view(accessor = my_team_members, public)]
+fn my_team_members(ctx: &ViewContext) -> Vec<TeamMemberView> {
+    let Some(ui) = ctx.db.user_identity().identity().find(ctx.sender()) else {
+        return vec![];
+    };
+    ctx.db
+        .team_member()
+        .user_id()
+        .filter(&ui.user_id)
+        .flat_map(|mine| ctx.db.team_member().team_id().filter(&mine.team_id))
+        .filter_map(|m| {
+            let user = ctx.db.user().id().find(&m.user_id)?;
+            Some(TeamMemberView {
+                team_id: m.team_id,
+                user_id: user.id,
+                username: user.username.clone(),
+                public_key: user.public_key.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the current clip of every team the current user belongs to.
+#[view(accessor = my_team_clips, public)]
+fn my_team_clips(ctx: &ViewContext) -> Vec<TeamClip> {
+    let Some(ui) = ctx.db.user_identity().identity().find(ctx.sender()) else {
+        return vec![];
+    };
+    ctx.db
+        .team_member()
+        .user_id()
+        .filter(&ui.user_id)
+        .filter_map(|m| ctx.db.team_clip().team_id().find(&m.team_id))
+        .collect()
+}
+
+/// Returns instance-wide usage stats. Only populated for admins; everyone else
+/// gets nothing. Scans whole tables, so it is meant for occasional use.
+#[view(accessor = server_stats, public)]
+fn server_stats(ctx: &ViewContext) -> Option<ServerStats> {
+    let ui = ctx.db.user_identity().identity().find(ctx.sender())?;
+    let user = ctx.db.user().id().find(&ui.user_id)?;
+    if !user.is_admin {
+        return None;
+    }
+
+    let personal_bytes: u64 = ctx.db.current_clip().iter().map(|c| c.size_bytes).sum();
+    let consumed_bytes: u64 = ctx.db.consumed_clip().iter().map(|c| c.size_bytes).sum();
+    let team_bytes: u64 = ctx.db.team_clip().iter().map(|c| c.size_bytes).sum();
+    let stored_bytes: u64 = ctx
+        .db
+        .current_clip()
+        .iter()
+        .map(|c| c.stored_bytes)
+        .sum::<u64>()
+        + ctx
+            .db
+            .consumed_clip()
+            .iter()
+            .map(|c| c.stored_bytes)
+            .sum::<u64>()
+        + ctx
+            .db
+            .team_clip()
+            .iter()
+            .map(|c| c.stored_bytes)
+            .sum::<u64>()
+        + ctx
+            .db
+            .device_clip()
+            .iter()
+            .map(|c| c.stored_bytes)
+            .sum::<u64>();
+    Some(ServerStats {
+        total_users: ctx.db.user().count(),
+        total_devices: ctx.db.device().count(),
+        outstanding_invite_codes: ctx.db.invite_code().count(),
+        total_teams: ctx.db.team().count(),
+        total_clip_bytes: personal_bytes + consumed_bytes + team_bytes,
+        total_stored_bytes: stored_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60 * 1_000_000;
+
+    fn at(micros: i64) -> Timestamp {
+        Timestamp::from_micros_since_unix_epoch(micros)
+    }
+
+    fn failed_login(first_attempt_at: i64, locked_until: i64) -> FailedLogin {
+        FailedLogin {
+            username: "alice".to_string(),
+            attempt_count: 1,
+            first_attempt_at: at(first_attempt_at),
+            locked_until: at(locked_until),
+        }
+    }
+
+    #[test]
+    fn lockout_starts_at_max_attempts() {
+        let now = at(1_000 * MINUTE);
+        let previous = Timestamp::UNIX_EPOCH;
+        assert_eq!(
+            locked_until_after_failure(MAX_FAILED_ATTEMPTS - 1, previous, now),
+            previous
+        );
+        assert_eq!(
+            locked_until_after_failure(MAX_FAILED_ATTEMPTS, previous, now),
+            add_micros(now, LOCKOUT_DURATION_MICROS)
+        );
+    }
+
+    #[test]
+    fn lockout_ends_after_duration() {
+        let record = failed_login(0, 15 * MINUTE);
+        assert!(is_locked_out(&record, at(14 * MINUTE)));
+        assert!(!is_locked_out(&record, at(15 * MINUTE)));
+    }
+
+    #[test]
+    fn attempt_window_resets_after_expiry() {
+        let record = failed_login(0, 0);
+        assert!(!attempt_window_expired(&record, at(ATTEMPT_WINDOW_MICROS)));
+        assert!(attempt_window_expired(
+            &record,
+            at(ATTEMPT_WINDOW_MICROS + 1)
+        ));
+    }
+
+    #[test]
+    fn failed_login_stale_once_window_and_lockout_pass() {
+        let record = failed_login(0, 0);
+        assert!(!failed_login_stale(&record, at(ATTEMPT_WINDOW_MICROS)));
+        assert!(failed_login_stale(&record, at(ATTEMPT_WINDOW_MICROS + 1)));
+
+        // A lockout outliving the window keeps the record
+        let locked = failed_login(0, ATTEMPT_WINDOW_MICROS + 10 * MINUTE);
+        assert!(!failed_login_stale(&locked, at(ATTEMPT_WINDOW_MICROS + 1)));
+        assert!(failed_login_stale(
+            &locked,
+            at(ATTEMPT_WINDOW_MICROS + 10 * MINUTE)
+        ));
+    }
+
+    #[test]
+    fn auth_result_stale_after_window() {
+        let result = AuthResult {
+            identity: Identity::ZERO,
+            error: None,
+            reported_at: at(0),
+        };
+        assert!(!auth_result_stale(&result, at(ATTEMPT_WINDOW_MICROS)));
+        assert!(auth_result_stale(&result, at(ATTEMPT_WINDOW_MICROS + 1)));
+    }
+
+    #[test]
+    fn invite_expires_after_ttl() {
+        let created_at = at(0);
+        let invite = InviteCode {
+            code: "code".to_string(),
+            created_by: 1,
+            created_at,
+            expires_at: invite_expires_at(created_at),
+        };
+        assert!(!invite_expired(&invite, at(INVITE_CODE_TTL_MICROS)));
+        assert!(invite_expired(&invite, at(INVITE_CODE_TTL_MICROS + 1)));
+    }
+
+    #[test]
+    fn clip_expires_after_ttl() {
+        let synced_at = at(10 * MINUTE);
+        let expires_at = clip_expires_at(synced_at, 60).unwrap();
+        assert_eq!(expires_at, at(11 * MINUTE));
+        // Readers before the sweep and the sweep itself agree on the boundary
+        assert!(!clip_expired(Some(expires_at), at(11 * MINUTE - 1)));
+        assert!(clip_expired(Some(expires_at), at(11 * MINUTE)));
+        assert!(!clip_expired(None, at(i64::MAX / 2)));
+    }
+
+    /// Stand-in for the `failed_login` table, calling `attempt_password` the
+    /// way `check_password` does. Like a reducer's transaction, the returned
+    /// record is only stored if the call succeeds: a locked-out attempt's
+    /// error leaves the table as it was.
+    #[derive(Default)]
+    struct LoginHarness {
+        record: Option<FailedLogin>,
+    }
+
+    impl LoginHarness {
+        fn login(&mut self, now: Timestamp, password_ok: bool) -> Result<(), String> {
+            let (check, record) =
+                attempt_password(self.record.clone(), "alice", now, || password_ok)?;
+            self.record = record;
+            match check {
+                PasswordCheck::Accepted => Ok(()),
+                PasswordCheck::Rejected => Err("Authentication failed".to_string()),
+            }
+        }
+
+        fn attempts(&self) -> u32 {
+            self.record
+                .as_ref()
+                .map_or(0, |record| record.attempt_count)
+        }
+    }
+
+    #[test]
+    fn wrong_password_is_recorded_without_a_reducer_error() {
+        // A reducer error rolls back its transaction, so the failed attempt is
+        // only stored if a wrong password comes back as Ok(Rejected)
+        let (check, record) = attempt_password(None, "alice", at(0), || false).unwrap();
+        assert_eq!(check, PasswordCheck::Rejected);
+        assert_eq!(record.map(|record| record.attempt_count), Some(1));
+
+        // While locked out the password isn't even checked
+        let locked = failed_login(0, 15 * MINUTE);
+        assert!(attempt_password(Some(locked), "alice", at(MINUTE), || unreachable!()).is_err());
+    }
+
+    #[test]
+    fn five_failures_lock_the_account() {
+        let mut harness = LoginHarness::default();
+        for i in 0..MAX_FAILED_ATTEMPTS as i64 {
+            assert!(harness.login(at(i * MINUTE), false).is_err());
+        }
+        assert_eq!(harness.attempts(), MAX_FAILED_ATTEMPTS);
+
+        // Even the right password is refused while locked, without counting
+        let sixth = at(MAX_FAILED_ATTEMPTS as i64 * MINUTE);
+        assert!(harness.login(sixth, true).is_err());
+        assert_eq!(harness.attempts(), MAX_FAILED_ATTEMPTS);
+    }
+
+    #[test]
+    fn lockout_lifts_and_counter_resets_after_window() {
+        let mut harness = LoginHarness::default();
+        for i in 0..MAX_FAILED_ATTEMPTS as i64 {
+            assert!(harness.login(at(i * MINUTE), false).is_err());
+        }
+        let last_failure = (MAX_FAILED_ATTEMPTS as i64 - 1) * MINUTE;
+        assert!(harness
+            .login(at(last_failure + LOCKOUT_DURATION_MICROS - 1), true)
+            .is_err());
+
+        // Past both the lockout and the attempt window, failures count from one
+        let later = at(last_failure + LOCKOUT_DURATION_MICROS);
+        assert!(harness.login(later, false).is_err());
+        assert_eq!(harness.attempts(), 1);
+        assert!(harness.login(later, true).is_ok());
+        assert_eq!(harness.attempts(), 0);
+    }
+
+    #[test]
+    fn failures_spread_past_window_never_lock() {
+        let mut harness = LoginHarness::default();
+        for i in 0..(2 * MAX_FAILED_ATTEMPTS as i64) {
+            assert!(harness
+                .login(at(i * (ATTEMPT_WINDOW_MICROS / 2 + 1)), false)
+                .is_err());
+            assert!(harness.attempts() <= 2);
+        }
+        let now = at(2 * MAX_FAILED_ATTEMPTS as i64 * (ATTEMPT_WINDOW_MICROS / 2 + 1));
+        assert!(harness.login(now, true).is_ok());
+    }
+
+    #[test]
+    fn invite_must_exist_and_be_unexpired() {
+        let created_at = at(0);
+        let invite = InviteCode {
+            code: "code".to_string(),
+            created_by: 1,
+            created_at,
+            expires_at: invite_expires_at(created_at),
+        };
+        assert_eq!(check_invite(None, at(0)), Err(InviteRejection::Unknown));
+        assert_eq!(
+            check_invite(Some(&invite), at(INVITE_CODE_TTL_MICROS)),
+            Ok(())
+        );
+        assert_eq!(
+            check_invite(Some(&invite), at(INVITE_CODE_TTL_MICROS + 1)),
+            Err(InviteRejection::Expired)
+        );
+    }
+
+    #[test]
+    fn clip_ttl_is_bounded() {
+        assert!(clip_expires_at(at(0), 0).is_err());
+        assert!(clip_expires_at(at(0), MAX_CLIP_TTL_SECS).is_ok());
+        assert!(clip_expires_at(at(0), MAX_CLIP_TTL_SECS + 1).is_err());
+    }
+
+    #[test]
+    fn source_app_is_bounded() {
+        assert!(validate_source_app("firefox").is_ok());
+        assert!(validate_source_app("").is_err());
+        assert!(validate_source_app(&"a".repeat(MAX_SOURCE_APP_LENGTH)).is_ok());
+        assert!(validate_source_app(&"a".repeat(MAX_SOURCE_APP_LENGTH + 1)).is_err());
+        assert!(validate_source_app("fire\nfox").is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use anyhow::{bail, Result};
+
+use crate::protocol::{Request, Response};
+
+pub async fn stats() -> Result<()> {
+    let response = super::send_request(Request::ServerStats).await?;
+
+    match response {
+        Response::ServerStats {
+            total_users,
+            total_devices,
+            outstanding_invite_codes,
+            total_teams,
+            total_clip_bytes,
+            total_stored_bytes,
+        } => {
+            println!("Users:        {}", total_users);
+            println!("Devices:      {}", total_devices);
+            println!("Invite codes: {}", outstanding_invite_codes);
+            println!("Teams:        {}", total_teams);
+            println!("Clip bytes:   {} (plaintext)", total_clip_bytes);
+            println!("Stored bytes: {} (encrypted)", total_stored_bytes);
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
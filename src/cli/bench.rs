@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+use crate::crypto;
+use crate::payload::{self, ClipboardPayload};
+
+/// Payload sizes benchmarked, in bytes of plaintext (text) or raw RGBA (image).
+const SIZES: &[usize] = &[1024, 64 * 1024, 1024 * 1024, 8 * 1024 * 1024];
+
+/// Repeat each measurement until at least this much time has passed.
+const MIN_BENCH_TIME: Duration = Duration::from_millis(200);
+
+/// Prose-like text of exactly `len` bytes: compressible, but not trivially so.
+fn synthetic_text(len: usize) -> String {
+    const WORDS: &[&str] = &[
+        "clipboard",
+        "sync",
+        "device",
+        "the",
+        "encrypted",
+        "of",
+        "paste",
+        "a",
+        "server",
+        "copy",
+        "and",
+        "key",
+        "image",
+        "to",
+        "text",
+        "daemon",
+    ];
+    let mut text = String::with_capacity(len + 16);
+    let mut state: u32 = 0x2545_f491;
+    while text.len() < len {
+        // xorshift, so runs are repeatable without pulling in an RNG
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        text.push_str(WORDS[state as usize % WORDS.len()]);
+        text.push(if state % 11 == 0 { '\n' } else { ' ' });
+    }
+    text.truncate(len);
+    text
+}
+
+/// A smooth gradient image with about `raw_len` bytes of RGBA data.
+fn synthetic_image(raw_len: usize) -> Result<ClipboardPayload> {
+    let side = ((raw_len / 4) as f64).sqrt().max(1.0) as u32;
+    let rgba: Vec<u8> = (0..side * side)
+        .flat_map(|i| {
+            let (x, y) = (i % side, i / side);
+            [(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255]
+        })
+        .collect();
+    Ok(ClipboardPayload::Image {
+        width: side,
+        height: side,
+        png_data: payload::rgba_to_png(&rgba, side, side)?,
+    })
+}
+
+/// Run `f` repeatedly for at least `MIN_BENCH_TIME` and return the mean duration.
+fn time<T>(mut f: impl FnMut() -> Result<T>) -> Result<Duration> {
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while runs == 0 || start.elapsed() < MIN_BENCH_TIME {
+        f()?;
+        runs += 1;
+    }
+    Ok(start.elapsed() / runs)
+}
+
+fn throughput_mb_s(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+fn human_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{}M", bytes / (1024 * 1024))
+    } else {
+        format!("{}K", bytes / 1024)
+    }
+}
+
+pub fn run() -> Result<()> {
+    let (identity, recipient) = crypto::generate_keypair();
+
+    println!(
+        "{:<6} {:>5} {:>10} {:>10} {:>8} {:>12} {:>12}",
+        "type", "size", "payload", "encrypted", "ratio", "encrypt MB/s", "decrypt MB/s"
+    );
+    for &size in SIZES {
+        for clip in [
+            ClipboardPayload::Text(synthetic_text(size)),
+            synthetic_image(size)?,
+        ] {
+            let data = clip.serialize()?;
+            let encrypted = crypto::encrypt(&data, std::slice::from_ref(&recipient))?;
+            let encrypt_time = time(|| crypto::encrypt(&data, std::slice::from_ref(&recipient)))?;
+            let decrypt_time = time(|| crypto::decrypt(&encrypted, &identity))?;
+
+            println!(
+                "{:<6} {:>5} {:>10} {:>10} {:>7.2}x {:>12.1} {:>12.1}",
+                clip.content_type_str(),
+                human_size(size),
+                data.len(),
+                encrypted.len(),
+                data.len() as f64 / encrypted.len() as f64,
+                throughput_mb_s(data.len(), encrypt_time),
+                throughput_mb_s(data.len(), decrypt_time),
+            );
+        }
+    }
+    println!();
+    println!("Compression is zstd (level 3) before age encryption; ratio is payload / encrypted.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_text_has_requested_length() {
+        for len in [0, 1, 1000, 4096] {
+            assert_eq!(synthetic_text(len).len(), len);
+        }
+        assert_eq!(synthetic_text(500), synthetic_text(500));
+    }
+}
@@ -0,0 +1,18 @@
+use anyhow::{bail, Result};
+
+use crate::protocol::{Request, Response};
+
+pub async fn run(quiet: bool) -> Result<()> {
+    match super::send_request(Request::ClearLocal).await? {
+        Response::Ok if quiet => {}
+        Response::Ok => eprintln!("Local clipboard cleared"),
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
@@ -1,49 +1,253 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use std::io::Read;
+use std::path::PathBuf;
 
-use crate::config::Config;
+use crate::config::{self, Config, MAX_IPC_CONNECTIONS};
+use crate::history::MAX_HISTORY_FILE_SIZE;
 
-pub fn run(key: Option<String>, value: Option<String>) -> Result<()> {
-    let mut config = Config::load().unwrap_or_default();
+const KEYS: &[&str] = &[
+    "watch_clipboard",
+    "poll_interval_ms",
+    "poll_interval_max_ms",
+    "poll_backoff_idle_cycles",
+    "clipboard_read_retries",
+    "server_url",
+    "database_name",
+    "server_fingerprint",
+    "local_history",
+    "local_history_max_entries",
+    "local_history_max_bytes",
+    "log_previews",
+    "primary_device_id",
+    "primary_hold_secs",
+    "secure_token_storage",
+    "trusted_senders",
+    "allow_empty_copy",
+    "pause_when_locked",
+    "copy_dedup_window_ms",
+    "max_ipc_connections",
+    "max_clip_bytes",
+    "min_sync_bytes",
+    "clipboard_source",
+    "password_command",
+    "share_source_app",
+    "write_status_file",
+];
+
+fn get(config: &Config, key: &str) -> Option<String> {
+    let value = match key {
+        "watch_clipboard" => config.watch_clipboard.to_string(),
+        "poll_interval_ms" => config.poll_interval_ms.to_string(),
+        "poll_interval_max_ms" => config.poll_interval_max_ms.to_string(),
+        "poll_backoff_idle_cycles" => config.poll_backoff_idle_cycles.to_string(),
+        "clipboard_read_retries" => config.clipboard_read_retries.to_string(),
+        "server_url" => config.server_url.clone(),
+        "database_name" => config.database_name.clone(),
+        "server_fingerprint" => config.server_fingerprint.clone().unwrap_or_default(),
+        "local_history" => config.local_history.to_string(),
+        "local_history_max_entries" => config.local_history_max_entries.to_string(),
+        "local_history_max_bytes" => config.local_history_max_bytes.to_string(),
+        "log_previews" => config.log_previews.to_string(),
+        "primary_device_id" => config.primary_device_id.clone().unwrap_or_default(),
+        "primary_hold_secs" => config.primary_hold_secs.to_string(),
+        "secure_token_storage" => config.secure_token_storage.to_string(),
+        "trusted_senders" => config.trusted_senders.join(","),
+        "allow_empty_copy" => config.allow_empty_copy.to_string(),
+        "pause_when_locked" => config.pause_when_locked.to_string(),
+        "copy_dedup_window_ms" => config.copy_dedup_window_ms.to_string(),
+        "max_ipc_connections" => config.max_ipc_connections.to_string(),
+        "max_clip_bytes" => config.max_clip_bytes.to_string(),
+        "min_sync_bytes" => config.min_sync_bytes.to_string(),
+        "clipboard_source" => config.clipboard_source.to_string(),
+        "password_command" => config.password_command.clone().unwrap_or_default(),
+        "share_source_app" => config.share_source_app.to_string(),
+        "write_status_file" => config.write_status_file.to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn set(config: &mut Config, key: &str, value: String) -> Result<()> {
+    match key {
+        "watch_clipboard" => {
+            config.watch_clipboard = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        "poll_interval_ms" => {
+            config.poll_interval_ms = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        "poll_interval_max_ms" => {
+            config.poll_interval_max_ms = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        "poll_backoff_idle_cycles" => {
+            config.poll_backoff_idle_cycles = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        "clipboard_read_retries" => {
+            config.clipboard_read_retries = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        "server_url" => config.server_url = value,
+        "database_name" => config.database_name = value,
+        // An empty value unpins the server
+        "server_fingerprint" => {
+            config.server_fingerprint = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        }
+        "local_history" => {
+            config.local_history = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        "local_history_max_entries" => {
+            config.local_history_max_entries = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        "local_history_max_bytes" => {
+            let max_bytes: usize = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number of bytes"))?;
+            if !(1..=MAX_HISTORY_FILE_SIZE).contains(&max_bytes) {
+                bail!(
+                    "Expected a number of bytes from 1 to {}",
+                    MAX_HISTORY_FILE_SIZE
+                );
+            }
+            config.local_history_max_bytes = max_bytes;
+        }
+        "log_previews" => {
+            config.log_previews = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        // An empty value goes back to plain last-write-wins
+        "primary_device_id" => {
+            config.primary_device_id = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        }
+        "primary_hold_secs" => {
+            config.primary_hold_secs = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        "secure_token_storage" => {
+            config.secure_token_storage = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        // Comma-separated device ids; an empty value trusts every device
+        "trusted_senders" => {
+            config.trusted_senders = value
+                .split(',')
+                .map(|d| d.trim().to_string())
+                .filter(|d| !d.is_empty())
+                .collect();
+        }
+        "allow_empty_copy" => {
+            config.allow_empty_copy = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        "pause_when_locked" => {
+            config.pause_when_locked = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        "copy_dedup_window_ms" => {
+            config.copy_dedup_window_ms = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        "max_ipc_connections" => {
+            let limit: usize = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+            if !(1..=MAX_IPC_CONNECTIONS).contains(&limit) {
+                bail!("Expected a number from 1 to {}", MAX_IPC_CONNECTIONS);
+            }
+            config.max_ipc_connections = limit;
+        }
+        "max_clip_bytes" => {
+            config.max_clip_bytes =
+                super::copy::parse_max_size(value).map_err(|e| anyhow::anyhow!("{}", e))?;
+        }
+        "min_sync_bytes" => {
+            config.min_sync_bytes = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number"))?;
+        }
+        // An empty value goes back to prompting
+        "password_command" => {
+            config.password_command = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        }
+        "clipboard_source" => {
+            config.clipboard_source = value.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+        }
+        "share_source_app" => {
+            config.share_source_app = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        "write_status_file" => {
+            config.write_status_file = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
+        }
+        _ => bail!(unknown_key(key)),
+    }
+    Ok(())
+}
+
+fn unknown_key(key: &str) -> String {
+    format!(
+        "Unknown config key: {}\nValid keys: {}",
+        key,
+        KEYS.join(", ")
+    )
+}
+
+/// Print the whole config, or one field of it, as JSON.
+fn print_json(config: &Config, key: Option<&str>) -> Result<()> {
+    let json = serde_json::to_value(config)?;
+    let value = match key {
+        None => &json,
+        Some(k) => json.get(k).ok_or_else(|| anyhow::anyhow!(unknown_key(k)))?,
+    };
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+pub fn run(key: Option<String>, value: Option<String>, json: bool) -> Result<()> {
+    let mut config = Config::load_or_default();
+
+    // clap rejects --json together with a value
+    if json {
+        return print_json(&config, key.as_deref());
+    }
 
     match (key, value) {
         // No args: show all config
         (None, None) => {
-            println!("watch_clipboard = {}", config.watch_clipboard);
-            println!("poll_interval_ms = {}", config.poll_interval_ms);
-            println!("server_url = {}", config.server_url);
-            println!("database_name = {}", config.database_name);
+            for k in KEYS {
+                println!("{} = {}", k, get(&config, k).unwrap_or_default());
+            }
         }
         // Key only: show that value
-        (Some(k), None) => match k.as_str() {
-            "watch_clipboard" => println!("{}", config.watch_clipboard),
-            "poll_interval_ms" => println!("{}", config.poll_interval_ms),
-            "server_url" => println!("{}", config.server_url),
-            "database_name" => println!("{}", config.database_name),
-            _ => bail!("Unknown config key: {}\nValid keys: watch_clipboard, poll_interval_ms, server_url, database_name", k),
+        (Some(k), None) => match get(&config, &k) {
+            Some(v) => println!("{}", v),
+            None => bail!(unknown_key(&k)),
         },
         // Key + value: set it
         (Some(k), Some(v)) => {
-            match k.as_str() {
-                "watch_clipboard" => {
-                    config.watch_clipboard = v.parse()
-                        .map_err(|_| anyhow::anyhow!("Expected true or false"))?;
-                }
-                "poll_interval_ms" => {
-                    config.poll_interval_ms = v.parse()
-                        .map_err(|_| anyhow::anyhow!("Expected a number"))?;
-                }
-                "server_url" => config.server_url = v,
-                "database_name" => config.database_name = v,
-                _ => bail!("Unknown config key: {}\nValid keys: watch_clipboard, poll_interval_ms, server_url, database_name", k),
-            }
+            set(&mut config, &k, v)?;
             config.save()?;
-            println!("Set {} = {}", k, match k.as_str() {
-                "watch_clipboard" => config.watch_clipboard.to_string(),
-                "poll_interval_ms" => config.poll_interval_ms.to_string(),
-                "server_url" => config.server_url,
-                "database_name" => config.database_name,
-                _ => unreachable!(),
-            });
+            println!("Set {} = {}", k, get(&config, &k).unwrap_or_default());
             println!("Restart the daemon for changes to take effect.");
         }
         // Value without key doesn't make sense
@@ -52,3 +256,113 @@ pub fn run(key: Option<String>, value: Option<String>) -> Result<()> {
 
     Ok(())
 }
+
+/// The config as portable TOML for `clipsync export-config`. Secrets are never
+/// in it: the token, keys, user_id and device_id live in their own files, and
+/// `password_command` is dropped since it can hold a password inline and
+/// names a store on this machine.
+fn export_toml(config: &Config) -> Result<String> {
+    let portable = Config {
+        password_command: None,
+        ..config.clone()
+    };
+    Ok(toml::to_string_pretty(&portable)?)
+}
+
+/// Parse `export-config` output into the config to save over `local`.
+/// Unknown keys are rejected rather than dropped, so a typo or a pasted
+/// secret doesn't pass silently; `local`'s `password_command` is kept.
+fn parse_import(contents: &str, local: &Config) -> Result<Config> {
+    let table: toml::Table = contents.parse().context("Not valid TOML")?;
+    for key in table.keys() {
+        if key != "rules" && !KEYS.contains(&key.as_str()) {
+            bail!(unknown_key(key));
+        }
+    }
+    let mut config: Config = toml::from_str(contents).context("Invalid config")?;
+    if !(1..=MAX_IPC_CONNECTIONS).contains(&config.max_ipc_connections) {
+        bail!(
+            "max_ipc_connections must be from 1 to {}",
+            MAX_IPC_CONNECTIONS
+        );
+    }
+    config.password_command = local.password_command.clone();
+    Ok(config)
+}
+
+/// Print this machine's settings, without secrets, for `import-config`.
+pub fn export() -> Result<()> {
+    print!("{}", export_toml(&Config::load_or_default())?);
+    Ok(())
+}
+
+/// Replace `config.toml` with settings from `export-config`, read from `file`
+/// or stdin. The old file is kept as `config.toml.bak`.
+pub fn import(file: Option<PathBuf>) -> Result<()> {
+    let contents = match file.filter(|path| path.as_os_str() != "-") {
+        Some(path) => std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        None => {
+            let mut contents = String::new();
+            std::io::stdin()
+                .read_to_string(&mut contents)
+                .context("Failed to read the config from stdin")?;
+            contents
+        }
+    };
+    let config = parse_import(&contents, &Config::load_or_default())?;
+
+    let path = config::config_dir()?.join("config.toml");
+    if path.exists() {
+        let backup = path.with_extension("toml.bak");
+        std::fs::copy(&path, &backup)
+            .with_context(|| format!("Failed to back up the config to {}", backup.display()))?;
+        println!("Backed up the old config to {}", backup.display());
+    }
+    config.save()?;
+    println!("Imported config to {}", path.display());
+    println!("Restart the daemon for changes to take effect.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_round_trips_without_password_command() {
+        let config = Config {
+            poll_interval_ms: 1000,
+            password_command: Some("pass show clipsync".to_string()),
+            ..Config::default()
+        };
+        let exported = export_toml(&config).unwrap();
+        assert!(!exported.contains("pass show"));
+
+        let imported = parse_import(&exported, &Config::default()).unwrap();
+        assert_eq!(imported.poll_interval_ms, 1000);
+        assert_eq!(imported.password_command, None);
+    }
+
+    #[test]
+    fn import_keeps_local_password_command() {
+        let local = Config {
+            password_command: Some("pass show clipsync".to_string()),
+            ..Config::default()
+        };
+        let imported = parse_import("watch_clipboard = false", &local).unwrap();
+        assert!(!imported.watch_clipboard);
+        assert_eq!(
+            imported.password_command.as_deref(),
+            Some("pass show clipsync")
+        );
+    }
+
+    #[test]
+    fn import_rejects_unknown_keys_and_bad_values() {
+        assert!(parse_import("token = \"abc\"", &Config::default()).is_err());
+        assert!(parse_import("poll_interval_ms = \"fast\"", &Config::default()).is_err());
+        assert!(parse_import("max_ipc_connections = 0", &Config::default()).is_err());
+        assert!(parse_import("not toml", &Config::default()).is_err());
+    }
+}
@@ -1,32 +1,212 @@
 use anyhow::{bail, Result};
 use std::io::{IsTerminal, Read};
+use std::time::Duration;
 
-use crate::protocol::{Request, Response};
-
-pub async fn run() -> Result<()> {
-    let data = if std::io::stdin().is_terminal() {
-        // Not piped, tell daemon to read system clipboard
-        None
-    } else {
-        // Piped, read from stdin
-        let mut buf = Vec::new();
-        std::io::stdin().read_to_end(&mut buf)?;
-        Some(buf)
+use crate::config::Config;
+use crate::payload::MAX_SYNC_SIZE;
+use crate::protocol::{check_frame_size, Request, Response, WaitConfirm};
+
+/// Read piped stdin, or return None when stdin is a terminal so the daemon
+/// reads the system clipboard instead.
+pub(super) fn read_stdin() -> Result<Option<Vec<u8>>> {
+    if std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let mut buf = Vec::new();
+    std::io::stdin().read_to_end(&mut buf)?;
+    Ok(Some(buf))
+}
+
+/// Parse `--dedup-window` seconds, e.g. `2` or `0.5`. Zero disables it.
+pub fn parse_dedup_window(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number of seconds '{}'", s))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("Invalid number of seconds '{}'", s))
+}
+
+/// Parse a clip size limit in bytes, with an optional K or M suffix
+/// (e.g. `800K`, `20MB`). Values over the server's limit are rejected.
+pub fn parse_max_size(s: &str) -> Result<usize, String> {
+    let trimmed = s.trim().to_ascii_uppercase();
+    let digits = trimmed.strip_suffix('B').unwrap_or(&trimmed);
+    let (digits, unit) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1024),
+        Some('M') => (&digits[..digits.len() - 1], 1024 * 1024),
+        _ => (digits, 1),
     };
+    let size = digits
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("Invalid size '{}', expected e.g. 800K or 20M", s))?;
+    if size > MAX_SYNC_SIZE {
+        return Err(format!(
+            "Size '{}' is over the server's limit of {} MB",
+            s,
+            MAX_SYNC_SIZE / (1024 * 1024)
+        ));
+    }
+    Ok(size)
+}
+
+/// Where `clipsync copy` reads its content from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CopySource {
+    /// Piped stdin if there is any, otherwise the system clipboard
+    #[default]
+    Auto,
+    /// `clipsync copy -`: read stdin even if it is a terminal
+    Stdin,
+    /// `clipsync copy --clipboard`: ignore stdin and read the system clipboard
+    Clipboard,
+}
+
+#[derive(Debug, Default)]
+pub struct CopyOptions {
+    pub source: CopySource,
+    /// Append to the current text clip instead of replacing it
+    pub append: bool,
+    /// Treat the input as HTML
+    pub html: bool,
+    /// Only set the local clipboard
+    pub local_only: bool,
+    /// Clipboard representation to read (e.g. "text/html")
+    pub target: Option<String>,
+    /// Succeed without syncing when the clipboard is empty
+    pub allow_empty: bool,
+    /// Also remove the synced clip when the clipboard is empty
+    pub clear_on_empty: bool,
+    /// Device name the clip is meant for (informational)
+    pub target_device: Option<String>,
+    /// Skip content identical to a sync this recent; None uses the config
+    pub dedup_window: Option<Duration>,
+    /// Have the server delete the clip after this long
+    pub ttl: Option<Duration>,
+    /// Size limit for this copy instead of `max_clip_bytes`
+    pub max_size: Option<usize>,
+    /// Refuse content under `min_sync_bytes`, like the clipboard watcher
+    pub apply_min_size: bool,
+    /// Wait until this many other devices apply the clip
+    pub wait_confirm: Option<usize>,
+    /// Give up on `wait_confirm` after this long
+    pub confirm_timeout: Duration,
+    /// Don't report the outcome on stderr; errors still are
+    pub quiet: bool,
+}
+
+/// Read the copy input. An explicit source wins over auto-detection, and
+/// picking a clipboard target implies reading the clipboard.
+fn read_input(source: CopySource, target: Option<&str>) -> Result<Option<Vec<u8>>> {
+    if target.is_some() {
+        return Ok(None);
+    }
+    match source {
+        CopySource::Auto => read_stdin(),
+        CopySource::Stdin => {
+            let mut buf = Vec::new();
+            std::io::stdin().read_to_end(&mut buf)?;
+            Ok(Some(buf))
+        }
+        CopySource::Clipboard => Ok(None),
+    }
+}
 
-    let response = super::send_request(Request::Copy { data }).await?;
+pub async fn run(options: CopyOptions) -> Result<()> {
+    let CopyOptions {
+        source,
+        append,
+        html,
+        local_only,
+        target,
+        allow_empty,
+        clear_on_empty,
+        target_device,
+        dedup_window,
+        ttl,
+        max_size,
+        apply_min_size,
+        wait_confirm,
+        confirm_timeout,
+        quiet,
+    } = options;
+    let config = Config::load_or_default();
+    let allow_empty = allow_empty || config.allow_empty_copy;
+    let dedup_window_ms = dedup_window
+        .map(|window| window.as_millis() as u64)
+        .unwrap_or(config.copy_dedup_window_ms);
+    let data = read_input(source, target.as_deref())?;
+    if html && data.is_none() {
+        bail!("Pipe the HTML to copy on stdin: clipsync copy --html < page.html");
+    }
+    if local_only && data.is_none() {
+        bail!("Pipe the content to copy on stdin: echo hello | clipsync copy --local-only");
+    }
+    if let Some(data) = &data {
+        check_frame_size(data.len()).map_err(|e| anyhow::anyhow!("{}", e))?;
+    }
 
-    match response {
-        Response::Ok => {
-            eprintln!("Clipboard synced");
+    let response = super::send_request(Request::Copy {
+        data,
+        append,
+        html,
+        local_only,
+        target,
+        allow_empty,
+        clear_on_empty,
+        target_device,
+        dedup_window_ms,
+        ttl_secs: ttl.map(|ttl| ttl.as_secs()),
+        max_size: max_size.map(|size| size as u64),
+        apply_min_size,
+        wait_confirm: wait_confirm.map(|devices| WaitConfirm {
+            devices,
+            timeout_secs: confirm_timeout.as_secs().max(1),
+        }),
+    })
+    .await?;
+
+    let confirmed;
+    let outcome = match response {
+        Response::Ok if local_only => "Local clipboard set",
+        Response::Ok => "Clipboard synced",
+        Response::SkippedDuplicate => "Same content was just synced, skipped",
+        Response::Confirmed { device_ids } => {
+            confirmed = format!("Clipboard synced, applied by {}", device_ids.join(", "));
+            &confirmed
         }
+        Response::NothingToCopy { cleared: false } => "Clipboard is empty, nothing to copy",
+        Response::NothingToCopy { cleared: true } => "Clipboard is empty, cleared the synced clip",
         Response::Error { message } => {
             bail!("{}", message);
         }
         _ => {
             bail!("Unexpected response");
         }
+    };
+    if !quiet {
+        eprintln!("{}", outcome);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_size_accepts_units_up_to_server_limit() {
+        assert_eq!(parse_max_size("4096"), Ok(4096));
+        assert_eq!(parse_max_size("800K"), Ok(800 * 1024));
+        assert_eq!(parse_max_size("20mb"), Ok(20 * 1024 * 1024));
+        assert_eq!(parse_max_size("55M"), Ok(MAX_SYNC_SIZE));
+        assert!(parse_max_size("56M").is_err());
+        assert!(parse_max_size("0").is_err());
+        assert!(parse_max_size("MB").is_err());
+        assert!(parse_max_size("lots").is_err());
+    }
+}
@@ -1,19 +1,81 @@
 use anyhow::{bail, Result};
 
-use crate::protocol::{Request, Response};
+use crate::config;
+use crate::protocol::{DeviceInfo, Page, Request, Response};
 
-pub async fn run() -> Result<()> {
-    let response = super::send_request(Request::ListDevices).await?;
+/// Pairs of (stale, newest) devices sharing a name, as left behind when a
+/// reinstall generates a fresh `device_id`. The most recently registered
+/// device of each name is taken to be the live one.
+fn likely_duplicates(devices: &[DeviceInfo]) -> Vec<(&DeviceInfo, &DeviceInfo)> {
+    let mut duplicates = Vec::new();
+    for device in devices {
+        let newest = devices
+            .iter()
+            .filter(|d| d.device_name.eq_ignore_ascii_case(&device.device_name))
+            .max_by_key(|d| (d.registered_at, d.id));
+        if let Some(newest) = newest.filter(|newest| newest.id != device.id) {
+            duplicates.push((device, newest));
+        }
+    }
+    duplicates
+}
+
+/// List the account's devices, or only page `page` (from 1) of them.
+pub async fn run(me: bool, page: Option<usize>) -> Result<()> {
+    let response = super::send_request(Request::ListDevices {
+        page: page.map(|number| Page::numbered(number, super::PAGE_SIZE)),
+    })
+    .await?;
+    let current = config::load_device_id()?;
 
     match response {
-        Response::Devices { devices } => {
+        Response::Devices { mut devices, total } => {
+            if me {
+                devices.retain(|d| Some(&d.device_id) == current.as_ref());
+            }
             if devices.is_empty() {
-                println!("No devices registered");
+                if me {
+                    println!("This device is not registered");
+                } else if page.is_some() && total > 0 {
+                    println!("No devices on this page ({} total)", total);
+                } else {
+                    println!("No devices registered");
+                }
             } else {
-                println!("{:<6} {:<38} {:<20}", "ID", "Device ID", "Name");
-                println!("{}", "-".repeat(64));
-                for d in devices {
-                    println!("{:<6} {:<38} {:<20}", d.id, d.device_id, d.device_name);
+                println!("  {:<6} {:<38} {:<20}", "ID", "Device ID", "Name");
+                println!("{}", "-".repeat(66));
+                for d in &devices {
+                    let marker = if Some(&d.device_id) == current.as_ref() {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!(
+                        "{} {:<6} {:<38} {:<20}",
+                        marker, d.id, d.device_id, d.device_name
+                    );
+                }
+
+                if let Some(number) = page {
+                    super::print_page_footer(number, total);
+                }
+
+                // A page may split a duplicate from its newest device
+                let duplicates = match page {
+                    Some(_) => Vec::new(),
+                    None => likely_duplicates(&devices),
+                };
+                if !duplicates.is_empty() {
+                    println!();
+                    println!(
+                        "Likely duplicates (same name, not seen since a newer one registered):"
+                    );
+                    for (stale, newest) in duplicates {
+                        println!(
+                            "  clipsync devices --merge {} --into {}",
+                            stale.device_id, newest.device_id
+                        );
+                    }
                 }
             }
         }
@@ -27,3 +89,66 @@ pub async fn run() -> Result<()> {
 
     Ok(())
 }
+
+/// Merge the device `old` into `into`, or into this device if not given.
+pub async fn merge(old: String, into: Option<String>) -> Result<()> {
+    let new_device_id = match into {
+        Some(id) => id,
+        None => config::load_device_id()?
+            .ok_or_else(|| anyhow::anyhow!("This device has no device_id; pass --into"))?,
+    };
+    let response = super::send_request(Request::MergeDevice {
+        old_device_id: old.clone(),
+        new_device_id: new_device_id.clone(),
+    })
+    .await?;
+
+    match response {
+        Response::Ok => {
+            println!("Merged {} into {}", old, new_device_id);
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: u64, name: &str, registered_at: Option<u64>) -> DeviceInfo {
+        DeviceInfo {
+            id,
+            device_id: format!("device-{}", id),
+            device_name: name.to_string(),
+            registered_at,
+        }
+    }
+
+    #[test]
+    fn duplicates_point_at_newest_of_same_name() {
+        let devices = vec![
+            device(1, "laptop", Some(100)),
+            device(2, "desk", Some(50)),
+            device(3, "Laptop", Some(300)),
+            device(4, "laptop", Some(200)),
+        ];
+        let pairs: Vec<(u64, u64)> = likely_duplicates(&devices)
+            .into_iter()
+            .map(|(stale, newest)| (stale.id, newest.id))
+            .collect();
+        assert_eq!(pairs, vec![(1, 3), (4, 3)]);
+    }
+
+    #[test]
+    fn unique_names_have_no_duplicates() {
+        let devices = vec![device(1, "laptop", None), device(2, "desk", None)];
+        assert!(likely_duplicates(&devices).is_empty());
+    }
+}
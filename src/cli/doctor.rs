@@ -0,0 +1,228 @@
+use anyhow::{bail, Context, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::config::{self, Config};
+use crate::crypto;
+
+/// Whether `mode` grants anything beyond `allowed` (e.g. group or other bits
+/// on a file that should be 0600).
+fn too_open(mode: u32, allowed: u32) -> bool {
+    mode & 0o777 & !allowed != 0
+}
+
+/// Tally of what the checks found, printing each result as it goes.
+struct Report {
+    fix: bool,
+    /// Problems `--fix` could repair, left alone because it wasn't given
+    fixable: usize,
+    /// Problems that need the user, or whose repair failed
+    unfixed: usize,
+}
+
+impl Report {
+    fn ok(&self, what: &str) {
+        println!("ok       {}", what);
+    }
+
+    /// Report a problem, and repair it when running with `--fix`. Repairs
+    /// only act on the problem they were called for, so re-running is safe.
+    fn repairable(&mut self, problem: &str, repair: impl FnOnce() -> Result<String>) {
+        if !self.fix {
+            println!("problem  {}", problem);
+            self.fixable += 1;
+            return;
+        }
+        match repair() {
+            Ok(done) => println!("fixed    {}: {}", problem, done),
+            Err(e) => {
+                println!("problem  {}: repair failed: {:#}", problem, e);
+                self.unfixed += 1;
+            }
+        }
+    }
+
+    fn unfixable(&mut self, problem: &str, guidance: &str) {
+        println!("problem  {}", problem);
+        println!("         {}", guidance);
+        self.unfixed += 1;
+    }
+}
+
+fn check_config_dir(report: &mut Report, dir: &Path) -> Result<()> {
+    match std::fs::metadata(dir) {
+        Ok(meta) if too_open(meta.permissions().mode(), 0o700) => {
+            report.repairable(
+                &format!("{} is accessible to other users", dir.display()),
+                || {
+                    config::ensure_config_dir()?;
+                    Ok("set to 0700".to_string())
+                },
+            );
+        }
+        Ok(_) => report.ok(&format!("{} permissions", dir.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            report.repairable(&format!("{} is missing", dir.display()), || {
+                config::ensure_config_dir()?;
+                Ok("created".to_string())
+            });
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to inspect {}", dir.display())),
+    }
+    Ok(())
+}
+
+/// Every file in the config dir holds credentials or clip data, so all of
+/// them should be owner-only.
+fn check_file_permissions(report: &mut Report, dir: &Path) -> Result<()> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+    let mut loose = 0;
+    for entry in entries {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if !meta.is_file() || !too_open(meta.permissions().mode(), 0o600) {
+            continue;
+        }
+        loose += 1;
+        let path = entry.path();
+        report.repairable(
+            &format!("{} is readable by other users", path.display()),
+            || {
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+                Ok("set to 0600".to_string())
+            },
+        );
+    }
+    if loose == 0 {
+        report.ok("config file permissions");
+    }
+    Ok(())
+}
+
+fn check_config_file(report: &mut Report, dir: &Path) {
+    let path = dir.join("config.toml");
+    if !path.exists() {
+        report.repairable("config.toml is missing", || {
+            Config::default().save()?;
+            Ok("wrote the defaults".to_string())
+        });
+        return;
+    }
+    match Config::load() {
+        Ok(_) => report.ok("config.toml"),
+        Err(e) => report.unfixable(
+            &format!("config.toml can't be read: {:#}", e),
+            &format!(
+                "Fix or remove {} (doctor --fix then recreates it)",
+                path.display()
+            ),
+        ),
+    }
+}
+
+fn check_device_id(report: &mut Report) -> Result<()> {
+    if config::load_device_id()?.is_some() {
+        report.ok("device_id");
+        return Ok(());
+    }
+    report.repairable("device_id is missing", || {
+        let id = uuid::Uuid::new_v4().to_string();
+        config::save_device_id(&id)?;
+        Ok(format!(
+            "generated {}; the daemon registers it on its next connect",
+            id
+        ))
+    });
+    Ok(())
+}
+
+fn check_identity(report: &mut Report) -> Result<()> {
+    let path = crypto::identity_file_path()?;
+    if !path.exists() {
+        report.unfixable(
+            "identity key is missing, so clips can't be decrypted",
+            "Restore it with `clipsync import-key`, or run `clipsync setup` again",
+        );
+        return Ok(());
+    }
+    match crypto::load_private_key() {
+        Ok(_) => report.ok("identity key"),
+        Err(e) => report.unfixable(
+            &format!("identity key can't be loaded: {:#}", e),
+            "Restore it with `clipsync import-key`, or run `clipsync setup` again",
+        ),
+    }
+    Ok(())
+}
+
+/// A socket file nothing is listening on is left over from a daemon that
+/// didn't shut down cleanly.
+fn check_socket(report: &mut Report) {
+    let path = config::socket_path();
+    if !path.exists() {
+        report.ok("no stale daemon socket");
+        return;
+    }
+    match std::os::unix::net::UnixStream::connect(&path) {
+        Ok(_) => report.ok("daemon socket is answering"),
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            report.repairable(
+                &format!("{} is stale (no daemon listening)", path.display()),
+                || {
+                    std::fs::remove_file(&path)?;
+                    Ok("removed".to_string())
+                },
+            );
+        }
+        Err(e) => report.unfixable(
+            &format!("{} can't be reached: {}", path.display(), e),
+            "Check its permissions, or stop the daemon and remove it",
+        ),
+    }
+}
+
+/// Check the local setup for common problems, repairing the safe ones with
+/// `fix`. Fails if any problem is left.
+pub fn run(fix: bool) -> Result<()> {
+    let dir = config::config_dir()?;
+    let mut report = Report {
+        fix,
+        fixable: 0,
+        unfixed: 0,
+    };
+
+    check_config_dir(&mut report, &dir)?;
+    check_config_file(&mut report, &dir);
+    check_device_id(&mut report)?;
+    check_identity(&mut report)?;
+    check_socket(&mut report);
+    // Last, so files the fixes above just wrote are checked too
+    check_file_permissions(&mut report, &dir)?;
+
+    if report.fixable > 0 {
+        println!();
+        println!("Run `clipsync doctor --fix` to repair what can be repaired automatically.");
+    }
+    let remaining = report.fixable + report.unfixed;
+    if remaining > 0 {
+        bail!("{} problem(s) found", remaining);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn too_open_flags_extra_bits_only() {
+        assert!(!too_open(0o100600, 0o600));
+        assert!(!too_open(0o400, 0o600));
+        assert!(too_open(0o644, 0o600));
+        assert!(too_open(0o604, 0o600));
+        assert!(!too_open(0o40700, 0o700));
+        assert!(too_open(0o755, 0o700));
+    }
+}
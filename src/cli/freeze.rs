@@ -0,0 +1,77 @@
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+use crate::protocol::{Request, Response};
+
+/// Parse a duration like `90s`, `5m` or `2h`. A bare number is seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit_secs) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        _ => (s, 1),
+    };
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}' (expected e.g. 30s, 5m, 1h)", s))?;
+    if value == 0 {
+        return Err("Duration must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(value * unit_secs))
+}
+
+pub async fn freeze(duration: Option<Duration>, quiet: bool) -> Result<()> {
+    let for_secs = duration.map(|d| d.as_secs());
+    match super::send_request(Request::Freeze { for_secs }).await? {
+        Response::Ok if quiet => {}
+        Response::Ok => match for_secs {
+            Some(secs) => eprintln!("Clipboard frozen for {}s", secs),
+            None => eprintln!("Clipboard frozen until `clipsync unfreeze`"),
+        },
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn unfreeze(quiet: bool) -> Result<()> {
+    match super::send_request(Request::Unfreeze).await? {
+        Response::Ok if quiet => {}
+        Response::Ok => eprintln!("Clipboard unfrozen"),
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_duration_units() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("0m").is_err());
+    }
+}
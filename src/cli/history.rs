@@ -0,0 +1,224 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+
+use crate::crypto;
+use crate::history::{self, HistoryEntry};
+use crate::payload::{self, ClipboardPayload};
+use crate::protocol::{Page, Request, Response};
+
+const PREVIEW_CHARS: usize = 60;
+
+/// Longest side of an image history thumbnail, in pixels.
+const THUMBNAIL_SIZE: u32 = 32;
+
+/// Kitty splits inline image data into chunks of at most this many bytes.
+const KITTY_CHUNK: usize = 4096;
+
+/// Terminal escape protocols for drawing an image inline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InlineImages {
+    Kitty,
+    Iterm,
+}
+
+impl InlineImages {
+    /// Detect image support from the environment. Only used when stdout is a
+    /// terminal, so piped output stays plain text.
+    fn detect() -> Option<Self> {
+        if !std::io::stdout().is_terminal() {
+            return None;
+        }
+        let term = std::env::var("TERM").unwrap_or_default();
+        let term_program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+        if term.contains("kitty") || std::env::var_os("KITTY_WINDOW_ID").is_some() {
+            Some(Self::Kitty)
+        } else if matches!(term_program.as_str(), "iTerm.app" | "WezTerm") {
+            Some(Self::Iterm)
+        } else {
+            None
+        }
+    }
+
+    /// Escape sequence drawing `png` two rows high.
+    fn escape(self, png: &[u8]) -> String {
+        let data = base64::engine::general_purpose::STANDARD.encode(png);
+        match self {
+            Self::Iterm => format!(
+                "\x1b]1337;File=inline=1;size={};height=2;preserveAspectRatio=1:{}\x07",
+                png.len(),
+                data
+            ),
+            Self::Kitty => {
+                let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+                let mut out = String::new();
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let more = u8::from(i + 1 < chunks.len());
+                    let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+                    if i == 0 {
+                        out.push_str(&format!("\x1b_Gf=100,a=T,r=2,m={};{}\x1b\\", more, chunk));
+                    } else {
+                        out.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Thumbnails for image entries, keyed by content hash so repeated copies of
+/// the same image are only downscaled once. Kept in memory only, since the
+/// history file is encrypted and thumbnails would leak its images.
+#[derive(Default)]
+struct Thumbnails {
+    cache: HashMap<u64, Option<Vec<u8>>>,
+}
+
+impl Thumbnails {
+    fn get(&mut self, payload: &ClipboardPayload) -> Option<&[u8]> {
+        let ClipboardPayload::Image { png_data, .. } = payload else {
+            return None;
+        };
+        self.cache
+            .entry(payload.content_hash())
+            .or_insert_with(|| payload::thumbnail_png(png_data, THUMBNAIL_SIZE).ok())
+            .as_deref()
+    }
+}
+
+fn local_identity() -> Result<age::x25519::Identity> {
+    crypto::load_private_key().context("No encryption key found. Run `clipsync setup`.")
+}
+
+/// Every readable local history entry with its index, newest first.
+pub(super) fn load_local() -> Result<Vec<(usize, HistoryEntry)>> {
+    history::load(&local_identity()?)
+}
+
+pub(super) fn format_age(timestamp: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let secs = now.saturating_sub(timestamp);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+pub fn run(local: bool, page: Option<usize>) -> Result<()> {
+    if !local {
+        bail!("Server-side history is not available. Use `clipsync history --local`.");
+    }
+
+    // Page by index range, so listed indexes still work with --delete and
+    // restore; only the page's records are decrypted
+    let shown = match page {
+        Some(number) => Page::numbered(number, super::PAGE_SIZE),
+        None => Page {
+            offset: 0,
+            limit: usize::MAX,
+        },
+    };
+    let (entries, total) = history::load_page(&local_identity()?, shown.offset, shown.limit)?;
+    if total == 0 {
+        println!("Local history is empty");
+        println!("Enable it with: clipsync config local_history true");
+        return Ok(());
+    }
+
+    let inline_images = InlineImages::detect();
+    let mut thumbnails = Thumbnails::default();
+
+    println!(
+        "{:<6} {:<10} {:<6} {:<12} {}",
+        "Index", "Age", "Type", "App", "Preview"
+    );
+    println!("{}", "-".repeat(80));
+    for (i, entry) in &entries {
+        let mut preview = entry.payload.preview(PREVIEW_CHARS);
+        let thumb = inline_images
+            .and_then(|protocol| Some(protocol.escape(thumbnails.get(&entry.payload)?)));
+        if let Some(thumb) = thumb {
+            preview = format!("{} {}", thumb, preview);
+        }
+        println!(
+            "{:<6} {:<10} {:<6} {:<12.12} {}",
+            i,
+            format_age(entry.timestamp),
+            entry.payload.content_type_str(),
+            entry.source_app.as_deref().unwrap_or("-"),
+            preview
+        );
+    }
+    if let Some(number) = page {
+        super::print_page_footer(number, total);
+    }
+
+    Ok(())
+}
+
+pub async fn delete(index: usize, local: bool, quiet: bool) -> Result<()> {
+    if !local {
+        bail!("Server-side history is not available. Use `clipsync history --local --delete`.");
+    }
+
+    let entry = history::get(&local_identity()?, index)?;
+
+    // The daemon writes the history file, so it does the delete too
+    let response = super::send_request(Request::DeleteHistory {
+        index,
+        timestamp: entry.timestamp,
+    })
+    .await?;
+
+    match response {
+        Response::Ok => {
+            if !quiet {
+                eprintln!("Deleted history entry {}", index);
+            }
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn restore(index: usize, local: bool, quiet: bool) -> Result<()> {
+    if !local {
+        bail!("Server-side history is not available. Use `clipsync restore --local`.");
+    }
+
+    let entry = history::get(&local_identity()?, index)?;
+
+    let response = super::send_request(Request::Restore {
+        payload: entry.payload,
+    })
+    .await?;
+
+    match response {
+        Response::Ok => {
+            if !quiet {
+                eprintln!("Restored history entry {}", index);
+            }
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use anyhow::{bail, Context, Result};
+
+use crate::config;
+use crate::crypto;
+
+use super::setup;
+
+/// Restore this device's private key from a recovery string printed by
+/// `clipsync setup --print-recovery`.
+pub fn run(recovery: Option<String>, force: bool) -> Result<()> {
+    let _lock = config::acquire_lock("setup.lock")
+        .map_err(|_| anyhow::anyhow!("Setup already in progress"))?;
+
+    let recovery = match recovery {
+        Some(r) => r,
+        // Read without echo so the string doesn't linger on screen or in scrollback
+        None => rpassword::prompt_password("Recovery string: ")?,
+    };
+    let encrypted = crypto::decode_recovery(&recovery)?;
+
+    let password = super::read_password(&config::Config::load_or_default())?;
+    let identity = setup::decrypt_server_key(&encrypted, &password)?;
+
+    if let Ok(existing) = crypto::load_private_key() {
+        if existing.to_public().to_string() == identity.to_public().to_string() {
+            println!("This key is already installed.");
+            return Ok(());
+        }
+        if !force {
+            bail!(
+                "A different key is already installed at {}. Use --force to replace it.",
+                crypto::identity_file_path()?.display()
+            );
+        }
+    }
+
+    crypto::store_private_key(&identity).context("Failed to save the recovered key")?;
+    println!("Key restored. Public key: {}", identity.to_public());
+    Ok(())
+}
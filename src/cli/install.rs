@@ -1,6 +1,9 @@
 use anyhow::{Context, Result};
 use service_manager::*;
 use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::config;
 
 const SERVICE_LABEL: &str = "com.clipsync.daemon";
 
@@ -11,8 +14,8 @@ fn service_label() -> Result<ServiceLabel> {
 }
 
 pub async fn install() -> Result<()> {
-    let mut manager = <dyn ServiceManager>::native()
-        .context("Failed to get native service manager")?;
+    let mut manager =
+        <dyn ServiceManager>::native().context("Failed to get native service manager")?;
     manager
         .set_level(ServiceLevel::User)
         .context("Failed to set service level to user")?;
@@ -46,9 +49,92 @@ pub async fn install() -> Result<()> {
     Ok(())
 }
 
-pub async fn uninstall() -> Result<()> {
-    let mut manager = <dyn ServiceManager>::native()
-        .context("Failed to get native service manager")?;
+/// Marker line from `scripts/xclip-clipsync`, used to recognise copies of the shim.
+const SHIM_MARKER: &str = "xclip shim that delegates clipboard reads to clipsync";
+
+/// Whether `path` is an xclip shim pointing back at clipsync: a symlink to the
+/// shim script or to this binary, or a copy of the shim script.
+fn is_clipsync_shim(path: &Path, exe: Option<&Path>) -> bool {
+    let Ok(target) = std::fs::canonicalize(path) else {
+        return false;
+    };
+    if exe.is_some_and(|exe| exe == target)
+        || target
+            .file_name()
+            .is_some_and(|name| name == "xclip-clipsync")
+    {
+        return true;
+    }
+    std::fs::read(&target)
+        .map(|contents| {
+            contents
+                .windows(SHIM_MARKER.len())
+                .any(|w| w == SHIM_MARKER.as_bytes())
+        })
+        .unwrap_or(false)
+}
+
+/// `xclip` entries on PATH that are clipsync shims.
+fn installed_shims() -> Vec<PathBuf> {
+    let exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| std::fs::canonicalize(exe).ok());
+    let Some(path_var) = std::env::var_os("PATH") else {
+        return Vec::new();
+    };
+    std::env::split_paths(&path_var)
+        .map(|dir| dir.join("xclip"))
+        .filter(|candidate| is_clipsync_shim(candidate, exe.as_deref()))
+        .collect()
+}
+
+/// Delete all local clipsync state: the config directory (config, token, keys,
+/// device id, local history), the daemon socket and any xclip shims.
+fn purge() -> Result<()> {
+    let config_dir = config::config_dir()?;
+    let socket = config::socket_path();
+    let shims = installed_shims();
+
+    let targets: Vec<&Path> = std::iter::once(config_dir.as_path())
+        .chain(std::iter::once(socket.as_path()))
+        .chain(shims.iter().map(PathBuf::as_path))
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect();
+    if targets.is_empty() {
+        println!("No local clipsync data found.");
+        return Ok(());
+    }
+
+    println!("This will permanently delete:");
+    for path in &targets {
+        println!("  {}", path.display());
+    }
+    println!("Your account stays on the server; run `clipsync setup` to use this device again.");
+    if !super::confirm("Continue?")? {
+        println!("Nothing was removed.");
+        return Ok(());
+    }
+
+    for path in targets {
+        let result = if path == config_dir {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+        match result {
+            Ok(()) => println!("Removed {}", path.display()),
+            Err(e) => eprintln!("Failed to remove {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the service. With `purge`, also delete all local clipsync data;
+/// by default config, keys and tokens are left in place.
+pub async fn uninstall(purge: bool) -> Result<()> {
+    let mut manager =
+        <dyn ServiceManager>::native().context("Failed to get native service manager")?;
     manager
         .set_level(ServiceLevel::User)
         .context("Failed to set service level to user")?;
@@ -58,13 +144,22 @@ pub async fn uninstall() -> Result<()> {
         label: service_label()?,
     });
 
-    manager
+    let result = manager
         .uninstall(ServiceUninstallCtx {
             label: service_label()?,
         })
-        .context("Failed to uninstall service")?;
+        .context("Failed to uninstall service");
+
+    match result {
+        Ok(()) => println!("Service uninstalled."),
+        // The service may never have been installed; purging should still go ahead
+        Err(e) if purge => eprintln!("{:#}", e),
+        Err(e) => return Err(e),
+    }
 
-    println!("Service uninstalled.");
+    if purge {
+        self::purge()?;
+    }
 
     Ok(())
 }
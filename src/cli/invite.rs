@@ -2,18 +2,13 @@ use anyhow::{bail, Result};
 
 use crate::protocol::{Request, Response};
 
-pub async fn run() -> Result<()> {
+async fn create_invite() -> Result<String> {
     let code = uuid::Uuid::new_v4().to_string();
 
     let response = super::send_request(Request::CreateInvite { code }).await?;
 
     match response {
-        Response::InviteCreated { code } => {
-            println!("Invite code: {}", code);
-            println!();
-            println!("Share this with the person you want to invite:");
-            println!("  clipsync setup <username> --invite-code {}", code);
-        }
+        Response::InviteCreated { code } => Ok(code),
         Response::Error { message } => {
             bail!("{}", message);
         }
@@ -21,6 +16,47 @@ pub async fn run() -> Result<()> {
             bail!("Unexpected response from daemon");
         }
     }
+}
+
+pub async fn run(count: u32) -> Result<()> {
+    if count == 0 {
+        bail!("--count must be at least 1");
+    }
+
+    if count == 1 {
+        let code = create_invite().await?;
+        println!("Invite code: {}", code);
+        println!();
+        println!("Share this with the person you want to invite:");
+        println!("  clipsync setup <username> --invite-code {}", code);
+        return Ok(());
+    }
+
+    let mut codes = Vec::new();
+    let mut failure = None;
+    for _ in 0..count {
+        match create_invite().await {
+            Ok(code) => codes.push(code),
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+
+    if !codes.is_empty() {
+        println!("Invite codes (one per person):");
+        for code in &codes {
+            println!("{}", code);
+        }
+        println!();
+        println!("Each person runs:");
+        println!("  clipsync setup <username> --invite-code <code>");
+    }
+
+    if let Some(e) = failure {
+        bail!("Created {} of {} invite codes: {}", codes.len(), count, e);
+    }
 
     Ok(())
 }
@@ -0,0 +1,186 @@
+use anyhow::{bail, Context, Result};
+use spacetimedb_sdk::{DbContext, Identity, Table};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{self, Config};
+use crate::crypto;
+use crate::module_bindings::*;
+
+use super::setup::{self, AuthRequest};
+
+/// Connect to a server with an existing token and read back the account's
+/// profile and devices.
+fn fetch_account(
+    server_url: String,
+    database_name: String,
+    token: Option<String>,
+) -> Result<(UserProfile, Vec<DeviceView>)> {
+    let (result_tx, result_rx) =
+        std::sync::mpsc::channel::<Result<(UserProfile, Vec<DeviceView>), String>>();
+
+    std::thread::Builder::new()
+        .name("migrate-stdb".to_string())
+        .spawn(move || {
+            let result_tx_sub = result_tx.clone();
+            let result_tx_build = result_tx.clone();
+
+            let conn = DbConnection::builder()
+                .with_uri(&server_url)
+                .with_database_name(&database_name)
+                .with_token(token)
+                .on_connect(
+                    move |conn: &DbConnection, _identity: Identity, _token: &str| {
+                        conn.subscription_builder()
+                            .on_applied(move |ctx: &SubscriptionEventContext| {
+                                let result = match ctx.db.my_profile().iter().next() {
+                                    Some(profile) => {
+                                        Ok((profile, ctx.db.my_devices().iter().collect()))
+                                    }
+                                    None => Err("Not logged in on the old server".to_string()),
+                                };
+                                let _ = result_tx_sub.send(result);
+                            })
+                            .subscribe_to_all_tables();
+                    },
+                )
+                .on_disconnect(
+                    move |_ctx: &ErrorContext, err: Option<spacetimedb_sdk::Error>| {
+                        if let Some(e) = err {
+                            let _ = result_tx.send(Err(format!("Disconnected: {:?}", e)));
+                        }
+                    },
+                )
+                .build();
+
+            let conn = match conn {
+                Ok(conn) => Arc::new(conn),
+                Err(e) => {
+                    let _ = result_tx_build
+                        .send(Err(format!("Failed to connect to SpacetimeDB: {}", e)));
+                    return;
+                }
+            };
+            let _handle = conn.run_threaded();
+
+            std::thread::sleep(Duration::from_secs(60));
+        })?;
+
+    result_rx
+        .recv_timeout(Duration::from_secs(30))
+        .context("Timed out waiting for the old server")?
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+pub async fn run(
+    from: Option<String>,
+    to: String,
+    database_name: Option<String>,
+    invite_code: Option<String>,
+) -> Result<()> {
+    let _lock = config::acquire_lock("setup.lock")
+        .map_err(|_| anyhow::anyhow!("Setup already in progress"))?;
+
+    let mut config = Config::load_or_default();
+    let from = from.unwrap_or_else(|| config.server_url.clone());
+    let to_database = database_name.unwrap_or_else(|| config.database_name.clone());
+    if from == to && to_database == config.database_name {
+        bail!("Already using {}", to);
+    }
+
+    let identity = crypto::load_private_key()
+        .context("No local key to migrate. Run `clipsync setup` first.")?;
+    let device_id = setup::load_or_create_device_id()?;
+    let device_name = gethostname::gethostname().to_string_lossy().to_string();
+
+    println!("Reading account from {}...", from);
+    let (profile, devices) = fetch_account(
+        from.clone(),
+        config.database_name.clone(),
+        config::load_token()?,
+    )?;
+
+    let password = rpassword::prompt_password(format!("Password for {}: ", profile.username))?;
+
+    // Make sure the password unlocks the old account's key and that it is the key we hold
+    let old_identity = setup::decrypt_server_key(&profile.encrypted_private_key, &password)?;
+    if old_identity.to_public().to_string() != identity.to_public().to_string() {
+        bail!("Local key does not match the account on {}", from);
+    }
+
+    use age::secrecy::ExposeSecret;
+    let private_key_str = identity.to_string().expose_secret().to_string();
+    let encrypted_private_key =
+        crypto::encrypt_with_passphrase(private_key_str.as_bytes(), &password)?;
+
+    println!("Authenticating with {}...", to);
+    let outcome = setup::authenticate(
+        to.clone(),
+        to_database.clone(),
+        None,
+        None,
+        AuthRequest {
+            username: profile.username.clone(),
+            password: password.clone(),
+            encrypted_private_key,
+            public_key: crypto::public_key_bytes(&identity.to_public()),
+            device_id: device_id.clone(),
+            device_name,
+            invite_code: invite_code.unwrap_or_default(),
+        },
+        // The password already unlocked the old account's key
+        || Ok(None),
+    )?;
+
+    // If the account already existed on the new server it must carry the same key,
+    // otherwise clips from this device would be unreadable there.
+    let new_identity = setup::decrypt_server_key(&outcome.encrypted_private_key, &password)?;
+    if new_identity.to_public().to_string() != identity.to_public().to_string() {
+        bail!(
+            "{} already has an account named {} with a different key",
+            to,
+            profile.username
+        );
+    }
+
+    config.server_url = to.clone();
+    config.database_name = to_database;
+    // A pin for the old server would lock this device out of the new one
+    let repinned = config.server_fingerprint.is_some();
+    config.server_fingerprint = if repinned {
+        outcome.server_identity.clone()
+    } else {
+        None
+    };
+    config.save()?;
+    config::save_user_id(outcome.user_id)?;
+    config::save_token(&outcome.token)?;
+
+    println!();
+    println!("Migrated {} to {}", profile.username, to);
+    if repinned {
+        match &outcome.server_identity {
+            Some(id) => println!("server_fingerprint is now pinned to {}", id),
+            None => println!(
+                "{} does not report its identity; server_fingerprint was cleared",
+                to
+            ),
+        }
+    }
+    let others: Vec<&DeviceView> = devices
+        .iter()
+        .filter(|d| d.device_id != device_id)
+        .collect();
+    if !others.is_empty() {
+        println!(
+            "Run `clipsync migrate-server --to {}` on your other devices:",
+            to
+        );
+        for d in others {
+            println!("  {} ({})", d.device_name, d.device_id);
+        }
+    }
+    println!("Restart the daemon for changes to take effect.");
+
+    Ok(())
+}
@@ -1,34 +1,125 @@
+pub mod admin;
+pub mod bench;
+pub mod clear;
 pub mod config;
 pub mod copy;
 pub mod devices;
+pub mod doctor;
+pub mod freeze;
+pub mod history;
+pub mod import_key;
 pub mod install;
 pub mod invite;
 pub mod logs;
+pub mod migrate;
 pub mod paste;
+pub mod qr;
+pub mod recent;
+pub mod rename_user;
 pub mod restart;
+pub mod revoke_sessions;
+pub mod send;
 pub mod setup;
 pub mod status;
+pub mod team;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod tune;
 pub mod xclip;
 
 use anyhow::{Context, Result};
 use bytes::BytesMut;
 use futures::{SinkExt, StreamExt};
+use std::path::Path;
 use tokio::net::UnixStream;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
-use crate::config::socket_path;
-use crate::protocol::{Request, Response, MAX_IPC_FRAME_SIZE};
+use crate::config::{self, socket_path, Config};
+use crate::protocol::{check_frame_size, Request, Response, MAX_IPC_FRAME_SIZE};
+
+/// Run `password_command` through the shell and return what it prints, minus
+/// one trailing newline. Its stdin and stderr are the terminal's, so commands
+/// like `pass` can still ask for a passphrase.
+fn run_password_command(command: &str) -> Result<String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run password_command `{}`", command))?;
+    if !output.status.success() {
+        anyhow::bail!("password_command `{}` failed ({})", command, output.status);
+    }
+    let mut password =
+        String::from_utf8(output.stdout).context("password_command printed non-UTF-8 output")?;
+    if password.ends_with('\n') {
+        password.pop();
+        if password.ends_with('\r') {
+            password.pop();
+        }
+    }
+    if password.is_empty() {
+        anyhow::bail!("password_command `{}` printed an empty password", command);
+    }
+    Ok(password)
+}
+
+/// Rows per page for list commands given `--page`.
+pub(crate) const PAGE_SIZE: usize = 20;
+
+/// Print where page `number` of `PAGE_SIZE` rows sits among `total`.
+pub(crate) fn print_page_footer(number: usize, total: usize) {
+    let pages = total.div_ceil(PAGE_SIZE).max(1);
+    println!();
+    println!("Page {} of {} ({} total)", number.max(1), pages, total);
+}
+
+/// Ask a yes/no question on the terminal; anything but yes is no.
+pub(crate) fn confirm(prompt: &str) -> Result<bool> {
+    use std::io::Write;
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// The account password: the output of `password_command` if one is
+/// configured, otherwise prompted for on the terminal.
+pub(crate) fn read_password(config: &Config) -> Result<String> {
+    match &config.password_command {
+        Some(command) => run_password_command(command),
+        None => Ok(rpassword::prompt_password("Password: ")?),
+    }
+}
+
+/// Check that `clipsync setup` has run on this device, so commands fail with
+/// the same guidance instead of whatever the socket or daemon reports.
+fn ensure_set_up() -> Result<()> {
+    if config::load_device_id()?.is_none()
+        || config::load_user_id()?.is_none()
+        || !config::token_exists()?
+    {
+        anyhow::bail!("Not logged in. Run `clipsync setup` first.");
+    }
+    Ok(())
+}
 
 pub async fn send_request(request: Request) -> Result<Response> {
+    ensure_set_up()?;
     let path = socket_path();
 
     if !path.exists() {
-        anyhow::bail!(
-            "Daemon not running. Start with `clipsync daemon` or `clipsync install`."
-        );
+        anyhow::bail!("Daemon not running. Start with `clipsync daemon` or `clipsync install`.");
     }
 
-    let stream = UnixStream::connect(&path)
+    send_request_at(&path, request).await
+}
+
+/// Send one request to the daemon socket at `path` and wait for its response.
+pub(crate) async fn send_request_at(path: &Path, request: Request) -> Result<Response> {
+    let stream = UnixStream::connect(path)
         .await
         .with_context(|| format!("Failed to connect to daemon at {}", path.display()))?;
 
@@ -38,6 +129,7 @@ pub async fn send_request(request: Request) -> Result<Response> {
     let mut framed = Framed::new(stream, codec);
 
     let request_bytes = serde_json::to_vec(&request)?;
+    check_frame_size(request_bytes.len()).map_err(|e| anyhow::anyhow!("{}", e))?;
     framed
         .send(BytesMut::from(&request_bytes[..]).freeze())
         .await?;
@@ -50,3 +142,25 @@ pub async fn send_request(request: Request) -> Result<Response> {
     let response: Response = serde_json::from_slice(&response_bytes)?;
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_command_output_is_trimmed_once() {
+        assert_eq!(
+            run_password_command("printf 'hunter2\\n'").unwrap(),
+            "hunter2"
+        );
+        assert_eq!(run_password_command("printf 'a b \\r\\n'").unwrap(), "a b ");
+        assert_eq!(run_password_command("printf 'x\\n\\n'").unwrap(), "x\n");
+    }
+
+    #[test]
+    fn password_command_failures_are_errors() {
+        assert!(run_password_command("exit 1").is_err());
+        assert!(run_password_command("printf ''").is_err());
+        assert!(run_password_command("echo").is_err());
+    }
+}
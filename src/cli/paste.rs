@@ -1,34 +1,287 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use base64::Engine;
 use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
+use crate::config;
+use crate::payload::ContentType;
 use crate::protocol::{Request, Response};
 
-pub async fn run(type_only: bool) -> Result<()> {
-    let response = super::send_request(Request::Paste).await?;
+/// Exit code for `paste --only-new` when the clip was already output.
+const NO_NEW_CLIP_EXIT_CODE: i32 = 3;
+
+/// How `paste` writes the clip's bytes to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PasteFormat {
+    /// The bytes as they are (UTF-8 for text)
+    #[default]
+    Raw,
+    Hex,
+    Base64,
+}
+
+impl FromStr for PasteFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(PasteFormat::Raw),
+            "hex" => Ok(PasteFormat::Hex),
+            "base64" => Ok(PasteFormat::Base64),
+            other => Err(format!(
+                "Unknown format '{}' (expected raw, hex or base64)",
+                other
+            )),
+        }
+    }
+}
+
+impl PasteFormat {
+    /// `data` encoded as one line of text, or None for `Raw`.
+    fn encode(self, data: &[u8]) -> Option<String> {
+        match self {
+            PasteFormat::Raw => None,
+            PasteFormat::Hex => Some(format!("{}\n", hex::encode(data))),
+            PasteFormat::Base64 => Some(format!(
+                "{}\n",
+                base64::engine::general_purpose::STANDARD.encode(data)
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PasteOptions {
+    /// Only print the content type
+    pub type_only: bool,
+    /// Take the clip off the server so no other device can paste it
+    pub consume: bool,
+    /// Gzip-encode the output
+    pub gzip: bool,
+    /// Write to this file instead of stdout
+    pub to_file: Option<PathBuf>,
+    /// Block until the next clip arrives
+    pub wait: bool,
+    /// With `wait`, accept the current clip if it is newer than this Unix time (seconds)
+    pub after: Option<i64>,
+    /// With `wait`, give up after this long
+    pub timeout: Option<Duration>,
+    /// Output nothing (and exit with `NO_NEW_CLIP_EXIT_CODE`) if the clip is
+    /// the one last output with this flag
+    pub only_new: bool,
+    /// Output the bytes whatever the content type, even one this build doesn't know
+    pub raw: bool,
+    /// Encoding of the bytes written to stdout
+    pub format: PasteFormat,
+    /// Don't explain on stderr why nothing was written to a terminal
+    pub quiet: bool,
+}
+
+fn last_seen_path() -> Result<PathBuf> {
+    Ok(config::config_dir()?.join("paste_last_seen"))
+}
+
+/// Marker identifying a clip for `--only-new`: its type and a hash of its
+/// data. FNV-1a rather than `DefaultHasher` so markers stay valid across
+/// Rust releases.
+fn clip_marker(content_type: &str, data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{}:{:016x}", content_type, hash)
+}
+
+fn read_last_seen(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Gzip-encode clip data for consumers that expect compressed input.
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn write_gzip(data: &[u8], quiet: bool) -> Result<bool> {
+    if std::io::stdout().is_terminal() {
+        if !quiet {
+            eprintln!("Gzip output is binary. Pipe to a file: clipsync paste --gzip > clip.gz");
+        }
+        return Ok(false);
+    }
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&gzip(data)?)?;
+    stdout.flush()?;
+    Ok(true)
+}
+
+/// Write clip data to stdout, refusing to dump image bytes into a terminal.
+/// `raw` writes any content type unchecked. Returns whether the data was
+/// actually written and flushed.
+pub(super) fn write_clip(content_type: &str, data: &[u8], raw: bool, quiet: bool) -> Result<bool> {
+    if !raw {
+        let content_type: ContentType = content_type.parse().map_err(anyhow::Error::msg)?;
+        if content_type == ContentType::Image && std::io::stdout().is_terminal() {
+            if !quiet {
+                eprintln!(
+                    "Image data ({} bytes). Pipe to a file: clipsync paste > image.png",
+                    data.len()
+                );
+            }
+            return Ok(false);
+        }
+    }
+    let mut stdout = std::io::stdout();
+    stdout.write_all(data)?;
+    stdout.flush()?;
+    Ok(true)
+}
+
+/// Write clip data to stdout as text encoded with `format`, which is safe for
+/// a terminal whatever the content type. Unknown types still need `raw`.
+fn write_encoded(content_type: &str, encoded: &str, raw: bool) -> Result<()> {
+    if !raw {
+        content_type
+            .parse::<ContentType>()
+            .map_err(anyhow::Error::msg)?;
+    }
+    let mut stdout = std::io::stdout();
+    stdout.write_all(encoded.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Write clip data to `path`. Unlike stdout there is no terminal to protect,
+/// so every known content type (or any, with `raw`) is written as-is.
+fn write_to_file(
+    path: &Path,
+    content_type: &str,
+    data: &[u8],
+    gzip: bool,
+    raw: bool,
+) -> Result<()> {
+    if !raw {
+        content_type
+            .parse::<ContentType>()
+            .map_err(anyhow::Error::msg)?;
+    }
+    if data.is_empty() {
+        bail!("Clip is empty, nothing written to {}", path.display());
+    }
+    let bytes = if gzip {
+        self::gzip(data)?
+    } else {
+        data.to_vec()
+    };
+    std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Describe the current clip (type, size, sender, age and a text preview)
+/// without writing its content.
+pub async fn peek() -> Result<()> {
+    match super::send_request(Request::Peek).await? {
+        Response::ClipMeta {
+            content_type,
+            size_bytes,
+            sender_device_id,
+            sender_device_name,
+            updated_at,
+            preview,
+            source_app,
+        } => {
+            println!("Type:     {}", content_type);
+            println!("Size:     {} bytes", size_bytes);
+            match sender_device_name {
+                Some(name) => println!("From:     {} ({})", name, sender_device_id),
+                None => println!("From:     {} (no longer registered)", sender_device_id),
+            }
+            println!("Updated:  {}", super::history::format_age(updated_at));
+            if let Some(app) = source_app {
+                println!("App:      {}", app);
+            }
+            if let Some(preview) = preview {
+                println!("Preview:  {}", preview);
+            }
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn run(options: PasteOptions) -> Result<()> {
+    let PasteOptions {
+        type_only,
+        consume,
+        gzip,
+        to_file,
+        wait,
+        after,
+        timeout,
+        only_new,
+        raw,
+        format,
+        quiet,
+    } = options;
+    let request = if wait {
+        Request::PasteWait {
+            after_micros: after.map(|secs| secs.saturating_mul(1_000_000)),
+            timeout_secs: timeout.map(|t| t.as_secs().max(1)),
+        }
+    } else {
+        Request::Paste { consume }
+    };
+    let response = super::send_request(request).await?;
 
     match response {
-        Response::ClipData { content_type, data } => {
+        Response::ClipData {
+            content_type,
+            data,
+            cached,
+        } => {
+            let marker = clip_marker(&content_type, &data);
+            let last_seen = last_seen_path()?;
+            if only_new && read_last_seen(&last_seen).as_deref() == Some(marker.as_str()) {
+                std::process::exit(NO_NEW_CLIP_EXIT_CODE);
+            }
             if type_only {
                 println!("{}", content_type);
                 return Ok(());
             }
-            match content_type.as_str() {
-                "text" => {
-                    std::io::stdout().write_all(&data)?;
-                }
-                "image" => {
-                    if std::io::stdout().is_terminal() {
-                        eprintln!(
-                            "Image data ({} bytes). Pipe to a file: clipsync paste > image.png",
-                            data.len()
-                        );
-                    } else {
-                        std::io::stdout().write_all(&data)?;
-                    }
-                }
-                _ => {
-                    bail!("Unknown content type: {}", content_type);
-                }
+            let written = if let Some(path) = &to_file {
+                write_to_file(path, &content_type, &data, gzip, raw)?;
+                true
+            } else if gzip {
+                write_gzip(&data, quiet)?
+            } else if let Some(encoded) = format.encode(&data) {
+                write_encoded(&content_type, &encoded, raw)?;
+                true
+            } else {
+                write_clip(&content_type, &data, raw, quiet)?
+            };
+
+            if cached && written && !quiet {
+                eprintln!("(cached, offline)");
+            }
+            // The server copy is already gone, so there is no retrying this paste
+            if consume && !written {
+                eprintln!("Clip was consumed from the server but not output");
+            }
+            if only_new && written {
+                config::write_private_file(&last_seen, marker.as_bytes())
+                    .context("Failed to record the last pasted clip")?;
             }
         }
         Response::Error { message } => {
@@ -41,3 +294,68 @@ pub async fn run(type_only: bool) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn write_to_file_rejects_empty_clip() {
+        let path = std::env::temp_dir().join(format!("clipsync-{}.txt", uuid::Uuid::new_v4()));
+        assert!(write_to_file(&path, "text", b"", false, false).is_err());
+        assert!(!path.exists());
+
+        write_to_file(&path, "text", b"hello", false, false).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn write_to_file_needs_raw_for_unknown_type() {
+        let path = std::env::temp_dir().join(format!("clipsync-{}.bin", uuid::Uuid::new_v4()));
+        let err = write_to_file(&path, "files", b"data", false, false).unwrap_err();
+        assert!(err.to_string().contains("Unsupported content type 'files'"));
+        assert!(!path.exists());
+
+        write_to_file(&path, "files", b"data", false, true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn clip_marker_tracks_type_and_data() {
+        let marker = clip_marker("text", b"hello");
+        assert_eq!(marker, clip_marker("text", b"hello"));
+        assert_ne!(marker, clip_marker("text", b"hello!"));
+        assert_ne!(marker, clip_marker("image", b"hello"));
+        assert_eq!(clip_marker("text", b""), "text:cbf29ce484222325");
+    }
+
+    #[test]
+    fn formats_encode_the_bytes() {
+        assert_eq!("raw".parse::<PasteFormat>(), Ok(PasteFormat::Raw));
+        assert!("base32".parse::<PasteFormat>().is_err());
+        assert_eq!(PasteFormat::Raw.encode(b"hi"), None);
+        assert_eq!(
+            PasteFormat::Hex.encode(&[0x00, 0xff, b'a']).as_deref(),
+            Some("00ff61\n")
+        );
+        assert_eq!(
+            PasteFormat::Base64.encode(b"hello").as_deref(),
+            Some("aGVsbG8=\n")
+        );
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let compressed = gzip(b"hello gzip").unwrap();
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"hello gzip");
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{bail, Result};
+use qrcode::render::unicode;
+use qrcode::QrCode;
+
+use crate::payload::ContentType;
+use crate::protocol::{Request, Response};
+
+/// Render text as a QR code using half-block characters, two modules per line.
+fn render(text: &str) -> Result<String> {
+    let code = QrCode::new(text.as_bytes()).map_err(|e| match e {
+        qrcode::types::QrError::DataTooLong => {
+            anyhow::anyhow!("Clip is too large for a QR code ({} bytes)", text.len())
+        }
+        e => anyhow::anyhow!("Failed to encode QR code: {}", e),
+    })?;
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+pub async fn run() -> Result<()> {
+    let response = super::send_request(Request::Paste { consume: false }).await?;
+
+    match response {
+        Response::ClipData {
+            content_type, data, ..
+        } => {
+            if content_type != ContentType::Text.as_str() {
+                bail!(
+                    "Current clip is {}, only text can be shown as a QR code",
+                    content_type
+                );
+            }
+            let text = String::from_utf8_lossy(&data);
+            println!("{}", render(&text)?);
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_small_text() {
+        let rendered = render("https://example.com").unwrap();
+        assert!(rendered.lines().count() > 10);
+    }
+
+    #[test]
+    fn render_rejects_oversized_text() {
+        let text = "x".repeat(8000);
+        assert!(render(&text).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::{bail, Result};
+
+use super::history::format_age;
+use crate::protocol::{RecentClip, Request, Response};
+
+async fn recent_clips() -> Result<Vec<RecentClip>> {
+    match super::send_request(Request::RecentByDevice).await? {
+        Response::RecentByDevice { clips } => Ok(clips),
+        Response::Error { message } => bail!("{}", message),
+        _ => bail!("Unexpected response"),
+    }
+}
+
+/// The clip sent by the device `device`, given by id or (unambiguous) name.
+fn find_clip<'a>(clips: &'a [RecentClip], device: &str) -> Result<&'a RecentClip> {
+    if let Some(clip) = clips.iter().find(|clip| clip.sender_device_id == device) {
+        return Ok(clip);
+    }
+    let mut named = clips.iter().filter(|clip| {
+        clip.sender_device_name
+            .as_deref()
+            .is_some_and(|name| name.eq_ignore_ascii_case(device))
+    });
+    match (named.next(), named.next()) {
+        (Some(clip), None) => Ok(clip),
+        (Some(_), Some(_)) => bail!("Several devices are named {}; pass a device ID", device),
+        (None, _) => bail!("No recent clip from {}", device),
+    }
+}
+
+/// List the last clip each device synced, newest first. The current clip is
+/// marked, so the ones it overwrote stand out.
+pub async fn run() -> Result<()> {
+    let clips = recent_clips().await?;
+    if clips.is_empty() {
+        println!("No recent clips");
+        return Ok(());
+    }
+
+    println!(
+        "  {:<20} {:<6} {:>10} {:<10}",
+        "Device", "Type", "Size", "Sent"
+    );
+    println!("{}", "-".repeat(52));
+    for clip in &clips {
+        let marker = if clip.current { "*" } else { " " };
+        let device = clip
+            .sender_device_name
+            .as_deref()
+            .unwrap_or(&clip.sender_device_id);
+        println!(
+            "{} {:<20} {:<6} {:>10} {:<10}",
+            marker,
+            device,
+            clip.content_type,
+            clip.size_bytes,
+            format_age(clip.updated_at)
+        );
+    }
+    println!();
+    println!("Restore one with: clipsync recent --restore <device>");
+    Ok(())
+}
+
+/// Make `device`'s last clip the current clip again, on every device.
+pub async fn restore(device: String, quiet: bool) -> Result<()> {
+    let clips = recent_clips().await?;
+    let clip = find_clip(&clips, &device)?;
+    if clip.current {
+        if !quiet {
+            eprintln!("That clip is already the current clip");
+        }
+        return Ok(());
+    }
+
+    let response = super::send_request(Request::RestoreDeviceClip {
+        sender_device_id: clip.sender_device_id.clone(),
+    })
+    .await?;
+    match response {
+        Response::Ok => {
+            if !quiet {
+                eprintln!("Restored the last clip from {}", device);
+            }
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(device_id: &str, name: Option<&str>) -> RecentClip {
+        RecentClip {
+            sender_device_id: device_id.to_string(),
+            sender_device_name: name.map(str::to_string),
+            content_type: "text".to_string(),
+            size_bytes: 1,
+            updated_at: 0,
+            current: false,
+        }
+    }
+
+    #[test]
+    fn finds_clip_by_id_or_name() {
+        let clips = vec![
+            clip("a1", Some("desk")),
+            clip("b2", Some("laptop")),
+            clip("c3", None),
+        ];
+        assert_eq!(find_clip(&clips, "b2").unwrap().sender_device_id, "b2");
+        assert_eq!(find_clip(&clips, "Desk").unwrap().sender_device_id, "a1");
+        assert!(find_clip(&clips, "phone").is_err());
+    }
+
+    #[test]
+    fn ambiguous_name_needs_an_id() {
+        let clips = vec![clip("a1", Some("desk")), clip("b2", Some("desk"))];
+        assert!(find_clip(&clips, "desk").is_err());
+        assert_eq!(find_clip(&clips, "b2").unwrap().sender_device_id, "b2");
+    }
+}
@@ -0,0 +1,33 @@
+use anyhow::{bail, Result};
+
+use crate::config::Config;
+use crate::protocol::{Request, Response};
+
+pub async fn run(new_username: String, quiet: bool) -> Result<()> {
+    if new_username.is_empty() {
+        bail!("Username cannot be empty");
+    }
+    let password = super::read_password(&Config::load_or_default())?;
+
+    let response = super::send_request(Request::RenameUser {
+        new_username: new_username.clone(),
+        password,
+    })
+    .await?;
+
+    match response {
+        Response::Ok if quiet => {}
+        Response::Ok => {
+            eprintln!("Renamed to {}", new_username);
+            eprintln!("Use the new name with `clipsync setup` on new devices.");
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use anyhow::{bail, Result};
+
+use crate::config::Config;
+use crate::protocol::{Request, Response};
+
+pub async fn run(quiet: bool) -> Result<()> {
+    eprintln!("This signs out every device on this account, including this one.");
+    let password = super::read_password(&Config::load_or_default())?;
+
+    let response = super::send_request(Request::RevokeSessions { password }).await?;
+
+    match response {
+        Response::Ok if quiet => {}
+        Response::Ok => {
+            eprintln!("All sessions revoked");
+            eprintln!("Run `clipsync setup` on each device (including this one) to sign back in.");
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::{bail, Result};
+
+use crate::protocol::{Request, Response};
+
+/// Push the system clipboard to the server right now, whether or not the
+/// daemon is watching it. All of an account's devices share its key, so the
+/// one encryption reaches every registered device.
+pub async fn to_all(quiet: bool) -> Result<()> {
+    let response = super::send_request(Request::Copy {
+        data: None,
+        append: false,
+        html: false,
+        local_only: false,
+        target: None,
+        allow_empty: false,
+        clear_on_empty: false,
+        target_device: None,
+        // An explicit send always goes out, even if it repeats the last sync
+        dedup_window_ms: 0,
+        ttl_secs: None,
+        max_size: None,
+        apply_min_size: false,
+        wait_confirm: None,
+    })
+    .await?;
+
+    match response {
+        Response::Ok => {}
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+    if quiet {
+        return Ok(());
+    }
+
+    match super::send_request(Request::ListDevices { page: None }).await? {
+        Response::Devices { total, .. } => match total {
+            1 => eprintln!("Clipboard sent, encrypted for 1 device"),
+            n => eprintln!("Clipboard sent, encrypted for {} devices", n),
+        },
+        // The clip is already out; only the count is missing
+        _ => eprintln!("Clipboard sent"),
+    }
+
+    Ok(())
+}
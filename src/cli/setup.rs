@@ -7,135 +7,137 @@ use crate::config::{self, Config};
 use crate::crypto;
 use crate::module_bindings::*;
 
-pub async fn run(username: String, invite_code: Option<String>) -> Result<()> {
-    let password = rpassword::prompt_password("Password: ")?;
-    if password.is_empty() {
-        bail!("Password cannot be empty");
-    }
+/// Password prompts allowed before setup gives up.
+const MAX_PASSWORD_ATTEMPTS: u32 = 3;
 
-    // Generate a local keypair (used if this is a new account)
-    let (local_identity, local_recipient) = crypto::generate_keypair();
-    let public_key = crypto::public_key_bytes(&local_recipient);
+/// How the server refuses a wrong password. A bad invite code gets the same
+/// answer, so signups can't be told apart from logins.
+const AUTH_REFUSED: &str = "Authentication failed";
 
-    // Encrypt local private key with password (stored on server for new accounts)
-    use age::secrecy::ExposeSecret;
-    let private_key_str = local_identity.to_string().expose_secret().to_string();
-    let encrypted_private_key =
-        crypto::encrypt_with_passphrase(private_key_str.as_bytes(), &password)?;
-
-    // Generate device ID if needed
-    let device_id = match config::load_device_id()? {
-        Some(id) => id,
-        None => {
-            let id = uuid::Uuid::new_v4().to_string();
-            config::save_device_id(&id)?;
-            id
-        }
-    };
-    let device_name = gethostname::gethostname().to_string_lossy().to_string();
-
-    // Ensure config exists
-    let config = Config::load().unwrap_or_default();
-    config.save()?;
+/// Arguments for the `authenticate` reducer.
+pub(super) struct AuthRequest {
+    pub username: String,
+    pub password: String,
+    pub encrypted_private_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub device_id: String,
+    pub device_name: String,
+    pub invite_code: String,
+}
 
-    println!("Connecting to SpacetimeDB...");
+/// What the server returned for a successful `authenticate`.
+pub(super) struct AuthOutcome {
+    pub token: String,
+    pub user_id: u64,
+    /// The account's private key as stored on the server (passphrase-encrypted).
+    /// For new accounts this is the key we just uploaded.
+    pub encrypted_private_key: Vec<u8>,
+    /// Hex identity the server reported for itself, if it exposes one.
+    pub server_identity: Option<String>,
+}
 
+/// Connect to a server, call `authenticate`, and wait for the resulting profile.
+/// If `server_fingerprint` is pinned, the server's identity is checked before
+/// the password is sent. When the server refuses the password, `retry` is
+/// asked for another request to send on the same connection; None gives up.
+pub(super) fn authenticate(
+    server_url: String,
+    database_name: String,
+    server_fingerprint: Option<String>,
+    existing_token: Option<String>,
+    request: AuthRequest,
+    mut retry: impl FnMut() -> Result<Option<AuthRequest>>,
+) -> Result<AuthOutcome> {
     // result: Ok((user_id, encrypted_private_key_from_server))
     let (result_tx, result_rx) = std::sync::mpsc::channel::<Result<(u64, Vec<u8>), String>>();
+    let (server_identity_tx, server_identity_rx) = std::sync::mpsc::channel::<Option<String>>();
     let (token_tx, token_rx) = std::sync::mpsc::channel::<String>();
-
-    let server_url = config.server_url.clone();
-    let database_name = config.database_name.clone();
-    let existing_token = config::load_token()?;
-
-    let un = username.clone();
-    let pw = password.clone();
-    let epk = encrypted_private_key.clone();
-    let pk = public_key.clone();
-    let did = device_id.clone();
-    let dn = device_name.clone();
-    let ic = invite_code.unwrap_or_default();
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel::<()>();
+    let (request_tx, request_rx) = std::sync::mpsc::channel::<AuthRequest>();
+    request_tx
+        .send(request)
+        .map_err(|_| anyhow::anyhow!("Failed to queue authentication"))?;
 
     std::thread::Builder::new()
         .name("setup-stdb".to_string())
         .spawn(move || {
             let result_tx_sub = result_tx.clone();
             let result_tx_build = result_tx.clone();
+            let result_tx_call = result_tx.clone();
             let token_tx_connect = token_tx.clone();
 
-            let un2 = un.clone();
-            let pw2 = pw.clone();
-            let epk2 = epk.clone();
-            let pk2 = pk.clone();
-            let did2 = did.clone();
-            let dn2 = dn.clone();
-            let ic2 = ic.clone();
-
             let conn = DbConnection::builder()
                 .with_uri(&server_url)
                 .with_database_name(&database_name)
                 .with_token(existing_token)
-                .on_connect(move |conn: &DbConnection, _identity: Identity, token: &str| {
-                    let _ = token_tx_connect.send(token.to_string());
-
-                    let rtx = result_tx_sub.clone();
-
-                    conn.subscription_builder()
-                        .on_applied(move |ctx: &SubscriptionEventContext| {
-                            // Call authenticate reducer
-                            if let Err(e) = ctx.reducers.authenticate(
-                                un2,
-                                pw2,
-                                epk2,
-                                pk2,
-                                did2,
-                                dn2,
-                                ic2,
-                            ) {
-                                let _ = rtx.send(Err(format!("Failed to call authenticate: {}", e)));
-                                return;
-                            }
-
-                            // Watch for my_profile insert to get user info
-                            // (the user table is private; my_profile view exposes it securely)
-                            let rtx2 = rtx.clone();
-                            ctx.db.my_profile().on_insert(
-                                move |_ctx2: &EventContext, profile: &UserProfile| {
-                                    let _ = rtx2.send(Ok((
-                                        profile.user_id,
-                                        profile.encrypted_private_key.clone(),
-                                    )));
-                                },
-                            );
-
-                            // Also check if profile already exists (login case where
-                            // user_identity row already exists and view is already populated)
-                            if let Some(profile) = ctx.db.my_profile().iter().next() {
-                                let _ = rtx.send(Ok((
-                                    profile.user_id,
-                                    profile.encrypted_private_key.clone(),
-                                )));
-                            }
-                        })
-                        .subscribe_to_all_tables();
-                })
-                .on_disconnect(move |_ctx: &ErrorContext, err: Option<spacetimedb_sdk::Error>| {
-                    if let Some(e) = err {
-                        let _ = result_tx.send(Err(format!("Disconnected: {:?}", e)));
-                    }
-                })
+                .on_connect(
+                    move |conn: &DbConnection, _identity: Identity, token: &str| {
+                        let _ = token_tx_connect.send(token.to_string());
+
+                        let rtx = result_tx_sub.clone();
+
+                        conn.subscription_builder()
+                            .on_applied(move |ctx: &SubscriptionEventContext| {
+                                let reported = ctx
+                                    .db
+                                    .server_identity()
+                                    .iter()
+                                    .next()
+                                    .map(|info| info.module_identity.to_hex().to_string());
+                                if let Err(e) = config::check_server_fingerprint(
+                                    server_fingerprint.as_deref(),
+                                    reported.as_deref(),
+                                ) {
+                                    let _ = rtx.send(Err(e));
+                                    return;
+                                }
+                                let _ = server_identity_tx.send(reported);
+                                let _ = ready_tx.send(());
+                            })
+                            .subscribe_to_all_tables();
+                    },
+                )
+                .on_disconnect(
+                    move |_ctx: &ErrorContext, err: Option<spacetimedb_sdk::Error>| {
+                        if let Some(e) = err {
+                            let _ = result_tx.send(Err(format!("Disconnected: {:?}", e)));
+                        }
+                    },
+                )
                 .build();
 
             let conn = match conn {
                 Ok(conn) => Arc::new(conn),
                 Err(e) => {
-                    let _ = result_tx_build.send(Err(format!("Failed to connect to SpacetimeDB: {}", e)));
+                    let _ = result_tx_build
+                        .send(Err(format!("Failed to connect to SpacetimeDB: {}", e)));
                     return;
                 }
             };
             let _handle = conn.run_threaded();
 
-            std::thread::sleep(Duration::from_secs(60));
+            // Nothing is sent until the server's identity has been checked
+            if ready_rx.recv_timeout(Duration::from_secs(60)).is_err() {
+                return;
+            }
+            // Runs until `authenticate` returns and drops the request sender
+            for request in request_rx {
+                let rtx = result_tx_call.clone();
+                if let Err(e) = conn.reducers.authenticate_then(
+                    request.username,
+                    request.password,
+                    request.encrypted_private_key,
+                    request.public_key,
+                    request.device_id,
+                    request.device_name,
+                    request.invite_code,
+                    move |ctx: &ReducerEventContext, outcome| {
+                        let _ = rtx.send(auth_outcome(ctx, outcome));
+                    },
+                ) {
+                    let _ = result_tx_call.send(Err(format!("Failed to call authenticate: {}", e)));
+                }
+            }
         })?;
 
     // Wait for token
@@ -143,50 +145,240 @@ pub async fn run(username: String, invite_code: Option<String>) -> Result<()> {
         .recv_timeout(Duration::from_secs(30))
         .context("Timed out waiting for SpacetimeDB connection")?;
 
-    // Wait for auth result
-    let result = result_rx
-        .recv_timeout(Duration::from_secs(30))
-        .context("Timed out waiting for authentication result")?;
+    loop {
+        let result = result_rx
+            .recv_timeout(Duration::from_secs(30))
+            .context("Timed out waiting for authentication result")?;
 
-    match result {
-        Ok((user_id, server_encrypted_pk)) => {
-            // Decrypt the private key from the server with our password.
-            // For new accounts, this is the key we just uploaded.
-            // For existing accounts, this is the original key.
-            let private_key_bytes =
-                crypto::decrypt_with_passphrase(&server_encrypted_pk, &password)
-                    .context("Failed to decrypt private key (wrong password?)")?;
+        match result {
+            Ok((user_id, encrypted_private_key)) => {
+                return Ok(AuthOutcome {
+                    token,
+                    user_id,
+                    encrypted_private_key,
+                    server_identity: server_identity_rx.try_recv().ok().flatten(),
+                })
+            }
+            Err(e) if e == AUTH_REFUSED => match retry()? {
+                Some(request) => request_tx
+                    .send(request)
+                    .map_err(|_| anyhow::anyhow!("Lost the connection to SpacetimeDB"))?,
+                None => bail!("Authentication failed: {}", e),
+            },
+            Err(e) => bail!("Authentication failed: {}", e),
+        }
+    }
+}
 
-            let private_key_str =
-                std::str::from_utf8(&private_key_bytes).context("Invalid private key")?;
+/// What an `authenticate` call came to: the user id and stored key of the
+/// profile it logged into, or why it was refused. A wrong password or invite
+/// code is reported through `my_auth_result` (the server commits the failed
+/// attempt), anything else as the reducer's own error.
+fn auth_outcome<E: std::fmt::Display>(
+    ctx: &ReducerEventContext,
+    outcome: Result<Result<(), String>, E>,
+) -> Result<(u64, Vec<u8>), String> {
+    outcome.map_err(|e| e.to_string())??;
+    if let Some(error) = ctx.db.my_auth_result().iter().next().and_then(|r| r.error) {
+        return Err(error);
+    }
+    // The user table is private; the my_profile view exposes our own row
+    ctx.db
+        .my_profile()
+        .iter()
+        .next()
+        .map(|profile| (profile.user_id, profile.encrypted_private_key))
+        .ok_or_else(|| "Server returned no profile".to_string())
+}
 
-            let age_identity: age::x25519::Identity = private_key_str
-                .trim()
-                .parse()
-                .map_err(|e| anyhow::anyhow!("Failed to parse private key: {}", e))?;
+/// Decrypt a passphrase-encrypted private key as returned by the server.
+pub(super) fn decrypt_server_key(
+    encrypted_private_key: &[u8],
+    password: &str,
+) -> Result<age::x25519::Identity> {
+    let private_key_bytes = crypto::decrypt_with_passphrase(encrypted_private_key, password)
+        .context("Failed to decrypt private key (wrong password?)")?;
 
-            // Save everything locally
-            config::save_user_id(user_id)?;
-            config::save_token(&token)?;
-            crypto::store_private_key(&age_identity)?;
+    let private_key_str = std::str::from_utf8(&private_key_bytes).context("Invalid private key")?;
 
-            let recipient = age_identity.to_public();
+    private_key_str
+        .trim()
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Failed to parse private key: {}", e))
+}
 
+/// Load this device's ID, generating and saving one on first use.
+pub(super) fn load_or_create_device_id() -> Result<String> {
+    match config::load_device_id()? {
+        Some(id) => Ok(id),
+        None => {
+            let id = uuid::Uuid::new_v4().to_string();
+            config::save_device_id(&id)?;
+            Ok(id)
+        }
+    }
+}
+
+/// What setup ended up with, printed once it completes.
+struct SetupResult {
+    username: String,
+    user_id: u64,
+    device_id: String,
+    device_name: String,
+    public_key: String,
+    server_identity: Option<String>,
+    /// Password-encrypted private key, only when `--print-recovery` was given
+    recovery: Option<String>,
+}
+
+impl SetupResult {
+    fn print(&self) {
+        println!();
+        println!("Setup complete!");
+        println!("  Username:    {}", self.username);
+        println!("  User ID:     {}", self.user_id);
+        println!("  Device ID:   {}", self.device_id);
+        println!("  Device Name: {}", self.device_name);
+        println!("  Public Key:  {}", self.public_key);
+        if let Some(server_identity) = &self.server_identity {
+            println!("  Server ID:   {}", server_identity);
+        }
+        if let Some(recovery) = &self.recovery {
+            println!();
+            println!("Recovery string (your private key, encrypted with your password):");
             println!();
-            println!("Setup complete!");
-            println!("  Username:    {}", username);
-            println!("  User ID:     {}", user_id);
-            println!("  Device ID:   {}", device_id);
-            println!("  Device Name: {}", device_name);
-            println!("  Public Key:  {}", recipient);
+            println!("{}", recovery);
             println!();
-            println!("Start the daemon with: clipsync daemon");
-            println!("Or install as a service: clipsync install");
+            println!("Store it somewhere safe, such as a password manager. Anyone holding it can");
+            println!(
+                "try to guess your password offline, so don't paste it into chats, tickets or"
+            );
+            println!("shell history. Restore it with: clipsync import-key");
         }
-        Err(e) => {
-            bail!("Authentication failed: {}", e);
+        println!();
+        println!("Start the daemon with: clipsync daemon");
+        println!("Or install as a service: clipsync install");
+    }
+}
+
+pub async fn run(
+    username: String,
+    invite_code: Option<String>,
+    print_recovery: bool,
+) -> Result<()> {
+    // Held until setup returns, so concurrent runs can't interleave writes to
+    // the token, user_id and identity files.
+    let _lock = config::acquire_lock("setup.lock")
+        .map_err(|_| anyhow::anyhow!("Setup already in progress"))?;
+
+    // Ensure config exists
+    let config = Config::load_or_default();
+    config.save()?;
+
+    let mut password = super::read_password(&config)?;
+    if password.is_empty() {
+        bail!("Password cannot be empty");
+    }
+
+    // Generate a local keypair (used if this is a new account)
+    let (local_identity, local_recipient) = crypto::generate_keypair();
+    let public_key = crypto::public_key_bytes(&local_recipient);
+    use age::secrecy::ExposeSecret;
+    let private_key_str = local_identity.to_string().expose_secret().to_string();
+
+    // Generate device ID if needed
+    let device_id = load_or_create_device_id()?;
+    let device_name = gethostname::gethostname().to_string_lossy().to_string();
+    let invite_code = invite_code.unwrap_or_default();
+
+    // The local private key goes up encrypted with the password (stored on the
+    // server for new accounts), so every attempt encrypts it afresh
+    let auth_request = |password: &str| -> Result<AuthRequest> {
+        Ok(AuthRequest {
+            username: username.clone(),
+            password: password.to_string(),
+            encrypted_private_key: crypto::encrypt_with_passphrase(
+                private_key_str.as_bytes(),
+                password,
+            )?,
+            public_key: public_key.clone(),
+            device_id: device_id.clone(),
+            device_name: device_name.clone(),
+            invite_code: invite_code.clone(),
+        })
+    };
+
+    println!("Connecting to SpacetimeDB...");
+
+    // The connection is already up, so a mistyped password is re-prompted
+    // instead of aborting setup
+    let mut attempts = 1;
+    let outcome = authenticate(
+        config.server_url.clone(),
+        config.database_name.clone(),
+        config.server_fingerprint.clone(),
+        config::load_token()?,
+        auth_request(&password)?,
+        || {
+            // Re-running the command would just return the same password
+            if config.password_command.is_some() {
+                bail!("Incorrect password from password_command");
+            }
+            if attempts >= MAX_PASSWORD_ATTEMPTS {
+                bail!("Incorrect password");
+            }
+            attempts += 1;
+            eprintln!("Incorrect password, try again.");
+            password = rpassword::prompt_password("Password: ")?;
+            auth_request(&password).map(Some)
+        },
+    )?;
+
+    // Decrypt the private key from the server with our password.
+    // For new accounts, this is the key we just uploaded.
+    // For existing accounts, this is the original key, which may still be
+    // encrypted with an older password than the server checks.
+    let age_identity = loop {
+        match decrypt_server_key(&outcome.encrypted_private_key, &password) {
+            Ok(identity) => break identity,
+            Err(e) if crypto::is_wrong_passphrase(&e) => {
+                // Re-running the command would just return the same password
+                if config.password_command.is_some() {
+                    bail!("Incorrect password from password_command");
+                }
+                if attempts >= MAX_PASSWORD_ATTEMPTS {
+                    bail!("Incorrect password");
+                }
+                attempts += 1;
+                eprintln!("Incorrect password, try again.");
+                password = rpassword::prompt_password("Password: ")?;
+            }
+            Err(e) => return Err(e),
         }
+    };
+
+    // Save everything locally. The key goes first: with secure_token_storage
+    // the token is encrypted to it.
+    crypto::store_private_key(&age_identity)?;
+    config::save_user_id(outcome.user_id)?;
+    config::save_token(&outcome.token)?;
+
+    let recovery = if print_recovery {
+        Some(crypto::encode_recovery(&age_identity, &password)?)
+    } else {
+        None
+    };
+
+    SetupResult {
+        username,
+        user_id: outcome.user_id,
+        device_id,
+        device_name,
+        public_key: age_identity.to_public().to_string(),
+        server_identity: outcome.server_identity,
+        recovery,
     }
+    .print();
 
     Ok(())
 }
@@ -1,9 +1,77 @@
 use anyhow::{bail, Result};
 
-use crate::protocol::{Request, Response};
+use crate::protocol::{Request, Response, ThreadHealth, CLOCK_SKEW_WARN_SECS};
 
-pub async fn run() -> Result<()> {
-    let response = super::send_request(Request::Status).await?;
+/// Describe a clock skew worth reporting, e.g. "3m ahead of the server".
+fn describe_clock_skew(skew_secs: i64) -> Option<String> {
+    if skew_secs.abs() < CLOCK_SKEW_WARN_SECS {
+        return None;
+    }
+    let secs = skew_secs.unsigned_abs();
+    let amount = match secs {
+        0..=119 => format!("{}s", secs),
+        120..=7199 => format!("{}m", secs / 60),
+        _ => format!("{}h", secs / 3600),
+    };
+    let direction = if skew_secs > 0 { "ahead of" } else { "behind" };
+    Some(format!("{} {} the server", amount, direction))
+}
+
+/// Format an uptime as e.g. "2d 3h", "3h 12m" or "45s".
+fn format_uptime(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// Describe one worker thread, e.g. "clipboard ok (0s ago)".
+fn describe_thread(thread: &ThreadHealth) -> String {
+    let state = if thread.healthy { "ok" } else { "STALLED" };
+    let restarts = match thread.restarts {
+        0 => String::new(),
+        1 => ", restarted once".to_string(),
+        n => format!(", restarted {} times", n),
+    };
+    match thread.last_tick_secs {
+        Some(secs) => format!("{} {} ({}s ago{})", thread.name, state, secs, restarts),
+        None => format!("{} {} (never ran{})", thread.name, state, restarts),
+    }
+}
+
+/// Format a byte count, e.g. "512 B", "3.4 KB" or "1.2 MB".
+fn format_size(bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    let size = bytes as f64;
+    if size < KB {
+        format!("{} B", bytes)
+    } else if size < KB * KB {
+        format!("{:.1} KB", size / KB)
+    } else {
+        format!("{:.1} MB", size / (KB * KB))
+    }
+}
+
+pub async fn run(socket_only: bool, json: bool, local_size: bool) -> Result<()> {
+    let response = super::send_request(Request::Status {
+        socket_only,
+        local_size,
+    })
+    .await?;
+
+    if json {
+        if let Response::Error { message } = &response {
+            bail!("{}", message);
+        }
+        let value = serde_json::to_value(&response)?;
+        let Some(status) = value.get("Status") else {
+            bail!("Unexpected response");
+        };
+        println!("{}", serde_json::to_string_pretty(status)?);
+        return Ok(());
+    }
 
     match response {
         Response::Status {
@@ -12,6 +80,18 @@ pub async fn run() -> Result<()> {
             user_id,
             device_id,
             watching,
+            clipboard_conflict,
+            frozen,
+            frozen_remaining_secs,
+            last_clip_intended_device,
+            last_clip_source_app,
+            key_matches_server,
+            failed_login_attempts,
+            clock_skew_secs,
+            last_apply_error,
+            uptime_secs,
+            threads,
+            local_clipboard,
         } => {
             println!("Connected: {}", connected);
             if let Some(name) = username {
@@ -22,6 +102,55 @@ pub async fn run() -> Result<()> {
             }
             println!("Device ID: {}", device_id);
             println!("Watching:  {}", watching);
+            match frozen_remaining_secs {
+                Some(secs) => println!("Frozen:    {} ({}s left)", frozen, secs),
+                None => println!("Frozen:    {}", frozen),
+            }
+            if let Some(name) = last_clip_intended_device {
+                let this_device = gethostname::gethostname().to_string_lossy().to_string();
+                if name.eq_ignore_ascii_case(&this_device) {
+                    println!("Last clip: meant for this device");
+                } else {
+                    println!("Last clip: meant for {}", name);
+                }
+            }
+            if let Some(app) = last_clip_source_app {
+                println!("Last clip: copied from {}", app);
+            }
+            if key_matches_server == Some(false) {
+                println!("Key:       does not match the server's, run `clipsync setup` again");
+            }
+            match failed_login_attempts {
+                Some(1) => println!("Security:  1 failed login attempt recently"),
+                Some(n) if n > 1 => println!("Security:  {} failed login attempts recently", n),
+                _ => {}
+            }
+            if let Some(skew) = clock_skew_secs.and_then(describe_clock_skew) {
+                println!("Clock:     {}, check this machine's time sync", skew);
+            }
+            if clipboard_conflict {
+                println!("Conflict:  possible clipboard conflict detected, local sync paused");
+            }
+            if let Some(error) = last_apply_error {
+                println!("Incoming:  last clip failed to apply: {}", error);
+            }
+            if let Some(secs) = uptime_secs {
+                println!("Uptime:    {}", format_uptime(secs));
+            }
+            if !threads.is_empty() {
+                let described: Vec<String> = threads.iter().map(describe_thread).collect();
+                println!("Threads:   {}", described.join(", "));
+            }
+            if local_size {
+                match local_clipboard {
+                    Some(clip) => println!(
+                        "Clipboard: {} ({})",
+                        format_size(clip.size_bytes),
+                        clip.content_type
+                    ),
+                    None => println!("Clipboard: empty or unreadable"),
+                }
+            }
         }
         Response::Error { message } => {
             bail!("{}", message);
@@ -33,3 +162,66 @@ pub async fn run() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_skew_reported_past_threshold() {
+        assert_eq!(describe_clock_skew(0), None);
+        assert_eq!(describe_clock_skew(CLOCK_SKEW_WARN_SECS - 1), None);
+        assert_eq!(
+            describe_clock_skew(CLOCK_SKEW_WARN_SECS).as_deref(),
+            Some("60s ahead of the server")
+        );
+        assert_eq!(
+            describe_clock_skew(-180).as_deref(),
+            Some("3m behind the server")
+        );
+        assert_eq!(
+            describe_clock_skew(2 * 3600).as_deref(),
+            Some("2h ahead of the server")
+        );
+    }
+
+    #[test]
+    fn uptime_uses_two_largest_units() {
+        assert_eq!(format_uptime(45), "45s");
+        assert_eq!(format_uptime(125), "2m 5s");
+        assert_eq!(format_uptime(3 * 3600 + 12 * 60), "3h 12m");
+        assert_eq!(format_uptime(2 * 86400 + 3 * 3600), "2d 3h");
+    }
+
+    #[test]
+    fn sizes_use_the_largest_unit() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(3482), "3.4 KB");
+        assert_eq!(format_size(1_258_291), "1.2 MB");
+    }
+
+    #[test]
+    fn stalled_thread_is_flagged() {
+        let thread = ThreadHealth {
+            name: "clipboard".to_string(),
+            last_tick_secs: Some(120),
+            healthy: false,
+            restarts: 0,
+        };
+        assert_eq!(describe_thread(&thread), "clipboard STALLED (120s ago)");
+    }
+
+    #[test]
+    fn restarted_thread_says_so() {
+        let thread = ThreadHealth {
+            name: "clipboard".to_string(),
+            last_tick_secs: Some(0),
+            healthy: true,
+            restarts: 2,
+        };
+        assert_eq!(
+            describe_thread(&thread),
+            "clipboard ok (0s ago, restarted 2 times)"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{bail, Result};
+
+use crate::protocol::{Request, Response};
+
+async fn send_expecting_ok(request: Request, success: &str, quiet: bool) -> Result<()> {
+    let response = super::send_request(request).await?;
+
+    match response {
+        Response::Ok => {
+            if !quiet {
+                eprintln!("{}", success);
+            }
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn create(name: String, quiet: bool) -> Result<()> {
+    let success = format!("Team {} created", name);
+    send_expecting_ok(Request::CreateTeam { name }, &success, quiet).await
+}
+
+pub async fn add(team: String, username: String, quiet: bool) -> Result<()> {
+    let success = format!("Added {} to {}", username, team);
+    send_expecting_ok(Request::AddTeamMember { team, username }, &success, quiet).await
+}
+
+pub async fn remove(team: String, username: String, quiet: bool) -> Result<()> {
+    let success = format!("Removed {} from {}", username, team);
+    send_expecting_ok(
+        Request::RemoveTeamMember { team, username },
+        &success,
+        quiet,
+    )
+    .await
+}
+
+pub async fn list() -> Result<()> {
+    let response = super::send_request(Request::ListTeams).await?;
+
+    match response {
+        Response::Teams { teams } => {
+            if teams.is_empty() {
+                println!("Not a member of any team");
+            } else {
+                println!("{:<20} {:<20} {}", "Team", "Owner", "Members");
+                println!("{}", "-".repeat(64));
+                for t in teams {
+                    println!("{:<20} {:<20} {}", t.name, t.owner, t.members.join(", "));
+                }
+            }
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn copy(team: String, quiet: bool) -> Result<()> {
+    let data = super::copy::read_stdin()?;
+    let success = format!("Clipboard synced to team {}", team);
+    send_expecting_ok(Request::TeamCopy { team, data }, &success, quiet).await
+}
+
+pub async fn paste(team: String, quiet: bool) -> Result<()> {
+    let response = super::send_request(Request::TeamPaste { team }).await?;
+
+    match response {
+        Response::ClipData {
+            content_type, data, ..
+        } => {
+            super::paste::write_clip(&content_type, &data, false, quiet)?;
+        }
+        Response::Error { message } => {
+            bail!("{}", message);
+        }
+        _ => {
+            bail!("Unexpected response");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use anyhow::{bail, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use super::history::{format_age, load_local};
+use crate::history::HistoryEntry;
+use crate::payload::ClipboardPayload;
+use crate::protocol::{Request, Response};
+
+const HELP: &str = "↑/↓ move  Enter restore  d delete  / search  Esc clear  q quit";
+
+struct App {
+    /// Loaded entries with their history indexes, newest first
+    entries: Vec<(usize, HistoryEntry)>,
+    /// Case-insensitive text filter; image entries never match a non-empty one
+    query: String,
+    searching: bool,
+    list: ListState,
+    status: String,
+}
+
+impl App {
+    fn new(entries: Vec<(usize, HistoryEntry)>) -> Self {
+        let mut list = ListState::default();
+        if !entries.is_empty() {
+            list.select(Some(0));
+        }
+        Self {
+            entries,
+            query: String::new(),
+            searching: false,
+            list,
+            status: HELP.to_string(),
+        }
+    }
+
+    /// Indexes into `entries` (newest first) matching the current query.
+    fn visible(&self) -> Vec<usize> {
+        let query = self.query.to_lowercase();
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, entry))| match &entry.payload {
+                ClipboardPayload::Text(text) => text.to_lowercase().contains(&query),
+                ClipboardPayload::Image { .. } => query.is_empty(),
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// The highlighted entry and its history index.
+    fn selected(&self) -> Option<&(usize, HistoryEntry)> {
+        self.list
+            .selected()
+            .and_then(|i| self.visible().get(i).copied())
+            .map(|i| &self.entries[i])
+    }
+
+    /// Keep the highlight on a visible row after the list changes.
+    fn clamp_selection(&mut self) {
+        let len = self.visible().len();
+        let selected = match self.list.selected() {
+            _ if len == 0 => None,
+            Some(i) => Some(i.min(len - 1)),
+            None => Some(0),
+        };
+        self.list.select(selected);
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        self.entries = load_local()?;
+        self.clamp_selection();
+        Ok(())
+    }
+
+    async fn restore(&mut self) -> Result<()> {
+        let Some(&(index, ref entry)) = self.selected() else {
+            return Ok(());
+        };
+        let payload = entry.payload.clone();
+        self.status = match super::send_request(Request::Restore { payload }).await? {
+            Response::Ok => format!("Restored history entry {}", index),
+            Response::Error { message } => message,
+            _ => bail!("Unexpected response"),
+        };
+        Ok(())
+    }
+
+    async fn delete(&mut self) -> Result<()> {
+        let Some(&(index, ref entry)) = self.selected() else {
+            return Ok(());
+        };
+        let timestamp = entry.timestamp;
+        self.status = match super::send_request(Request::DeleteHistory { index, timestamp }).await?
+        {
+            Response::Ok => format!("Deleted history entry {}", index),
+            Response::Error { message } => message,
+            _ => bail!("Unexpected response"),
+        };
+        self.reload()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .visible()
+            .into_iter()
+            .map(|i| {
+                let (index, entry) = &self.entries[i];
+                ListItem::new(format!(
+                    "{:<4} {:<8} {}",
+                    index,
+                    format_age(entry.timestamp),
+                    entry.payload.preview(list_area.width as usize)
+                ))
+            })
+            .collect();
+        let title = if self.query.is_empty() {
+            "History".to_string()
+        } else {
+            format!("History matching \"{}\"", self.query)
+        };
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        let preview = match self.selected().map(|(_, entry)| &entry.payload) {
+            Some(ClipboardPayload::Text(text)) => text.clone(),
+            Some(image) => image.summary(),
+            None => String::new(),
+        };
+        frame.render_widget(
+            Paragraph::new(preview)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title("Preview")),
+            preview_area,
+        );
+
+        let footer_text = if self.searching {
+            format!("/{}", self.query)
+        } else {
+            self.status.clone()
+        };
+        frame.render_widget(Line::from(footer_text).dim(), footer);
+    }
+}
+
+/// Browse the local history: restore entries to the clipboard or delete them.
+/// Restores and deletes go through the daemon, like `clipsync restore`.
+pub async fn run() -> Result<()> {
+    let entries = load_local()?;
+    if entries.is_empty() {
+        println!("Local history is empty");
+        println!("Enable it with: clipsync config local_history true");
+        return Ok(());
+    }
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, App::new(entries)).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(terminal: &mut DefaultTerminal, mut app: App) -> Result<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if app.searching {
+            match key.code {
+                KeyCode::Enter => app.searching = false,
+                KeyCode::Esc => {
+                    app.searching = false;
+                    app.query.clear();
+                }
+                KeyCode::Backspace => {
+                    app.query.pop();
+                }
+                KeyCode::Char(c) => app.query.push(c),
+                _ => {}
+            }
+            app.list.select(Some(0));
+            app.clamp_selection();
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => app.list.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => app.list.select_previous(),
+            KeyCode::Enter => app.restore().await?,
+            KeyCode::Char('d') | KeyCode::Delete => app.delete().await?,
+            KeyCode::Char('/') => app.searching = true,
+            KeyCode::Esc => {
+                app.query.clear();
+                app.clamp_selection();
+            }
+            _ => {}
+        }
+        app.clamp_selection();
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{bail, Result};
+use std::time::{Duration, Instant};
+
+use crate::config::{ClipboardSource, Config};
+
+/// Clipboard reads averaged for the measurement.
+const READ_SAMPLES: u32 = 20;
+
+/// Bounds on the suggested interval: below the minimum copies are picked up
+/// no faster in practice, above the maximum syncing starts to feel laggy.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(5000);
+
+/// Suggestions are rounded up to a multiple of this, so they read as chosen.
+const ROUND_TO_MS: u64 = 50;
+
+/// The poll interval at which reads costing `read_cost` each take at most
+/// `cpu_percent` of one core, clamped and rounded up to `ROUND_TO_MS`.
+fn suggest_poll_interval(read_cost: Duration, cpu_percent: f64) -> Duration {
+    let interval = read_cost.as_secs_f64() * 100.0 / cpu_percent;
+    let ms = (interval * 1000.0).ceil() as u64;
+    let ms = ms.div_ceil(ROUND_TO_MS) * ROUND_TO_MS;
+    Duration::from_millis(ms).clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+}
+
+/// Mean cost of one daemon poll: a text read, then an image read when
+/// there's no text, the same order the clipboard thread uses.
+fn measure_read_cost() -> Result<Duration> {
+    let mut clipboard = arboard::Clipboard::new()
+        .map_err(|e| anyhow::anyhow!("Failed to open the clipboard: {}", e))?;
+    let start = Instant::now();
+    for _ in 0..READ_SAMPLES {
+        let has_text = clipboard.get_text().is_ok_and(|text| !text.is_empty());
+        if !has_text {
+            let _ = clipboard.get_image();
+        }
+    }
+    Ok(start.elapsed() / READ_SAMPLES)
+}
+
+/// Measure how long a clipboard read takes here and suggest the fastest
+/// `poll_interval_ms` that keeps polling under `cpu_percent` of one core,
+/// saving it if the user agrees.
+pub fn run(cpu_percent: f64) -> Result<()> {
+    if !(cpu_percent > 0.0 && cpu_percent <= 100.0) {
+        bail!("--cpu-percent must be above 0 and at most 100");
+    }
+    let mut config = Config::load_or_default();
+    if config.clipboard_source != ClipboardSource::System {
+        bail!(
+            "clipsync tune only measures the system clipboard (clipboard_source is not \"system\")"
+        );
+    }
+
+    println!("Timing {} clipboard reads...", READ_SAMPLES);
+    let read_cost = measure_read_cost()?;
+    let suggested = suggest_poll_interval(read_cost, cpu_percent);
+    let suggested_ms = suggested.as_millis() as u64;
+
+    println!("Clipboard read: {:.2} ms", read_cost.as_secs_f64() * 1000.0);
+    println!(
+        "Suggested poll_interval_ms: {} (about {:.2}% of one core; currently {})",
+        suggested_ms,
+        read_cost.as_secs_f64() / suggested.as_secs_f64() * 100.0,
+        config.poll_interval_ms
+    );
+    if suggested_ms == config.poll_interval_ms {
+        println!("Already set; nothing to change.");
+        return Ok(());
+    }
+    if !super::confirm("Save it to the config?")? {
+        println!("Config left unchanged.");
+        return Ok(());
+    }
+
+    config.poll_interval_ms = suggested_ms;
+    // Keep the backoff ceiling at or above the new base interval
+    config.poll_interval_max_ms = config.poll_interval_max_ms.max(suggested_ms);
+    config.save()?;
+    println!("Set poll_interval_ms = {}", suggested_ms);
+    println!("Restart the daemon for changes to take effect.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_keeps_reads_under_target() {
+        // 2ms reads at 1% of a core need at least 200ms between them
+        assert_eq!(
+            suggest_poll_interval(Duration::from_millis(2), 1.0),
+            Duration::from_millis(200)
+        );
+        assert_eq!(
+            suggest_poll_interval(Duration::from_micros(2300), 1.0),
+            Duration::from_millis(250)
+        );
+    }
+
+    #[test]
+    fn interval_is_clamped() {
+        assert_eq!(
+            suggest_poll_interval(Duration::from_micros(10), 1.0),
+            MIN_POLL_INTERVAL
+        );
+        assert_eq!(
+            suggest_poll_interval(Duration::from_millis(200), 1.0),
+            MAX_POLL_INTERVAL
+        );
+    }
+}
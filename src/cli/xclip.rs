@@ -1,7 +1,46 @@
-use anyhow::{bail, Result};
-use std::io::Write;
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
 
-use crate::protocol::{Request, Response};
+use crate::daemon::clipboard::ClipboardTarget;
+use crate::payload::{self, ClipboardPayload, ContentType};
+use crate::protocol::{check_frame_size, Request, Response};
+
+/// Turn bytes written to the shim into a clip. A `-t` target declares the
+/// type, as with real xclip; without one, PNG bytes are an image and
+/// anything else is text.
+fn input_payload(data: Vec<u8>, target: Option<&str>) -> Result<ClipboardPayload> {
+    let is_image = match target {
+        Some(mime) => {
+            ClipboardTarget::from_mime(mime).map_err(anyhow::Error::msg)? == ClipboardTarget::Image
+        }
+        None => data.starts_with(payload::PNG_SIGNATURE),
+    };
+    if !is_image {
+        return Ok(ClipboardPayload::Text(
+            String::from_utf8_lossy(&data).into_owned(),
+        ));
+    }
+    let (width, height) =
+        payload::png_dimensions(&data).context("Input declared as image/png is not a valid PNG")?;
+    Ok(ClipboardPayload::Image {
+        width,
+        height,
+        png_data: data,
+    })
+}
+
+/// `xclip -i`: put stdin on the clipboard and sync it, like a history restore.
+async fn write_input(target: Option<&str>) -> Result<()> {
+    let mut data = Vec::new();
+    std::io::stdin().read_to_end(&mut data)?;
+    check_frame_size(data.len()).map_err(anyhow::Error::msg)?;
+    let payload = input_payload(data, target)?;
+    match super::send_request(Request::Restore { payload }).await? {
+        Response::Ok => Ok(()),
+        Response::Error { message } => bail!("{}", message),
+        _ => bail!("Unexpected response from daemon"),
+    }
+}
 
 pub async fn run(args: Vec<String>) -> Result<()> {
     let mut selection = None;
@@ -29,32 +68,34 @@ pub async fn run(args: Vec<String>) -> Result<()> {
         }
     }
 
-    // Only handle clipboard reads (-selection clipboard -o)
-    if selection.as_deref() != Some("clipboard") || !output {
+    // Only the clipboard selection is synced
+    if selection.as_deref() != Some("clipboard") {
         return Ok(());
     }
+    // Input is xclip's default mode, with or without -i
+    if !output {
+        return write_input(target.as_deref()).await;
+    }
 
-    let response = super::send_request(Request::Paste).await?;
+    let response = super::send_request(Request::Paste { consume: false }).await?;
 
-    let clip_type = match &response {
-        Response::ClipData { content_type, .. } => content_type.clone(),
+    let clip_type: ContentType = match &response {
+        Response::ClipData { content_type, .. } => {
+            content_type.parse().map_err(anyhow::Error::msg)?
+        }
         _ => bail!("Unexpected response from daemon"),
     };
 
     // TARGETS query
     if target.as_deref() == Some("TARGETS") {
-        match clip_type.as_str() {
-            "image" => println!("image/png"),
-            "text" => println!("text/plain"),
-            _ => bail!("Unknown clip type: {}", clip_type),
-        }
+        println!("{}", clip_type.mime());
         return Ok(());
     }
 
     // Image read
     if let Some(t) = &target {
         if t.starts_with("image/") {
-            if clip_type == "image" {
+            if clip_type == ContentType::Image {
                 if let Response::ClipData { data, .. } = response {
                     std::io::stdout().write_all(&data)?;
                     return Ok(());
@@ -66,7 +107,7 @@ pub async fn run(args: Vec<String>) -> Result<()> {
 
     // Text read (explicit text/plain or no target)
     if target.as_deref() == Some("text/plain") || target.is_none() {
-        if clip_type == "text" {
+        if clip_type == ContentType::Text {
             if let Response::ClipData { data, .. } = response {
                 std::io::stdout().write_all(&data)?;
                 return Ok(());
@@ -77,3 +118,46 @@ pub async fn run(args: Vec<String>) -> Result<()> {
 
     bail!("Unsupported target: {}", target.unwrap_or_default());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png() -> Vec<u8> {
+        payload::rgba_to_png(&[0, 0, 0, 255], 1, 1).unwrap()
+    }
+
+    #[test]
+    fn sniffs_png_without_target() {
+        assert!(matches!(
+            input_payload(png(), None).unwrap(),
+            ClipboardPayload::Image {
+                width: 1,
+                height: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            input_payload(b"hello".to_vec(), None).unwrap(),
+            ClipboardPayload::Text(text) if text == "hello"
+        ));
+    }
+
+    #[test]
+    fn declared_target_wins_over_content() {
+        assert!(matches!(
+            input_payload(png(), Some("text/plain")).unwrap(),
+            ClipboardPayload::Text(_)
+        ));
+        assert!(input_payload(b"hello".to_vec(), Some("image/jpeg")).is_err());
+    }
+
+    #[test]
+    fn declared_png_must_be_valid() {
+        let err = input_payload(b"not a png".to_vec(), Some("image/png")).unwrap_err();
+        assert!(err.to_string().contains("not a valid PNG"));
+        let mut truncated = png();
+        truncated.truncate(12);
+        assert!(input_payload(truncated, Some("image/png")).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[cfg(unix)]
 fn set_file_mode(path: &std::path::Path, mode: u32) -> Result<()> {
@@ -9,6 +9,52 @@ fn set_file_mode(path: &std::path::Path, mode: u32) -> Result<()> {
         .with_context(|| format!("Failed to set permissions on {}", path.display()))
 }
 
+/// Write `contents` to `path` atomically, readable only by the owner. The data
+/// goes to a temporary file next to it which is synced and renamed over
+/// `path`, so an interrupted write leaves the old file rather than a
+/// truncated one.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let mut tmp_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid path {}", path.display()))?
+        .to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(&tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    drop(file);
+    // The temporary file may have been left behind with other permissions
+    #[cfg(unix)]
+    set_file_mode(&tmp, 0o600)?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Read a small credential file, trimmed. A missing or empty file (e.g. from
+/// an interrupted write by an older version) counts as not set.
+fn read_trimmed(path: &Path, what: &str) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", what))?;
+    let contents = contents.trim();
+    Ok((!contents.is_empty()).then(|| contents.to_string()))
+}
+
 pub fn ensure_config_dir() -> Result<PathBuf> {
     let dir = config_dir()?;
     std::fs::create_dir_all(&dir)?;
@@ -17,16 +63,210 @@ pub fn ensure_config_dir() -> Result<PathBuf> {
     Ok(dir)
 }
 
+/// Content type a routing rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleContentType {
+    Text,
+    Image,
+    /// Matches every content type
+    Any,
+}
+
+impl RuleContentType {
+    fn matches(self, content_type: &str) -> bool {
+        match self {
+            RuleContentType::Text => content_type == "text",
+            RuleContentType::Image => content_type == "image",
+            RuleContentType::Any => true,
+        }
+    }
+}
+
+/// What the daemon does with clips of a matching content type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Send local changes and apply incoming clips (the default)
+    Sync,
+    /// Send local changes but don't apply incoming clips
+    Send,
+    /// Apply incoming clips but don't send local changes
+    Receive,
+    /// Neither send nor apply
+    Ignore,
+}
+
+impl RuleAction {
+    pub fn allows_send(self) -> bool {
+        matches!(self, RuleAction::Sync | RuleAction::Send)
+    }
+
+    pub fn allows_receive(self) -> bool {
+        matches!(self, RuleAction::Sync | RuleAction::Receive)
+    }
+}
+
+/// What the daemon treats as "the clipboard". Written as `system`, `none` or
+/// `file:<path>` in the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ClipboardSource {
+    /// The desktop clipboard (the default)
+    #[default]
+    System,
+    /// A plain file: changes to it are synced and incoming clips are written
+    /// to it. For headless machines without a desktop clipboard.
+    File(PathBuf),
+    /// No clipboard at all; only `clipsync copy` with piped data and
+    /// `clipsync paste` are useful
+    None,
+}
+
+impl std::str::FromStr for ClipboardSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "system" => Ok(ClipboardSource::System),
+            "none" => Ok(ClipboardSource::None),
+            other => match other.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(ClipboardSource::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "Invalid clipboard_source '{}' (expected system, none or file:<path>)",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ClipboardSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClipboardSource::System => write!(f, "system"),
+            ClipboardSource::File(path) => write!(f, "file:{}", path.display()),
+            ClipboardSource::None => write!(f, "none"),
+        }
+    }
+}
+
+impl TryFrom<String> for ClipboardSource {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ClipboardSource> for String {
+    fn from(source: ClipboardSource) -> Self {
+        source.to_string()
+    }
+}
+
+/// A content-based routing rule, e.g. `{ when = "image", action = "receive" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingRule {
+    pub when: RuleContentType,
+    pub action: RuleAction,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default = "default_watch_clipboard")]
     pub watch_clipboard: bool,
     #[serde(default = "default_poll_interval")]
     pub poll_interval_ms: u64,
+    /// Upper bound for the idle polling backoff. Equal to `poll_interval_ms`
+    /// (the default) disables backoff.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_max_ms: u64,
+    /// Idle polls before the interval doubles.
+    #[serde(default = "default_poll_backoff_idle_cycles")]
+    pub poll_backoff_idle_cycles: u32,
+    /// Extra attempts at a clipboard read that fails, e.g. while another app
+    /// holds the X11 selection. A read that still fails counts as unchanged.
+    #[serde(default = "default_clipboard_read_retries")]
+    pub clipboard_read_retries: u32,
     #[serde(default = "default_server_url")]
     pub server_url: String,
     #[serde(default = "default_database_name")]
     pub database_name: String,
+    /// Hex identity of the server module this client must talk to. When set,
+    /// connecting to a server that reports a different identity is refused.
+    #[serde(default)]
+    pub server_fingerprint: Option<String>,
+    /// Keep an encrypted, local-only history of clipboard changes.
+    #[serde(default)]
+    pub local_history: bool,
+    #[serde(default = "default_local_history_max_entries")]
+    pub local_history_max_entries: usize,
+    /// Total size of the local history file; the oldest entries go first
+    /// once either this or `local_history_max_entries` is exceeded
+    #[serde(default = "default_local_history_max_bytes")]
+    pub local_history_max_bytes: usize,
+    /// Debugging aid: log short previews of clip content. Off by default so
+    /// no clip content ever reaches the logs, only types and sizes.
+    #[serde(default)]
+    pub log_previews: bool,
+    /// Routing rules for automatic syncing, evaluated in order; the first match wins.
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+    /// Device whose clips win conflicts. Unset (the default) is pure last-write-wins.
+    #[serde(default)]
+    pub primary_device_id: Option<String>,
+    /// With a primary device set, clips from other devices are ignored until
+    /// they are this much newer than the primary's last clip.
+    #[serde(default = "default_primary_hold_secs")]
+    pub primary_hold_secs: u64,
+    /// Keep the SpacetimeDB token encrypted to this device's age identity
+    /// instead of in plaintext.
+    #[serde(default)]
+    pub secure_token_storage: bool,
+    /// Device ids whose incoming clips are applied. Empty (the default)
+    /// trusts every device on the account.
+    #[serde(default)]
+    pub trusted_senders: Vec<String>,
+    /// Treat `clipsync copy` of an empty clipboard as a no-op success instead
+    /// of an error, for scripts that copy on a timer.
+    #[serde(default)]
+    pub allow_empty_copy: bool,
+    /// Pause syncing in both directions while the screen is locked. Only
+    /// supported where logind reports the lock state; elsewhere a no-op.
+    #[serde(default)]
+    pub pause_when_locked: bool,
+    /// `clipsync copy` skips content identical to a sync this recent, to
+    /// absorb apps that write the same clip twice in quick succession.
+    #[serde(default = "default_copy_dedup_window_ms")]
+    pub copy_dedup_window_ms: u64,
+    /// Most IPC clients the daemon serves at once; more wait to be accepted.
+    /// Clamped to `1..=MAX_IPC_CONNECTIONS`.
+    #[serde(default = "default_max_ipc_connections")]
+    pub max_ipc_connections: usize,
+    /// Largest serialized clip to sync. Clamped to the server's limit,
+    /// `payload::MAX_SYNC_SIZE`.
+    #[serde(default = "default_max_clip_bytes")]
+    pub max_clip_bytes: usize,
+    /// Smallest serialized clip the clipboard watcher syncs, so stray
+    /// one-character copies stay local. `clipsync copy` ignores it unless
+    /// given `--apply-min-size`. 0 (the default) syncs everything.
+    #[serde(default)]
+    pub min_sync_bytes: usize,
+    #[serde(default)]
+    pub clipboard_source: ClipboardSource,
+    /// Shell command whose output is the account password, e.g.
+    /// `pass show clipsync`. Used instead of prompting when set.
+    #[serde(default)]
+    pub password_command: Option<String>,
+    /// Tag synced clips with the app they were copied from (X11 only). The
+    /// app name is stored unencrypted next to the clip, so it's off by default.
+    #[serde(default)]
+    pub share_source_app: bool,
+    /// Keep a one-line state summary at `status_file_path()` for status bars
+    /// to read, instead of them polling `clipsync status`.
+    #[serde(default)]
+    pub write_status_file: bool,
 }
 
 fn default_watch_clipboard() -> bool {
@@ -37,6 +277,14 @@ fn default_poll_interval() -> u64 {
     500
 }
 
+fn default_clipboard_read_retries() -> u32 {
+    2
+}
+
+fn default_poll_backoff_idle_cycles() -> u32 {
+    20
+}
+
 fn default_server_url() -> String {
     "https://maincloud.spacetimedb.com".to_string()
 }
@@ -45,13 +293,63 @@ fn default_database_name() -> String {
     "clipsync".to_string()
 }
 
+fn default_local_history_max_entries() -> usize {
+    100
+}
+
+fn default_local_history_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_primary_hold_secs() -> u64 {
+    60
+}
+
+fn default_copy_dedup_window_ms() -> u64 {
+    1000
+}
+
+fn default_max_ipc_connections() -> usize {
+    16
+}
+
+/// Upper bound for `max_ipc_connections`; each connection holds a descriptor.
+pub const MAX_IPC_CONNECTIONS: usize = 256;
+
+fn default_max_clip_bytes() -> usize {
+    crate::payload::MAX_SYNC_SIZE
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             watch_clipboard: true,
             poll_interval_ms: default_poll_interval(),
+            poll_interval_max_ms: default_poll_interval(),
+            poll_backoff_idle_cycles: default_poll_backoff_idle_cycles(),
+            clipboard_read_retries: default_clipboard_read_retries(),
             server_url: default_server_url(),
             database_name: default_database_name(),
+            server_fingerprint: None,
+            local_history: false,
+            local_history_max_entries: default_local_history_max_entries(),
+            local_history_max_bytes: default_local_history_max_bytes(),
+            log_previews: false,
+            rules: Vec::new(),
+            primary_device_id: None,
+            primary_hold_secs: default_primary_hold_secs(),
+            secure_token_storage: false,
+            trusted_senders: Vec::new(),
+            allow_empty_copy: false,
+            pause_when_locked: false,
+            copy_dedup_window_ms: default_copy_dedup_window_ms(),
+            max_ipc_connections: default_max_ipc_connections(),
+            max_clip_bytes: default_max_clip_bytes(),
+            min_sync_bytes: 0,
+            clipboard_source: ClipboardSource::System,
+            password_command: None,
+            share_source_app: false,
+            write_status_file: false,
         }
     }
 }
@@ -69,15 +367,124 @@ impl Config {
         }
     }
 
+    /// Load the config for CLI commands, falling back to defaults with a
+    /// warning when it can't be read or parsed. The bad file is first copied to
+    /// `config.toml.bak`, since commands that save the config would otherwise
+    /// overwrite it with the defaults.
+    pub fn load_or_default() -> Self {
+        match Self::load() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Warning: {:#}; using default config", e);
+                if let Ok(dir) = config_dir() {
+                    let backup = dir.join("config.toml.bak");
+                    match std::fs::copy(dir.join("config.toml"), &backup) {
+                        Ok(_) => {
+                            eprintln!("Backed up the unreadable config to {}", backup.display())
+                        }
+                        Err(e) => eprintln!("Failed to back up the unreadable config: {}", e),
+                    }
+                }
+                Self::default()
+            }
+        }
+    }
+
+    /// Resolve the routing action for a content type ("text", "image").
+    /// Content types no rule matches are synced in both directions.
+    pub fn route(&self, content_type: &str) -> RuleAction {
+        self.rules
+            .iter()
+            .find(|rule| rule.when.matches(content_type))
+            .map(|rule| rule.action)
+            .unwrap_or(RuleAction::Sync)
+    }
+
+    /// `max_ipc_connections`, clamped to a usable range.
+    pub fn ipc_connection_limit(&self) -> usize {
+        self.max_ipc_connections.clamp(1, MAX_IPC_CONNECTIONS)
+    }
+
+    /// Local history bounds, or None if local history is off.
+    pub fn local_history_retention(&self) -> Option<crate::history::Retention> {
+        self.local_history.then_some(crate::history::Retention {
+            max_entries: self.local_history_max_entries,
+            max_bytes: self.local_history_max_bytes,
+        })
+    }
+
+    /// `max_clip_bytes`, capped at what the server accepts.
+    pub fn clip_size_limit(&self) -> usize {
+        self.max_clip_bytes.min(crate::payload::MAX_SYNC_SIZE)
+    }
+
+    pub fn is_trusted_sender(&self, device_id: &str) -> bool {
+        self.trusted_senders.is_empty() || self.trusted_senders.iter().any(|d| d == device_id)
+    }
+
+    pub fn is_primary_device(&self, device_id: &str) -> bool {
+        self.primary_device_id.as_deref() == Some(device_id)
+    }
+
+    /// Whether a clip from a non-primary device, stamped `updated_at_micros`,
+    /// should apply given when the primary device last sent one. Always true
+    /// when no primary device is configured.
+    pub fn allows_non_primary(
+        &self,
+        updated_at_micros: i64,
+        primary_last_micros: Option<i64>,
+    ) -> bool {
+        match (&self.primary_device_id, primary_last_micros) {
+            (Some(_), Some(primary_last)) => {
+                let hold_micros = self.primary_hold_secs.saturating_mul(1_000_000) as i64;
+                updated_at_micros >= primary_last.saturating_add(hold_micros)
+            }
+            _ => true,
+        }
+    }
+
     pub fn save(&self) -> Result<()> {
         let path = config_dir()?.join("config.toml");
         ensure_config_dir()?;
         let contents = toml::to_string_pretty(self)?;
-        std::fs::write(&path, contents)
-            .with_context(|| format!("Failed to write config to {}", path.display()))?;
-        #[cfg(unix)]
-        set_file_mode(&path, 0o600)?;
-        Ok(())
+        write_private_file(&path, contents.as_bytes())
+            .with_context(|| format!("Failed to write config to {}", path.display()))
+    }
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    let fingerprint = fingerprint.trim();
+    fingerprint
+        .strip_prefix("0x")
+        .unwrap_or(fingerprint)
+        .to_ascii_lowercase()
+}
+
+/// Check the identity a server reported against the pinned `server_fingerprint`.
+/// Anything passes when nothing is pinned.
+pub fn check_server_fingerprint(
+    pinned: Option<&str>,
+    reported: Option<&str>,
+) -> Result<(), String> {
+    let Some(pinned) = pinned else {
+        return Ok(());
+    };
+    match reported {
+        Some(reported) if normalize_fingerprint(reported) == normalize_fingerprint(pinned) => {
+            Ok(())
+        }
+        Some(reported) => Err(format!(
+            "SERVER IDENTITY MISMATCH: expected {} but the server reported {}. Refusing to \
+             connect; check server_url, or update server_fingerprint if the server was \
+             intentionally replaced.",
+            pinned.trim(),
+            reported
+        )),
+        None => Err(format!(
+            "Server did not report its identity, but server_fingerprint is pinned to {}. \
+             Refusing to connect.",
+            pinned.trim()
+        )),
     }
 }
 
@@ -95,47 +502,85 @@ fn token_path() -> Result<PathBuf> {
     Ok(config_dir()?.join("token"))
 }
 
+fn encrypted_token_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("token.age"))
+}
+
 pub fn load_device_id() -> Result<Option<String>> {
-    let path = device_id_path()?;
-    if path.exists() {
-        let id = std::fs::read_to_string(&path)
-            .with_context(|| "Failed to read device_id")?
-            .trim()
-            .to_string();
-        Ok(Some(id))
-    } else {
-        Ok(None)
-    }
+    read_trimmed(&device_id_path()?, "device_id")
 }
 
 pub fn save_device_id(id: &str) -> Result<()> {
     let path = device_id_path()?;
     ensure_config_dir()?;
-    std::fs::write(&path, id).with_context(|| "Failed to write device_id")?;
-    #[cfg(unix)]
-    set_file_mode(&path, 0o600)?;
-    Ok(())
+    write_private_file(&path, id.as_bytes()).with_context(|| "Failed to write device_id")
+}
+
+/// Whether a token has been saved, in either storage form, without reading it.
+pub fn token_exists() -> Result<bool> {
+    Ok(token_path()?.exists() || encrypted_token_path()?.exists())
 }
 
+/// Load the token, decrypting it if it was stored with `secure_token_storage`.
 pub fn load_token() -> Result<Option<String>> {
-    let path = token_path()?;
-    if path.exists() {
-        let token = std::fs::read_to_string(&path)
-            .with_context(|| "Failed to read token")?
-            .trim()
-            .to_string();
-        Ok(Some(token))
-    } else {
-        Ok(None)
+    let encrypted_path = encrypted_token_path()?;
+    if encrypted_path.exists() {
+        let encrypted =
+            std::fs::read(&encrypted_path).with_context(|| "Failed to read encrypted token")?;
+        if encrypted.is_empty() {
+            return Ok(None);
+        }
+        let identity = crate::crypto::load_private_key()
+            .context("Token is encrypted but the private key could not be loaded")?;
+        let token =
+            crate::crypto::decrypt(&encrypted, &identity).context("Failed to decrypt token")?;
+        let token = String::from_utf8(token).context("Decrypted token is not valid UTF-8")?;
+        return Ok(Some(token.trim().to_string()));
     }
+
+    read_trimmed(&token_path()?, "token")
 }
 
+/// Save the token, encrypted to the device's age identity when
+/// `secure_token_storage` is on. Without a usable identity it falls back to
+/// the plaintext file and warns.
 pub fn save_token(token: &str) -> Result<()> {
-    let path = token_path()?;
     ensure_config_dir()?;
-    std::fs::write(&path, token).with_context(|| "Failed to write token")?;
-    #[cfg(unix)]
-    set_file_mode(&path, 0o600)?;
+    let path = token_path()?;
+    let encrypted_path = encrypted_token_path()?;
+
+    let secure = Config::load()
+        .map(|c| c.secure_token_storage)
+        .unwrap_or(false);
+    if secure {
+        match crate::crypto::load_private_key() {
+            Ok(identity) => {
+                let encrypted = crate::crypto::encrypt(token.as_bytes(), &[identity.to_public()])?;
+                write_private_file(&encrypted_path, &encrypted)
+                    .with_context(|| "Failed to write encrypted token")?;
+                // Don't leave a plaintext copy behind
+                if path.exists() {
+                    std::fs::remove_file(&path)
+                        .with_context(|| "Failed to remove plaintext token")?;
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "secure_token_storage is on but no private key is available ({}); \
+                     storing the token in plaintext",
+                    e
+                );
+            }
+        }
+    }
+
+    write_private_file(&path, token.as_bytes()).with_context(|| "Failed to write token")?;
+    // An older encrypted token would otherwise shadow this one in load_token
+    if encrypted_path.exists() {
+        std::fs::remove_file(&encrypted_path)
+            .with_context(|| "Failed to remove encrypted token")?;
+    }
     Ok(())
 }
 
@@ -144,35 +589,66 @@ fn user_id_path() -> Result<PathBuf> {
 }
 
 pub fn load_user_id() -> Result<Option<u64>> {
-    let path = user_id_path()?;
-    if path.exists() {
-        let content = std::fs::read_to_string(&path)
-            .with_context(|| "Failed to read user_id")?;
-        let id: u64 = content.trim().parse().context("Failed to parse user_id")?;
-        Ok(Some(id))
-    } else {
-        Ok(None)
-    }
+    read_trimmed(&user_id_path()?, "user_id")?
+        .map(|id| id.parse().context("Failed to parse user_id"))
+        .transpose()
 }
 
 pub fn save_user_id(user_id: u64) -> Result<()> {
     let path = user_id_path()?;
     ensure_config_dir()?;
-    std::fs::write(&path, user_id.to_string()).with_context(|| "Failed to write user_id")?;
-    #[cfg(unix)]
-    set_file_mode(&path, 0o600)?;
-    Ok(())
+    write_private_file(&path, user_id.to_string().as_bytes())
+        .with_context(|| "Failed to write user_id")
 }
 
-pub fn socket_path() -> PathBuf {
+/// Take an exclusive, non-blocking lock on `config_dir()/<name>`. The lock is
+/// held until the returned guard is dropped (or the process exits), so a second
+/// holder fails fast instead of racing on the same files.
+pub fn acquire_lock(name: &str) -> Result<nix::fcntl::Flock<std::fs::File>> {
+    let dir = ensure_config_dir()?;
+    lock_file(&dir.join(name))
+}
+
+/// Take an exclusive, non-blocking lock on the file at `path`, creating it if needed.
+fn lock_file(path: &Path) -> Result<nix::fcntl::Flock<std::fs::File>> {
+    use nix::fcntl::{Flock, FlockArg};
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+    Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(|(_, errno)| {
+        if errno == nix::errno::Errno::EWOULDBLOCK {
+            anyhow::anyhow!("{} is held by another process", path.display())
+        } else {
+            anyhow::anyhow!("Failed to lock {}: {}", path.display(), errno)
+        }
+    })
+}
+
+/// `clipsync.<extension>` in the per-user runtime directory, falling back to
+/// a uid-suffixed name in /tmp.
+fn runtime_path(extension: &str) -> PathBuf {
     if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
-        return PathBuf::from(runtime_dir).join("clipsync.sock");
+        return PathBuf::from(runtime_dir).join(format!("clipsync.{}", extension));
     }
     if let Ok(tmpdir) = std::env::var("TMPDIR") {
-        return PathBuf::from(tmpdir).join("clipsync.sock");
+        return PathBuf::from(tmpdir).join(format!("clipsync.{}", extension));
     }
     let uid = nix::unistd::getuid().as_raw();
-    PathBuf::from(format!("/tmp/clipsync-{}.sock", uid))
+    PathBuf::from(format!("/tmp/clipsync-{}.{}", uid, extension))
+}
+
+pub fn socket_path() -> PathBuf {
+    runtime_path("sock")
+}
+
+/// Where the daemon keeps its one-line state for status bars, with
+/// `write_status_file` on.
+pub fn status_file_path() -> PathBuf {
+    runtime_path("status")
 }
 
 #[cfg(test)]
@@ -186,12 +662,65 @@ mod tests {
         assert!(path.to_str().unwrap().contains("clipsync"));
     }
 
+    #[test]
+    fn status_file_sits_next_to_socket() {
+        assert_eq!(socket_path().parent(), status_file_path().parent());
+        assert!(status_file_path().to_string_lossy().ends_with(".status"));
+    }
+
+    #[test]
+    fn acquire_lock_is_exclusive() {
+        let path = std::env::temp_dir().join(format!("clipsync-{}.lock", uuid::Uuid::new_v4()));
+        let guard = lock_file(&path).unwrap();
+        assert!(lock_file(&path).is_err());
+        drop(guard);
+        assert!(lock_file(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn write_private_file_replaces_atomically() {
+        let path = std::env::temp_dir().join(format!("clipsync-{}", uuid::Uuid::new_v4()));
+        write_private_file(&path, b"old").unwrap();
+        write_private_file(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!path
+            .with_file_name(format!(
+                "{}.tmp",
+                path.file_name().unwrap().to_string_lossy()
+            ))
+            .exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::write(&path, "  \n").unwrap();
+        assert_eq!(read_trimmed(&path, "test").unwrap(), None);
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[test]
     fn default_config_values() {
         let config = Config::default();
         assert!(config.watch_clipboard);
         assert_eq!(config.poll_interval_ms, 500);
+        assert_eq!(config.poll_interval_max_ms, 500);
         assert_eq!(config.database_name, "clipsync");
+        assert!(!config.local_history);
+        assert_eq!(config.local_history_max_entries, 100);
+        assert_eq!(config.local_history_max_bytes, 64 * 1024 * 1024);
+        assert!(!config.log_previews);
+        assert!(config.server_fingerprint.is_none());
+        assert_eq!(config.copy_dedup_window_ms, 1000);
+        assert_eq!(config.max_ipc_connections, 16);
+        assert_eq!(config.max_clip_bytes, crate::payload::MAX_SYNC_SIZE);
+        assert_eq!(config.min_sync_bytes, 0);
+        assert_eq!(config.clipboard_read_retries, 2);
+        assert!(!config.share_source_app);
+        assert!(!config.write_status_file);
     }
 
     #[test]
@@ -199,14 +728,182 @@ mod tests {
         let config = Config {
             watch_clipboard: true,
             poll_interval_ms: 1000,
+            poll_interval_max_ms: 8000,
+            poll_backoff_idle_cycles: 5,
+            clipboard_read_retries: 0,
             server_url: "https://example.com".to_string(),
             database_name: "test".to_string(),
+            server_fingerprint: Some("c200abcd".to_string()),
+            local_history: true,
+            local_history_max_entries: 10,
+            local_history_max_bytes: 4096,
+            log_previews: false,
+            rules: vec![RoutingRule {
+                when: RuleContentType::Image,
+                action: RuleAction::Receive,
+            }],
+            primary_device_id: Some("desk".to_string()),
+            primary_hold_secs: 30,
+            secure_token_storage: true,
+            trusted_senders: vec!["desk".to_string()],
+            allow_empty_copy: true,
+            pause_when_locked: true,
+            copy_dedup_window_ms: 250,
+            max_ipc_connections: 64,
+            max_clip_bytes: 1024 * 1024,
+            min_sync_bytes: 2,
+            clipboard_source: ClipboardSource::File(PathBuf::from("/tmp/clip")),
+            password_command: Some("pass show clipsync".to_string()),
+            share_source_app: true,
+            write_status_file: true,
         };
         let serialized = toml::to_string_pretty(&config).unwrap();
         let deserialized: Config = toml::from_str(&serialized).unwrap();
         assert_eq!(deserialized.watch_clipboard, true);
         assert_eq!(deserialized.poll_interval_ms, 1000);
+        assert_eq!(deserialized.poll_interval_max_ms, 8000);
+        assert_eq!(deserialized.poll_backoff_idle_cycles, 5);
+        assert_eq!(deserialized.clipboard_read_retries, 0);
         assert_eq!(deserialized.server_url, "https://example.com");
         assert_eq!(deserialized.database_name, "test");
+        assert_eq!(deserialized.server_fingerprint.as_deref(), Some("c200abcd"));
+        assert!(deserialized.local_history);
+        assert_eq!(deserialized.local_history_max_entries, 10);
+        assert_eq!(deserialized.local_history_max_bytes, 4096);
+        assert_eq!(deserialized.rules, config.rules);
+        assert_eq!(deserialized.primary_device_id.as_deref(), Some("desk"));
+        assert_eq!(deserialized.primary_hold_secs, 30);
+        assert!(deserialized.secure_token_storage);
+        assert_eq!(deserialized.trusted_senders, vec!["desk".to_string()]);
+        assert!(deserialized.allow_empty_copy);
+        assert!(deserialized.pause_when_locked);
+        assert_eq!(deserialized.copy_dedup_window_ms, 250);
+        assert_eq!(deserialized.max_ipc_connections, 64);
+        assert_eq!(deserialized.max_clip_bytes, 1024 * 1024);
+        assert_eq!(deserialized.min_sync_bytes, 2);
+        assert_eq!(
+            deserialized.clipboard_source,
+            ClipboardSource::File(PathBuf::from("/tmp/clip"))
+        );
+        assert_eq!(
+            deserialized.password_command.as_deref(),
+            Some("pass show clipsync")
+        );
+        assert!(deserialized.share_source_app);
+        assert!(deserialized.write_status_file);
+    }
+
+    #[test]
+    fn primary_device_holds_off_other_devices() {
+        let mut config = Config::default();
+        assert!(config.allows_non_primary(0, Some(0)));
+
+        config.primary_device_id = Some("desk".to_string());
+        config.primary_hold_secs = 10;
+        assert!(config.is_primary_device("desk"));
+        assert!(!config.is_primary_device("laptop"));
+        // Nothing from the primary yet
+        assert!(config.allows_non_primary(0, None));
+        assert!(!config.allows_non_primary(5_000_000, Some(0)));
+        assert!(config.allows_non_primary(10_000_000, Some(0)));
+    }
+
+    #[test]
+    fn clipboard_source_parses() {
+        assert_eq!("system".parse(), Ok(ClipboardSource::System));
+        assert_eq!("none".parse(), Ok(ClipboardSource::None));
+        assert_eq!(
+            "file:/var/lib/clip.txt".parse(),
+            Ok(ClipboardSource::File(PathBuf::from("/var/lib/clip.txt")))
+        );
+        assert!("file:".parse::<ClipboardSource>().is_err());
+        assert!("pipe".parse::<ClipboardSource>().is_err());
+        assert!(toml::from_str::<Config>("clipboard_source = \"bogus\"").is_err());
+    }
+
+    #[test]
+    fn ipc_connection_limit_is_clamped() {
+        let mut config = Config::default();
+        assert_eq!(config.ipc_connection_limit(), 16);
+        config.max_ipc_connections = 0;
+        assert_eq!(config.ipc_connection_limit(), 1);
+        config.max_ipc_connections = 100_000;
+        assert_eq!(config.ipc_connection_limit(), MAX_IPC_CONNECTIONS);
+    }
+
+    #[test]
+    fn clip_size_limit_is_capped() {
+        let mut config = Config::default();
+        config.max_clip_bytes = 1024;
+        assert_eq!(config.clip_size_limit(), 1024);
+        config.max_clip_bytes = usize::MAX;
+        assert_eq!(config.clip_size_limit(), crate::payload::MAX_SYNC_SIZE);
+    }
+
+    #[test]
+    fn trusted_senders_filter_devices() {
+        let mut config = Config::default();
+        assert!(config.is_trusted_sender("anything"));
+
+        config.trusted_senders = vec!["desk".to_string(), "laptop".to_string()];
+        assert!(config.is_trusted_sender("desk"));
+        assert!(config.is_trusted_sender("laptop"));
+        assert!(!config.is_trusted_sender("tablet"));
+    }
+
+    #[test]
+    fn routing_rules_first_match_wins() {
+        let config: Config = toml::from_str(
+            r#"
+            [[rules]]
+            when = "image"
+            action = "receive"
+
+            [[rules]]
+            when = "any"
+            action = "ignore"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.route("image"), RuleAction::Receive);
+        assert_eq!(config.route("text"), RuleAction::Ignore);
+    }
+
+    #[test]
+    fn routing_defaults_to_sync() {
+        let config = Config::default();
+        assert_eq!(config.route("text"), RuleAction::Sync);
+        assert!(config.route("image").allows_send());
+        assert!(config.route("image").allows_receive());
+    }
+
+    #[test]
+    fn routing_rejects_unknown_content_type() {
+        let result: Result<Config, _> = toml::from_str(
+            r#"
+            [[rules]]
+            when = "video"
+            action = "sync"
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn server_fingerprint_check() {
+        assert!(check_server_fingerprint(None, Some("c200abcd")).is_ok());
+        assert!(check_server_fingerprint(None, None).is_ok());
+        assert!(check_server_fingerprint(Some(" 0xC200ABCD "), Some("c200abcd")).is_ok());
+        assert!(check_server_fingerprint(Some("c200abcd"), Some("c200ffff")).is_err());
+        assert!(check_server_fingerprint(Some("c200abcd"), None).is_err());
+    }
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let config: Config = toml::from_str("watch_clipboard = false").unwrap();
+        assert!(!config.watch_clipboard);
+        assert_eq!(config.poll_interval_ms, 500);
+        assert!(!config.local_history);
+        assert_eq!(config.local_history_max_entries, 100);
     }
 }
@@ -20,21 +20,19 @@ pub fn store_private_key(identity: &x25519::Identity) -> Result<()> {
 
     config::ensure_config_dir()?;
     let path = identity_file_path()?;
-    std::fs::write(&path, &key_str).with_context(|| "Failed to write identity file")?;
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
-    }
-
-    Ok(())
+    config::write_private_file(&path, key_str.as_bytes())
+        .with_context(|| "Failed to write identity file")
 }
 
 pub fn load_private_key() -> Result<x25519::Identity> {
     let path = identity_file_path()?;
-    let key_str =
-        std::fs::read_to_string(&path).with_context(|| "Failed to read identity file")?;
+    let key_str = std::fs::read_to_string(&path).with_context(|| "Failed to read identity file")?;
+    if key_str.trim().is_empty() {
+        anyhow::bail!(
+            "Identity file {} is empty. Run `clipsync setup` or `clipsync import-key`.",
+            path.display()
+        );
+    }
     let identity: x25519::Identity = key_str
         .trim()
         .parse()
@@ -43,9 +41,9 @@ pub fn load_private_key() -> Result<x25519::Identity> {
 }
 
 pub fn encrypt_with_passphrase(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
-    let encryptor = age::Encryptor::with_user_passphrase(
-        age::secrecy::SecretString::from(passphrase.to_string()),
-    );
+    let encryptor = age::Encryptor::with_user_passphrase(age::secrecy::SecretString::from(
+        passphrase.to_string(),
+    ));
 
     let mut encrypted = vec![];
     let mut writer = encryptor
@@ -65,14 +63,13 @@ pub fn decrypt_with_passphrase(encrypted: &[u8], passphrase: &str) -> Result<Vec
     let decryptor = age::Decryptor::new(encrypted)
         .map_err(|e| anyhow::anyhow!("Failed to create passphrase decryptor: {}", e))?;
 
-    let identity = age::scrypt::Identity::new(
-        age::secrecy::SecretString::from(passphrase.to_string()),
-    );
+    let identity =
+        age::scrypt::Identity::new(age::secrecy::SecretString::from(passphrase.to_string()));
 
     let mut decrypted = vec![];
     let mut reader = decryptor
         .decrypt(std::iter::once(&identity as &dyn age::Identity))
-        .map_err(|e| anyhow::anyhow!("Failed to decrypt with passphrase: {}", e))?;
+        .context("Failed to decrypt with passphrase")?;
     reader
         .read_to_end(&mut decrypted)
         .with_context(|| "Failed to read passphrase-decrypted data")?;
@@ -80,11 +77,22 @@ pub fn decrypt_with_passphrase(encrypted: &[u8], passphrase: &str) -> Result<Vec
     Ok(decrypted)
 }
 
+/// Whether a `decrypt_with_passphrase` error means the passphrase was wrong,
+/// as opposed to the data being corrupt.
+pub fn is_wrong_passphrase(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<age::DecryptError>(),
+        Some(age::DecryptError::DecryptionFailed | age::DecryptError::NoMatchingKeys)
+    )
+}
+
 pub fn encrypt(data: &[u8], recipients: &[x25519::Recipient]) -> Result<Vec<u8>> {
     // Compress with zstd first
     let compressed = zstd::encode_all(data, 3).with_context(|| "zstd compression failed")?;
+    age_encrypt(&compressed, recipients)
+}
 
-    // Encrypt with age
+fn age_encrypt(data: &[u8], recipients: &[x25519::Recipient]) -> Result<Vec<u8>> {
     let recipient_refs: Vec<&dyn age::Recipient> = recipients
         .iter()
         .map(|r| r as &dyn age::Recipient)
@@ -98,7 +106,7 @@ pub fn encrypt(data: &[u8], recipients: &[x25519::Recipient]) -> Result<Vec<u8>>
         .wrap_output(&mut encrypted)
         .with_context(|| "Failed to create age writer")?;
     writer
-        .write_all(&compressed)
+        .write_all(data)
         .with_context(|| "Failed to write encrypted data")?;
     writer
         .finish()
@@ -119,11 +127,51 @@ pub fn decrypt(encrypted: &[u8], identity: &x25519::Identity) -> Result<Vec<u8>>
         .read_to_end(&mut decrypted)
         .with_context(|| "Failed to read decrypted data")?;
 
-    // Decompress with zstd
-    let decompressed =
-        zstd::decode_all(decrypted.as_slice()).with_context(|| "zstd decompression failed")?;
+    // Decompress with zstd. A blob that decrypts but isn't zstd (an older or
+    // newer format) is still returned if it parses as a clip, rather than
+    // losing the clip to a decompression error.
+    match zstd::decode_all(decrypted.as_slice()) {
+        Ok(decompressed) => Ok(decompressed),
+        Err(e) if crate::payload::ClipboardPayload::deserialize(&decrypted).is_ok() => {
+            tracing::warn!(
+                "zstd decompression failed ({}); using data as uncompressed",
+                e
+            );
+            Ok(decrypted)
+        }
+        Err(e) => Err(anyhow::Error::new(e).context("zstd decompression failed")),
+    }
+}
+
+/// Prefix that marks (and versions) a recovery string.
+const RECOVERY_PREFIX: &str = "clipsync-recovery-1:";
 
-    Ok(decompressed)
+/// Encrypt `identity` under `password` and encode it as a single-line
+/// recovery string for `clipsync import-key`. The raw key never appears in it.
+pub fn encode_recovery(identity: &x25519::Identity, password: &str) -> Result<String> {
+    let key_str = identity.to_string().expose_secret().to_string();
+    let encrypted = encrypt_with_passphrase(key_str.as_bytes(), password)?;
+    let hex: String = encrypted.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}{}", RECOVERY_PREFIX, hex))
+}
+
+/// Decode a recovery string back into the passphrase-encrypted key it carries.
+pub fn decode_recovery(recovery: &str) -> Result<Vec<u8>> {
+    let hex = recovery
+        .trim()
+        .strip_prefix(RECOVERY_PREFIX)
+        .context("Not a clipsync recovery string")?;
+    if hex.len() % 2 != 0 {
+        anyhow::bail!("Recovery string is truncated");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .context("Recovery string is corrupt")
+        })
+        .collect()
 }
 
 pub fn public_key_bytes(recipient: &x25519::Recipient) -> Vec<u8> {
@@ -133,6 +181,22 @@ pub fn public_key_bytes(recipient: &x25519::Recipient) -> Vec<u8> {
     recipient.to_string().into_bytes()
 }
 
+/// Parse a public key stored by `public_key_bytes` back into a recipient.
+pub fn recipient_from_bytes(bytes: &[u8]) -> Result<x25519::Recipient> {
+    let key_str = std::str::from_utf8(bytes).with_context(|| "Public key is not valid UTF-8")?;
+    key_str
+        .trim()
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Failed to parse public key: {}", e))
+}
+
+/// Whether `identity`'s public key is the one stored on the server. A
+/// mismatch means other devices encrypt to a key this device doesn't hold.
+pub fn key_matches(identity: &x25519::Identity, server_public_key: &[u8]) -> bool {
+    recipient_from_bytes(server_public_key)
+        .is_ok_and(|server| server.to_string() == identity.to_public().to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -149,6 +213,24 @@ mod tests {
         assert_eq!(decrypted, plaintext);
     }
 
+    #[test]
+    fn uncompressed_payload_still_decrypts() {
+        let (identity, recipient) = generate_keypair();
+        let data = crate::payload::ClipboardPayload::Text("legacy".to_string())
+            .serialize()
+            .unwrap();
+
+        let encrypted = age_encrypt(&data, &[recipient]).unwrap();
+        assert_eq!(decrypt(&encrypted, &identity).unwrap(), data);
+    }
+
+    #[test]
+    fn uncompressed_non_payload_fails() {
+        let (identity, recipient) = generate_keypair();
+        let encrypted = age_encrypt(&[0xff; 16], &[recipient]).unwrap();
+        assert!(decrypt(&encrypted, &identity).is_err());
+    }
+
     #[test]
     fn encrypt_decrypt_large_data() {
         let (identity, recipient) = generate_keypair();
@@ -170,4 +252,63 @@ mod tests {
         let decrypted = decrypt_with_passphrase(&encrypted, passphrase).unwrap();
         assert_eq!(decrypted, plaintext);
     }
+
+    #[test]
+    fn wrong_passphrase_is_detected() {
+        let encrypted = encrypt_with_passphrase(b"secret", "right password").unwrap();
+        let err = decrypt_with_passphrase(&encrypted, "wrong password").unwrap_err();
+        assert!(is_wrong_passphrase(&err));
+
+        let err = decrypt_with_passphrase(b"not age data", "right password").unwrap_err();
+        assert!(!is_wrong_passphrase(&err));
+    }
+
+    #[test]
+    fn recovery_round_trip() {
+        let (identity, recipient) = generate_keypair();
+        let recovery = encode_recovery(&identity, "hunter22").unwrap();
+        assert!(!recovery.contains(identity.to_string().expose_secret()));
+        assert!(!recovery.contains(char::is_whitespace));
+
+        let encrypted = decode_recovery(&recovery).unwrap();
+        let key = decrypt_with_passphrase(&encrypted, "hunter22").unwrap();
+        let restored: x25519::Identity = std::str::from_utf8(&key).unwrap().parse().unwrap();
+        assert_eq!(restored.to_public().to_string(), recipient.to_string());
+    }
+
+    #[test]
+    fn recovery_rejects_garbage() {
+        assert!(decode_recovery("age1notarecovery").is_err());
+        assert!(decode_recovery("clipsync-recovery-1:abc").is_err());
+        assert!(decode_recovery("clipsync-recovery-1:zz").is_err());
+    }
+
+    #[test]
+    fn public_key_bytes_round_trip() {
+        let (identity, recipient) = generate_keypair();
+        let parsed = recipient_from_bytes(&public_key_bytes(&recipient)).unwrap();
+        let encrypted = encrypt(b"team clip", &[parsed]).unwrap();
+        assert_eq!(decrypt(&encrypted, &identity).unwrap(), b"team clip");
+    }
+
+    #[test]
+    fn key_matches_only_own_public_key() {
+        let (identity, recipient) = generate_keypair();
+        let (_, other) = generate_keypair();
+        assert!(key_matches(&identity, &public_key_bytes(&recipient)));
+        assert!(!key_matches(&identity, &public_key_bytes(&other)));
+        assert!(!key_matches(&identity, b"garbage"));
+    }
+
+    #[test]
+    fn encrypt_to_multiple_recipients() {
+        let (alice, alice_pub) = generate_keypair();
+        let (bob, bob_pub) = generate_keypair();
+        let (carol, _) = generate_keypair();
+
+        let encrypted = encrypt(b"shared", &[alice_pub, bob_pub]).unwrap();
+        assert_eq!(decrypt(&encrypted, &alice).unwrap(), b"shared");
+        assert_eq!(decrypt(&encrypted, &bob).unwrap(), b"shared");
+        assert!(decrypt(&encrypted, &carol).is_err());
+    }
 }
@@ -0,0 +1,117 @@
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
+
+/// Parse `xprop -root _NET_ACTIVE_WINDOW` output into the window id, e.g.
+/// `_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007`. None when no window
+/// has focus (id 0x0) or the property isn't set.
+fn parse_active_window(output: &str) -> Option<String> {
+    let (_, ids) = output.trim().split_once('#')?;
+    let id = ids.split(',').next()?.trim();
+    if !id.starts_with("0x") || u64::from_str_radix(&id[2..], 16).ok()? == 0 {
+        return None;
+    }
+    Some(id.to_string())
+}
+
+/// Parse `xprop -id <window> WM_CLASS` output, e.g.
+/// `WM_CLASS(STRING) = "Navigator", "firefox"`, into the class name (the
+/// second string), falling back to the instance name.
+fn parse_wm_class(output: &str) -> Option<String> {
+    let (_, values) = output.trim().split_once('=')?;
+    let names: Vec<&str> = values
+        .split(',')
+        .map(|name| name.trim().trim_matches('"'))
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.get(1).or(names.first()).map(|name| name.to_string())
+}
+
+/// How long one `xprop` call may run before it's killed. The clipboard
+/// thread waits on it when a change is detected, so it must not hang there.
+#[cfg(target_os = "linux")]
+const XPROP_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[cfg(target_os = "linux")]
+fn xprop(args: &[&str]) -> Option<String> {
+    use std::io::Read;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new("xprop")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let deadline = Instant::now() + XPROP_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    };
+    if !status.success() {
+        return None;
+    }
+    let mut stdout = Vec::new();
+    child.stdout.take()?.read_to_end(&mut stdout).ok()?;
+    Some(String::from_utf8_lossy(&stdout).into_owned())
+}
+
+/// The window class of the focused X11 window, for tagging clips with the
+/// app they were copied from. None without an X display or `xprop`, or if
+/// `xprop` doesn't answer in time.
+#[cfg(target_os = "linux")]
+pub fn active_app() -> Option<String> {
+    std::env::var_os("DISPLAY")?;
+    let window = parse_active_window(&xprop(&["-root", "_NET_ACTIVE_WINDOW"])?)?;
+    parse_wm_class(&xprop(&["-id", &window, "WM_CLASS"])?)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn active_app() -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_active_window() {
+        assert_eq!(
+            parse_active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n").as_deref(),
+            Some("0x3a00007")
+        );
+        assert_eq!(
+            parse_active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007, 0x0\n")
+                .as_deref(),
+            Some("0x3a00007")
+        );
+        assert_eq!(
+            parse_active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0\n"),
+            None
+        );
+        assert_eq!(
+            parse_active_window("_NET_ACTIVE_WINDOW:  not found.\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_wm_class() {
+        assert_eq!(
+            parse_wm_class("WM_CLASS(STRING) = \"Navigator\", \"firefox\"\n").as_deref(),
+            Some("firefox")
+        );
+        assert_eq!(
+            parse_wm_class("WM_CLASS(STRING) = \"xterm\"\n").as_deref(),
+            Some("xterm")
+        );
+        assert_eq!(parse_wm_class("WM_CLASS:  not found.\n"), None);
+    }
+}
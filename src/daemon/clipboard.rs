@@ -1,20 +1,286 @@
 use anyhow::Result;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
 use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
+use super::health::Heartbeat;
+use crate::config::{self, ClipboardSource};
 use crate::payload::{self, ClipboardPayload};
 
 #[derive(Debug)]
 pub enum ClipboardEvent {
-    Changed { payload: ClipboardPayload },
+    Changed {
+        payload: ClipboardPayload,
+        /// The app focused when the change was detected, if asked for
+        source_app: Option<String>,
+    },
 }
 
 pub enum ClipboardCommand {
-    SetClipboard { payload: ClipboardPayload },
-    ReadClipboard { reply: tokio::sync::oneshot::Sender<Option<ClipboardPayload>> },
+    SetClipboard {
+        payload: ClipboardPayload,
+        /// Told whether the clipboard took the payload
+        reply: Option<tokio::sync::oneshot::Sender<Result<(), String>>>,
+    },
+    /// Set HTML together with a plain-text fallback, for apps that only read text
+    SetHtml { html: String, alt_text: String },
+    /// Empty the system clipboard
+    Clear,
+    /// Read the clipboard, either text-then-image or one specific target
+    ReadClipboard {
+        target: Option<ClipboardTarget>,
+        reply: tokio::sync::oneshot::Sender<Option<ClipboardPayload>>,
+    },
+    /// Content type and size of the clipboard, read like a poll; the content
+    /// itself stays in the clipboard thread
+    Measure {
+        reply: tokio::sync::oneshot::Sender<Option<(payload::ContentType, usize)>>,
+    },
+}
+
+/// A clipboard representation that can be read explicitly, for apps that
+/// offer several (e.g. a spreadsheet cell as text, HTML and an image).
+/// arboard can't list a clipboard's targets, so only these are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardTarget {
+    Text,
+    /// Synced as the HTML markup itself, as text
+    Html,
+    Image,
+}
+
+impl ClipboardTarget {
+    pub fn from_mime(mime: &str) -> Result<Self, String> {
+        match mime.trim().to_ascii_lowercase().as_str() {
+            "text/plain" | "text" | "utf8_string" => Ok(ClipboardTarget::Text),
+            "text/html" | "html" => Ok(ClipboardTarget::Html),
+            "image/png" | "image" => Ok(ClipboardTarget::Image),
+            other => Err(format!(
+                "Unsupported clipboard target: {} (supported: text/plain, text/html, image/png)",
+                other
+            )),
+        }
+    }
+}
+
+/// How long a single arboard call may run before the watchdog reports it as stuck.
+const ARBOARD_STALL_THRESHOLD: Duration = Duration::from_secs(5);
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Records the arboard call currently in flight so a separate thread can report
+/// when the clipboard thread is wedged (arboard can block indefinitely on some
+/// platforms while another app holds the selection).
+#[derive(Clone, Default)]
+struct Watchdog {
+    busy_since: Arc<Mutex<Option<(Instant, &'static str)>>>,
+}
+
+impl Watchdog {
+    /// Forget the call in flight, after a panic abandoned it.
+    fn reset(&self) {
+        *self.busy_since.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    fn guard<T>(&self, op: &'static str, f: impl FnOnce() -> T) -> T {
+        *self.busy_since.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), op));
+        let result = f();
+        *self.busy_since.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        result
+    }
+
+    /// Spawn the monitoring thread. It exits once the clipboard thread has
+    /// dropped its handle.
+    fn spawn_monitor(&self) -> Result<()> {
+        let busy_since = self.busy_since.clone();
+        std::thread::Builder::new()
+            .name("clipboard-watchdog".to_string())
+            .spawn(move || {
+                let mut reported = false;
+                while Arc::strong_count(&busy_since) > 1 {
+                    std::thread::sleep(WATCHDOG_CHECK_INTERVAL);
+                    let current = *busy_since.lock().unwrap_or_else(|e| e.into_inner());
+                    match current {
+                        Some((started, op)) if started.elapsed() >= ARBOARD_STALL_THRESHOLD => {
+                            if !reported {
+                                warn!(
+                                    "Clipboard thread stuck in {} for {}s",
+                                    op,
+                                    started.elapsed().as_secs()
+                                );
+                                reported = true;
+                            }
+                        }
+                        _ => {
+                            if reported {
+                                info!("Clipboard thread recovered");
+                                reported = false;
+                            }
+                        }
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
+
+/// A clipboard thread that panics is restarted, at most this many times per
+/// window, so a clipboard that panics on every read doesn't loop forever.
+const MAX_CLIPBOARD_RESTARTS: usize = 3;
+const CLIPBOARD_RESTART_WINDOW: Duration = Duration::from_secs(10 * 60);
+const CLIPBOARD_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Restarts of the clipboard thread within the last `CLIPBOARD_RESTART_WINDOW`.
+#[derive(Debug, Default)]
+struct RestartBudget {
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    /// Whether another restart is allowed now, recording it if so.
+    fn allow(&mut self, now: Instant) -> bool {
+        while self
+            .restarts
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= CLIPBOARD_RESTART_WINDOW)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= MAX_CLIPBOARD_RESTARTS {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+}
+
+/// Clipboard changes within this window are considered when looking for a conflict.
+const CONFLICT_WINDOW: Duration = Duration::from_secs(10);
+/// Number of changes alternating between the same two contents that counts as a conflict.
+const CONFLICT_MIN_FLIPS: usize = 6;
+/// How long local changes are ignored once a conflict is detected.
+const CONFLICT_SUPPRESS_FOR: Duration = Duration::from_secs(60);
+
+/// Spots another clipboard manager fighting over the clipboard: when the
+/// contents keep flipping between the same two values, each tool is re-syncing
+/// the other's writes. Syncing is then suppressed for a while to break the loop.
+#[derive(Debug, Default)]
+struct ConflictDetector {
+    changes: VecDeque<(Instant, u64)>,
+    suppressed_until: Option<Instant>,
+}
+
+impl ConflictDetector {
+    /// Record a change of clipboard contents. Returns true if this change
+    /// completes an alternating pattern and syncing should now be suppressed.
+    fn record(&mut self, hash: u64, now: Instant) -> bool {
+        while let Some(&(at, _)) = self.changes.front() {
+            if now.duration_since(at) <= CONFLICT_WINDOW {
+                break;
+            }
+            self.changes.pop_front();
+        }
+        self.changes.push_back((now, hash));
+        if self.changes.len() > CONFLICT_MIN_FLIPS {
+            self.changes.pop_front();
+        }
+
+        let alternating = self.changes.len() == CONFLICT_MIN_FLIPS
+            && self
+                .changes
+                .iter()
+                .zip(self.changes.iter().skip(2))
+                .all(|((_, a), (_, b))| a == b)
+            && self.changes[0].1 != self.changes[1].1;
+        if alternating {
+            self.changes.clear();
+            self.suppressed_until = Some(now + CONFLICT_SUPPRESS_FOR);
+        }
+        alternating
+    }
+
+    fn is_suppressed(&self, now: Instant) -> bool {
+        self.suppressed_until.is_some_and(|until| now < until)
+    }
+
+    /// Clear an expired suppression. Returns true if one just ended.
+    fn expire(&mut self, now: Instant) -> bool {
+        if self.suppressed_until.is_some() && !self.is_suppressed(now) {
+            self.suppressed_until = None;
+            return true;
+        }
+        false
+    }
+}
+
+/// Polling schedule for the clipboard watcher. While the clipboard is idle the
+/// interval doubles every `idle_cycles_per_step` polls, up to `max`; any change
+/// snaps it back to `base`. With `max == base` polling is fixed.
+#[derive(Debug, Clone)]
+pub struct PollBackoff {
+    base: Duration,
+    max: Duration,
+    idle_cycles_per_step: u32,
+    idle_cycles: u32,
+    current: Duration,
+}
+
+impl PollBackoff {
+    pub fn new(base_ms: u64, max_ms: u64, idle_cycles_per_step: u32) -> Self {
+        let base = Duration::from_millis(base_ms);
+        Self {
+            base,
+            max: Duration::from_millis(max_ms).max(base),
+            idle_cycles_per_step: idle_cycles_per_step.max(1),
+            idle_cycles: 0,
+            current: base,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.current
+    }
+
+    fn on_activity(&mut self) {
+        self.idle_cycles = 0;
+        self.current = self.base;
+    }
+
+    fn on_idle(&mut self) {
+        self.idle_cycles += 1;
+        if self.idle_cycles >= self.idle_cycles_per_step {
+            self.idle_cycles = 0;
+            self.current = (self.current * 2).min(self.max);
+        }
+    }
+}
+
+/// Hand the pending event to the main loop without blocking. If the channel is
+/// full the event stays pending and is retried after the next poll (replaced by
+/// any newer change in the meantime), since only the latest clipboard state
+/// matters. Returns false once the main loop has gone away.
+fn flush_pending(
+    event_tx: &mpsc::Sender<ClipboardEvent>,
+    pending: &mut Option<ClipboardEvent>,
+) -> bool {
+    let Some(event) = pending.take() else {
+        return true;
+    };
+    match event_tx.try_send(event) {
+        Ok(()) => true,
+        Err(mpsc::error::TrySendError::Full(event)) => {
+            debug!("Clipboard event channel full, deferring change");
+            *pending = Some(event);
+            true
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => false,
+    }
 }
 
 fn hash_bytes(data: &[u8]) -> u64 {
@@ -23,152 +289,683 @@ fn hash_bytes(data: &[u8]) -> u64 {
     hasher.finish()
 }
 
-/// Spawn clipboard polling thread that detects changes.
+/// Where the clipboard thread reads and writes clips (`clipboard_source`).
+enum Backend {
+    System(arboard::Clipboard),
+    File(PathBuf),
+}
+
+impl Backend {
+    /// Read the clipboard. Ok(None) means it is empty; an error means it
+    /// couldn't be read, and says nothing about its contents.
+    fn read(
+        &mut self,
+        watchdog: &Watchdog,
+        target: Option<ClipboardTarget>,
+        retries: u32,
+    ) -> Result<Option<ClipboardPayload>, String> {
+        match self {
+            Backend::System(clipboard) => match target {
+                Some(target) => read_clipboard_target(clipboard, watchdog, target, retries),
+                None => read_clipboard(clipboard, watchdog, retries),
+            },
+            Backend::File(path) => Ok(read_clip_file(path, target)),
+        }
+    }
+
+    fn set(&mut self, watchdog: &Watchdog, payload: &ClipboardPayload) -> Result<(), String> {
+        match (self, payload) {
+            (Backend::System(clipboard), ClipboardPayload::Text(text)) => watchdog
+                .guard("set_text", || clipboard.set_text(text))
+                .map_err(|e| format!("Failed to set clipboard text: {}", e)),
+            (Backend::System(clipboard), ClipboardPayload::Image { png_data, .. }) => {
+                let (w, h, rgba) = payload::png_to_rgba(png_data)
+                    .map_err(|e| format!("Failed to decode PNG for clipboard: {}", e))?;
+                let img_data = arboard::ImageData {
+                    width: w as usize,
+                    height: h as usize,
+                    bytes: rgba.into(),
+                };
+                watchdog
+                    .guard("set_image", || clipboard.set_image(img_data))
+                    .map_err(|e| format!("Failed to set clipboard image: {}", e))
+            }
+            (Backend::File(path), ClipboardPayload::Text(text)) => {
+                write_clip_file(path, text.as_bytes())
+            }
+            (Backend::File(path), ClipboardPayload::Image { png_data, .. }) => {
+                write_clip_file(path, png_data)
+            }
+        }
+    }
+
+    /// Set HTML with a text fallback. Returns the text the watcher will read
+    /// back: the fallback from the system clipboard, the markup from a file.
+    fn set_html<'a>(
+        &mut self,
+        watchdog: &Watchdog,
+        html: &'a str,
+        alt_text: &'a str,
+    ) -> (&'a str, Result<(), String>) {
+        match self {
+            Backend::System(clipboard) => (
+                alt_text,
+                watchdog
+                    .guard("set_html", || clipboard.set_html(html, Some(alt_text)))
+                    .map_err(|e| format!("Failed to set clipboard HTML: {}", e)),
+            ),
+            Backend::File(path) => (html, write_clip_file(path, html.as_bytes())),
+        }
+    }
+
+    fn clear(&mut self, watchdog: &Watchdog) -> Result<(), String> {
+        match self {
+            Backend::System(clipboard) => watchdog
+                .guard("clear", || clipboard.clear())
+                .map_err(|e| format!("Failed to clear clipboard: {}", e)),
+            Backend::File(path) => write_clip_file(path, b""),
+        }
+    }
+}
+
+/// Read a clip file: PNG data (by signature) is an image, anything else must
+/// be UTF-8 text. Missing, empty and non-regular files (e.g. a FIFO, which
+/// would block the thread) count as an empty clipboard.
+fn read_clip_file(path: &Path, target: Option<ClipboardTarget>) -> Option<ClipboardPayload> {
+    if !std::fs::metadata(path).is_ok_and(|m| m.is_file()) {
+        return None;
+    }
+    let data = match std::fs::read(path) {
+        Ok(data) if !data.is_empty() => data,
+        Ok(_) => return None,
+        Err(e) => {
+            debug!("Failed to read clip file {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    if data.starts_with(payload::PNG_SIGNATURE) {
+        if matches!(target, Some(ClipboardTarget::Text | ClipboardTarget::Html)) {
+            return None;
+        }
+        let (width, height) = payload::png_dimensions(&data)
+            .map_err(|e| debug!("Unreadable PNG in clip file {}: {}", path.display(), e))
+            .ok()?;
+        return Some(ClipboardPayload::Image {
+            width,
+            height,
+            png_data: data,
+        });
+    }
+    if target == Some(ClipboardTarget::Image) {
+        return None;
+    }
+    match String::from_utf8(data) {
+        Ok(text) => Some(ClipboardPayload::Text(text)),
+        Err(_) => {
+            debug!("Clip file {} is neither UTF-8 text nor PNG", path.display());
+            None
+        }
+    }
+}
+
+/// Replace the clip file in one rename, so readers never see half a clip.
+fn write_clip_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    config::write_private_file(path, data)
+        .map_err(|e| format!("Failed to write clip file {}: {:#}", path.display(), e))
+}
+
+/// Spawn clipboard polling thread that detects changes. `conflict` is set
+/// while local changes are being ignored because of a suspected conflict with
+/// another clipboard manager. With `share_source_app` set, each change is
+/// tagged with the app focused when it was detected. With
+/// `ClipboardSource::None` no thread is started, so clipboard commands fail
+/// and nothing is watched.
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_clipboard_watcher(
-    poll_interval_ms: u64,
+    source: ClipboardSource,
+    mut backoff: PollBackoff,
     event_tx: mpsc::Sender<ClipboardEvent>,
     command_rx: std::sync::mpsc::Receiver<ClipboardCommand>,
+    conflict: Arc<AtomicBool>,
+    heartbeat: Heartbeat,
+    read_retries: u32,
+    share_source_app: bool,
 ) -> Result<()> {
-    let last_written_hash: Arc<Mutex<Option<u64>>> = Arc::new(Mutex::new(None));
-    let last_written_hash_for_cmd = last_written_hash.clone();
+    if source == ClipboardSource::None {
+        info!("Clipboard source is none, not starting the clipboard thread");
+        return Ok(());
+    }
+
+    let last_written_hash: Mutex<Option<u64>> = Mutex::new(None);
+
+    let watchdog = Watchdog::default();
+    watchdog.spawn_monitor()?;
 
     // Spawn the command handler + clipboard poller in one thread
     std::thread::Builder::new()
         .name("clipboard".to_string())
         .spawn(move || {
-            let mut clipboard = match arboard::Clipboard::new() {
-                Ok(cb) => cb,
-                Err(e) => {
-                    error!("Failed to initialize clipboard: {}", e);
+            let mut budget = RestartBudget::default();
+            loop {
+                let run = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    run_clipboard_loop(
+                        &source,
+                        &mut backoff,
+                        &event_tx,
+                        &command_rx,
+                        &conflict,
+                        &heartbeat,
+                        read_retries,
+                        share_source_app,
+                        &last_written_hash,
+                        &watchdog,
+                    )
+                }));
+                let Err(panic) = run else {
+                    return;
+                };
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                if !budget.allow(Instant::now()) {
+                    error!(
+                        "Clipboard thread panicked ({}), and has restarted {} times in {}m; \
+                         giving up, restart the daemon to sync the clipboard again",
+                        message,
+                        MAX_CLIPBOARD_RESTARTS,
+                        CLIPBOARD_RESTART_WINDOW.as_secs() / 60
+                    );
                     return;
                 }
-            };
+                error!("Clipboard thread panicked ({}), restarting it", message);
+                heartbeat.record_restart();
+                // The panic may have left an arboard call marked as in flight
+                watchdog.reset();
+                std::thread::sleep(CLIPBOARD_RESTART_DELAY);
+            }
+        })?;
 
-            let mut last_hash: Option<u64> = None;
-            let poll_dur = std::time::Duration::from_millis(poll_interval_ms);
+    Ok(())
+}
 
-            loop {
-                // Process any pending commands (non-blocking)
-                while let Ok(cmd) = command_rx.try_recv() {
-                    match cmd {
-                        ClipboardCommand::SetClipboard { payload } => {
-                            match &payload {
-                                ClipboardPayload::Text(text) => {
-                                    let h = hash_bytes(text.as_bytes());
-                                    *last_written_hash_for_cmd.lock().unwrap_or_else(|e| e.into_inner()) = Some(h);
-                                    last_hash = Some(h);
-                                    if let Err(e) = clipboard.set_text(text) {
-                                        error!("Failed to set clipboard text: {}", e);
-                                    }
-                                }
-                                ClipboardPayload::Image {
-                                    png_data,
-                                    ..
-                                } => {
-                                    match payload::png_to_rgba(png_data) {
-                                        Ok((w, h, rgba)) => {
-                                            let hash = hash_bytes(png_data);
-                                            *last_written_hash_for_cmd.lock().unwrap_or_else(|e| e.into_inner()) =
-                                                Some(hash);
-                                            last_hash = Some(hash);
-                                            let img_data = arboard::ImageData {
-                                                width: w as usize,
-                                                height: h as usize,
-                                                bytes: rgba.into(),
-                                            };
-                                            if let Err(e) = clipboard.set_image(img_data) {
-                                                error!("Failed to set clipboard image: {}", e);
-                                            }
-                                        }
-                                        Err(e) => {
-                                            error!("Failed to decode PNG for clipboard: {}", e);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        ClipboardCommand::ReadClipboard { reply } => {
-                            let payload = read_clipboard(&mut clipboard);
-                            let _ = reply.send(payload);
-                        }
-                    }
-                }
+/// The clipboard thread's work: apply commands and poll for changes until
+/// the daemon stops listening. Clipboard state is set up afresh on each call,
+/// so a restart after a panic starts clean.
+#[allow(clippy::too_many_arguments)]
+fn run_clipboard_loop(
+    source: &ClipboardSource,
+    backoff: &mut PollBackoff,
+    event_tx: &mpsc::Sender<ClipboardEvent>,
+    command_rx: &std::sync::mpsc::Receiver<ClipboardCommand>,
+    conflict: &AtomicBool,
+    heartbeat: &Heartbeat,
+    read_retries: u32,
+    share_source_app: bool,
+    last_written_hash: &Mutex<Option<u64>>,
+    watchdog: &Watchdog,
+) {
+    let mut backend = match source.clone() {
+        ClipboardSource::File(path) => {
+            info!("Using {} as the clipboard", path.display());
+            Backend::File(path)
+        }
+        _ => match watchdog.guard("init", arboard::Clipboard::new) {
+            Ok(cb) => Backend::System(cb),
+            Err(e) => {
+                error!("Failed to initialize clipboard: {}", e);
+                return;
+            }
+        },
+    };
+
+    let mut last_hash: Option<u64> = None;
+    let mut woken_by: Option<ClipboardCommand> = None;
+    let mut pending: Option<ClipboardEvent> = None;
+    let mut conflicts = ConflictDetector::default();
 
-                // Poll clipboard for changes
-                if let Some(current_payload) = read_clipboard(&mut clipboard) {
-                    let current_hash = match &current_payload {
+    loop {
+        heartbeat.tick();
+        if conflicts.expire(Instant::now()) {
+            info!("Clipboard conflict suppression ended, resuming sync");
+            conflict.store(false, Ordering::Release);
+        }
+
+        // Process any pending commands (non-blocking)
+        for cmd in woken_by.take().into_iter().chain(command_rx.try_iter()) {
+            backoff.on_activity();
+            match cmd {
+                ClipboardCommand::SetClipboard { payload, reply } => {
+                    let h = match &payload {
                         ClipboardPayload::Text(text) => hash_bytes(text.as_bytes()),
-                        ClipboardPayload::Image { png_data, .. } => {
-                            // Hash raw clipboard data, not the PNG encoding
-                            // But since we only have PNG here, we use it
-                            hash_bytes(png_data)
-                        }
+                        ClipboardPayload::Image { png_data, .. } => hash_bytes(png_data),
                     };
+                    *last_written_hash.lock().unwrap_or_else(|e| e.into_inner()) = Some(h);
+                    if last_hash != Some(h) && conflicts.record(h, Instant::now()) {
+                        report_conflict(conflict);
+                    }
+                    last_hash = Some(h);
+                    let result = backend.set(watchdog, &payload);
+                    if let Err(e) = &result {
+                        error!("{}", e);
+                    }
+                    if let Some(reply) = reply {
+                        let _ = reply.send(result);
+                    }
+                }
+                ClipboardCommand::SetHtml { html, alt_text } => {
+                    let (readback, result) = backend.set_html(watchdog, &html, &alt_text);
+                    // The watcher reads this back, so that's what to skip
+                    let h = hash_bytes(readback.as_bytes());
+                    *last_written_hash.lock().unwrap_or_else(|e| e.into_inner()) = Some(h);
+                    if last_hash != Some(h) && conflicts.record(h, Instant::now()) {
+                        report_conflict(conflict);
+                    }
+                    last_hash = Some(h);
+                    if let Err(e) = result {
+                        error!("{}", e);
+                    }
+                }
+                ClipboardCommand::Clear => {
+                    match backend.clear(watchdog) {
+                        // Whatever is copied next counts as a change, even if it
+                        // matches what was there before
+                        Ok(()) => last_hash = None,
+                        Err(e) => error!("{}", e),
+                    }
+                }
+                ClipboardCommand::ReadClipboard { target, reply } => {
+                    let payload =
+                        backend
+                            .read(watchdog, target, read_retries)
+                            .unwrap_or_else(|e| {
+                                warn!("Failed to read clipboard: {}", e);
+                                None
+                            });
+                    let _ = reply.send(payload);
+                }
+                ClipboardCommand::Measure { reply } => {
+                    let measured = backend
+                        .read(watchdog, None, read_retries)
+                        .unwrap_or_else(|e| {
+                            warn!("Failed to read clipboard: {}", e);
+                            None
+                        })
+                        .map(|payload| (payload.content_type(), payload.data_len()));
+                    let _ = reply.send(measured);
+                }
+            }
+        }
 
-                    let should_notify = match last_hash {
-                        Some(prev) => prev != current_hash,
-                        None => true,
+        // Poll clipboard for changes. A failed read is not an empty
+        // clipboard, so it leaves last_hash alone
+        match backend.read(watchdog, None, read_retries) {
+            Ok(Some(current_payload)) => {
+                let current_hash = match &current_payload {
+                    ClipboardPayload::Text(text) => hash_bytes(text.as_bytes()),
+                    ClipboardPayload::Image { png_data, .. } => {
+                        // Hash raw clipboard data, not the PNG encoding
+                        // But since we only have PNG here, we use it
+                        hash_bytes(png_data)
+                    }
+                };
+
+                let should_notify = match last_hash {
+                    Some(prev) => prev != current_hash,
+                    None => true,
+                };
+
+                if should_notify {
+                    backoff.on_activity();
+
+                    // Check if this is content we just wrote
+                    let was_written = {
+                        let guard = last_written_hash.lock().unwrap_or_else(|e| e.into_inner());
+                        guard.as_ref() == Some(&current_hash)
                     };
 
-                    if should_notify {
-                        // Check if this is content we just wrote
-                        let was_written = {
-                            let guard = last_written_hash.lock().unwrap_or_else(|e| e.into_inner());
-                            guard.as_ref() == Some(&current_hash)
-                        };
-
-                        if !was_written {
-                            debug!("Clipboard changed, notifying");
-                            if event_tx
-                                .blocking_send(ClipboardEvent::Changed {
-                                    payload: current_payload,
-                                })
-                                .is_err()
-                            {
-                                break;
-                            }
-                        } else {
-                            // Clear the written hash now that we've seen it
-                            *last_written_hash.lock().unwrap_or_else(|e| e.into_inner()) = None;
-                        }
+                    let now = Instant::now();
+                    if !was_written && conflicts.record(current_hash, now) {
+                        report_conflict(conflict);
+                    }
 
-                        last_hash = Some(current_hash);
+                    if !was_written && conflicts.is_suppressed(now) {
+                        debug!("Ignoring clipboard change during suspected conflict");
+                    } else if !was_written {
+                        debug!("Clipboard changed, notifying");
+                        if pending.is_some() {
+                            debug!("Replacing undelivered clipboard change with newer one");
+                        }
+                        // Asked now, while the app it came from likely still
+                        // has focus
+                        let source_app = share_source_app
+                            .then(super::active_app::active_app)
+                            .flatten();
+                        pending = Some(ClipboardEvent::Changed {
+                            payload: current_payload,
+                            source_app,
+                        });
+                    } else {
+                        // Clear the written hash now that we've seen it
+                        *last_written_hash.lock().unwrap_or_else(|e| e.into_inner()) = None;
                     }
-                }
 
-                std::thread::sleep(poll_dur);
+                    last_hash = Some(current_hash);
+                } else {
+                    backoff.on_idle();
+                }
             }
-        })?;
+            Ok(None) => backoff.on_idle(),
+            Err(e) => {
+                debug!("Clipboard read failed, treating it as unchanged: {}", e);
+                backoff.on_idle();
+            }
+        }
 
-    Ok(())
+        if !flush_pending(event_tx, &mut pending) {
+            break;
+        }
+
+        // Wait for the next poll, waking immediately if a command arrives
+        if let Ok(cmd) = command_rx.recv_timeout(backoff.interval()) {
+            woken_by = Some(cmd);
+        }
+    }
 }
 
-fn read_clipboard(clipboard: &mut arboard::Clipboard) -> Option<ClipboardPayload> {
-    // Try text first
-    if let Ok(text) = clipboard.get_text() {
-        if !text.is_empty() {
-            return Some(ClipboardPayload::Text(text));
+fn report_conflict(conflict: &AtomicBool) {
+    warn!(
+        "Possible clipboard conflict detected: contents keep alternating, another clipboard \
+         manager may be syncing too. Ignoring local changes for {}s",
+        CONFLICT_SUPPRESS_FOR.as_secs()
+    );
+    conflict.store(true, Ordering::Release);
+}
+
+/// Pause between attempts at a failed clipboard read.
+const READ_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// Run an arboard read, retrying up to `retries` more times when it fails for
+/// a reason other than the content being absent. Reads fail briefly while
+/// another app holds the selection (common on X11), which mustn't be taken
+/// for an empty clipboard.
+fn read_with_retry<T>(
+    retries: u32,
+    mut read: impl FnMut() -> Result<T, arboard::Error>,
+) -> Result<Option<T>, arboard::Error> {
+    let mut attempt = 0;
+    loop {
+        match read() {
+            Ok(value) => return Ok(Some(value)),
+            Err(arboard::Error::ContentNotAvailable) => return Ok(None),
+            Err(e) if attempt >= retries => return Err(e),
+            Err(_) => {
+                attempt += 1;
+                std::thread::sleep(READ_RETRY_DELAY);
+            }
         }
     }
+}
+
+fn read_clipboard(
+    clipboard: &mut arboard::Clipboard,
+    watchdog: &Watchdog,
+    retries: u32,
+) -> Result<Option<ClipboardPayload>, String> {
+    // Try text first, then image. If text can't be read we don't know there
+    // isn't any, so don't fall back to the image
+    match read_clipboard_target(clipboard, watchdog, ClipboardTarget::Text, retries)? {
+        Some(text) => Ok(Some(text)),
+        None => read_clipboard_target(clipboard, watchdog, ClipboardTarget::Image, retries),
+    }
+}
 
-    // Try image
-    if let Ok(img) = clipboard.get_image() {
-        let rgba = img.bytes.to_vec();
-        let width = img.width as u32;
-        let height = img.height as u32;
-        match payload::rgba_to_png(&rgba, width, height) {
-            Ok(png_data) => {
-                return Some(ClipboardPayload::Image {
+fn read_clipboard_target(
+    clipboard: &mut arboard::Clipboard,
+    watchdog: &Watchdog,
+    target: ClipboardTarget,
+    retries: u32,
+) -> Result<Option<ClipboardPayload>, String> {
+    match target {
+        ClipboardTarget::Text => {
+            let text = read_with_retry(retries, || {
+                watchdog.guard("get_text", || clipboard.get_text())
+            })
+            .map_err(|e| format!("Failed to read clipboard text: {}", e))?;
+            Ok(text
+                .filter(|text| !text.is_empty())
+                .map(ClipboardPayload::Text))
+        }
+        ClipboardTarget::Html => {
+            let html = read_with_retry(retries, || {
+                watchdog.guard("get_html", || clipboard.get().html())
+            })
+            .map_err(|e| format!("Failed to read clipboard HTML: {}", e))?;
+            Ok(html
+                .filter(|html| !html.is_empty())
+                .map(ClipboardPayload::Text))
+        }
+        ClipboardTarget::Image => {
+            let Some(img) = read_with_retry(retries, || {
+                watchdog.guard("get_image", || clipboard.get_image())
+            })
+            .map_err(|e| format!("Failed to read clipboard image: {}", e))?
+            else {
+                return Ok(None);
+            };
+            let rgba = img.bytes.to_vec();
+            let width = img.width as u32;
+            let height = img.height as u32;
+            match payload::rgba_to_png(&rgba, width, height) {
+                Ok(png_data) => Ok(Some(ClipboardPayload::Image {
                     width,
                     height,
                     png_data,
-                });
+                })),
+                Err(e) => {
+                    warn!("Failed to convert clipboard image to PNG: {}", e);
+                    Ok(None)
+                }
             }
-            Err(e) => {
-                warn!("Failed to convert clipboard image to PNG: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_event(text: &str) -> ClipboardEvent {
+        ClipboardEvent::Changed {
+            payload: ClipboardPayload::Text(text.to_string()),
+            source_app: None,
+        }
+    }
+
+    #[test]
+    fn read_retry_recovers_from_transient_errors() {
+        let mut failures = 2;
+        let result = read_with_retry(2, || {
+            if failures > 0 {
+                failures -= 1;
+                Err(arboard::Error::ClipboardOccupied)
+            } else {
+                Ok("text")
             }
+        });
+        assert!(matches!(result, Ok(Some("text"))));
+    }
+
+    #[test]
+    fn read_retry_distinguishes_empty_from_failed() {
+        let mut attempts = 0;
+        let empty = read_with_retry(3, || {
+            attempts += 1;
+            Err::<(), _>(arboard::Error::ContentNotAvailable)
+        });
+        assert!(matches!(empty, Ok(None)));
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let failed = read_with_retry(1, || {
+            attempts += 1;
+            Err::<(), _>(arboard::Error::ClipboardOccupied)
+        });
+        assert!(failed.is_err());
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn clip_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("clipsync-{}.clip", uuid::Uuid::new_v4()));
+        assert!(read_clip_file(&path, None).is_none());
+
+        write_clip_file(&path, b"hello").unwrap();
+        assert!(
+            matches!(read_clip_file(&path, None), Some(ClipboardPayload::Text(s)) if s == "hello")
+        );
+        assert!(read_clip_file(&path, Some(ClipboardTarget::Image)).is_none());
+
+        let png = payload::rgba_to_png(&[0; 3 * 2 * 4], 3, 2).unwrap();
+        write_clip_file(&path, &png).unwrap();
+        assert!(matches!(
+            read_clip_file(&path, None),
+            Some(ClipboardPayload::Image {
+                width: 3,
+                height: 2,
+                ..
+            })
+        ));
+        assert!(read_clip_file(&path, Some(ClipboardTarget::Text)).is_none());
+
+        write_clip_file(&path, b"").unwrap();
+        assert!(read_clip_file(&path, None).is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn clipboard_target_from_mime() {
+        assert_eq!(
+            ClipboardTarget::from_mime("text/html"),
+            Ok(ClipboardTarget::Html)
+        );
+        assert_eq!(
+            ClipboardTarget::from_mime("TEXT/PLAIN"),
+            Ok(ClipboardTarget::Text)
+        );
+        assert_eq!(
+            ClipboardTarget::from_mime("image/png"),
+            Ok(ClipboardTarget::Image)
+        );
+        assert!(ClipboardTarget::from_mime("application/x-foo").is_err());
+    }
+
+    #[test]
+    fn flush_pending_keeps_event_when_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut pending = Some(text_event("first"));
+        assert!(flush_pending(&tx, &mut pending));
+        assert!(pending.is_none());
+
+        pending = Some(text_event("second"));
+        assert!(flush_pending(&tx, &mut pending));
+        assert!(pending.is_some());
+
+        // A newer change replaces the deferred one and is delivered once there's room
+        pending = Some(text_event("third"));
+        let ClipboardEvent::Changed { payload, .. } = rx.try_recv().unwrap();
+        assert!(matches!(payload, ClipboardPayload::Text(s) if s == "first"));
+        assert!(flush_pending(&tx, &mut pending));
+        let ClipboardEvent::Changed { payload, .. } = rx.try_recv().unwrap();
+        assert!(matches!(payload, ClipboardPayload::Text(s) if s == "third"));
+    }
+
+    #[test]
+    fn flush_pending_reports_closed_channel() {
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        let mut pending = Some(text_event("orphan"));
+        assert!(!flush_pending(&tx, &mut pending));
+    }
+
+    #[test]
+    fn conflict_detected_on_rapid_alternation() {
+        let mut detector = ConflictDetector::default();
+        let start = Instant::now();
+        for i in 0..CONFLICT_MIN_FLIPS - 1 {
+            let hash = if i % 2 == 0 { 1 } else { 2 };
+            assert!(!detector.record(hash, start + Duration::from_millis(i as u64 * 100)));
         }
+        let now = start + Duration::from_secs(1);
+        assert!(detector.record(2, now));
+        assert!(detector.is_suppressed(now));
+
+        let later = now + CONFLICT_SUPPRESS_FOR;
+        assert!(!detector.is_suppressed(later));
+        assert!(detector.expire(later));
+        assert!(!detector.expire(later));
     }
 
-    None
+    #[test]
+    fn conflict_ignores_slow_or_varied_changes() {
+        let mut detector = ConflictDetector::default();
+        let start = Instant::now();
+        // Alternating, but spread over longer than the window
+        for i in 0..CONFLICT_MIN_FLIPS as u64 * 2 {
+            let at = start + CONFLICT_WINDOW * i as u32;
+            assert!(!detector.record(i % 2, at));
+        }
+
+        // Rapid, but more than two distinct contents
+        let mut detector = ConflictDetector::default();
+        for i in 0..CONFLICT_MIN_FLIPS as u64 * 2 {
+            assert!(!detector.record(i % 3, start + Duration::from_millis(i * 10)));
+        }
+    }
+
+    #[test]
+    fn backoff_ramps_to_max_and_snaps_back() {
+        let mut backoff = PollBackoff::new(100, 350, 2);
+        assert_eq!(backoff.interval(), Duration::from_millis(100));
+
+        backoff.on_idle();
+        assert_eq!(backoff.interval(), Duration::from_millis(100));
+        backoff.on_idle();
+        assert_eq!(backoff.interval(), Duration::from_millis(200));
+        backoff.on_idle();
+        backoff.on_idle();
+        assert_eq!(backoff.interval(), Duration::from_millis(350));
+
+        backoff.on_activity();
+        assert_eq!(backoff.interval(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_is_fixed_when_max_equals_base() {
+        let mut backoff = PollBackoff::new(500, 500, 1);
+        for _ in 0..10 {
+            backoff.on_idle();
+        }
+        assert_eq!(backoff.interval(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn backoff_max_never_below_base() {
+        let mut backoff = PollBackoff::new(1000, 500, 1);
+        backoff.on_idle();
+        assert_eq!(backoff.interval(), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn restarts_are_bounded_per_window() {
+        let start = Instant::now();
+        let mut budget = RestartBudget::default();
+        for _ in 0..MAX_CLIPBOARD_RESTARTS {
+            assert!(budget.allow(start));
+        }
+        assert!(!budget.allow(start + Duration::from_secs(1)));
+        // Once the earlier restarts age out of the window, more are allowed
+        assert!(budget.allow(start + CLIPBOARD_RESTART_WINDOW));
+    }
 }
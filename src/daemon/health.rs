@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::protocol::ThreadHealth;
+
+/// A worker thread that hasn't ticked for this long is reported as stalled,
+/// on top of its own expected quiet time.
+const STALL_GRACE: Duration = Duration::from_secs(30);
+
+/// Time a worker thread last went round its loop, shared with the main loop,
+/// and how often it was restarted after a panic.
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat {
+    /// Milliseconds since the Unix epoch; zero means it never ticked
+    last_tick: Arc<AtomicU64>,
+    restarts: Arc<AtomicU32>,
+}
+
+impl Heartbeat {
+    pub fn tick(&self) {
+        self.last_tick.store(unix_millis(), Ordering::Release);
+    }
+
+    pub fn record_restart(&self) {
+        self.restarts.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Seconds since the last tick, or None if there hasn't been one.
+    fn age_secs(&self) -> Option<u64> {
+        match self.last_tick.load(Ordering::Acquire) {
+            0 => None,
+            last => Some(unix_millis().saturating_sub(last) / 1000),
+        }
+    }
+
+    fn restarts(&self) -> u32 {
+        self.restarts.load(Ordering::Acquire)
+    }
+}
+
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A thread is healthy if it ticked within its quiet time plus `STALL_GRACE`.
+fn is_healthy(age_secs: Option<u64>, quiet_for: Duration) -> bool {
+    age_secs.is_some_and(|age| age <= (quiet_for + STALL_GRACE).as_secs())
+}
+
+/// Heartbeats of the daemon's worker threads. The socket server isn't
+/// tracked: a status request reaching the daemon shows it is up.
+pub struct DaemonHealth {
+    started: Instant,
+    /// None when `clipboard_source = "none"` and no clipboard thread runs
+    clipboard: Option<(Heartbeat, Duration)>,
+    spacetime: (Heartbeat, Duration),
+}
+
+impl DaemonHealth {
+    /// `*_quiet_for` is the longest a thread may legitimately go without
+    /// ticking, e.g. its slowest poll interval or reconnect backoff.
+    pub fn new(
+        clipboard: Option<(Heartbeat, Duration)>,
+        spacetime: Heartbeat,
+        spacetime_quiet_for: Duration,
+    ) -> Self {
+        Self {
+            started: Instant::now(),
+            clipboard,
+            spacetime: (spacetime, spacetime_quiet_for),
+        }
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    pub fn threads(&self) -> Vec<ThreadHealth> {
+        self.clipboard
+            .iter()
+            .map(|beat| ("clipboard", beat))
+            .chain(std::iter::once(("spacetimedb", &self.spacetime)))
+            .map(|(name, (heartbeat, quiet_for))| {
+                let last_tick_secs = heartbeat.age_secs();
+                ThreadHealth {
+                    name: name.to_string(),
+                    last_tick_secs,
+                    healthy: is_healthy(last_tick_secs, *quiet_for),
+                    restarts: heartbeat.restarts(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_ticked_is_unhealthy() {
+        assert!(!is_healthy(None, Duration::from_secs(60)));
+        assert!(Heartbeat::default().age_secs().is_none());
+    }
+
+    #[test]
+    fn stall_allows_quiet_time_plus_grace() {
+        let quiet_for = Duration::from_secs(60);
+        let limit = (quiet_for + STALL_GRACE).as_secs();
+        assert!(is_healthy(Some(0), quiet_for));
+        assert!(is_healthy(Some(limit), quiet_for));
+        assert!(!is_healthy(Some(limit + 1), quiet_for));
+    }
+
+    #[test]
+    fn tick_resets_age() {
+        let heartbeat = Heartbeat::default();
+        heartbeat.tick();
+        assert_eq!(heartbeat.age_secs(), Some(0));
+    }
+
+    #[test]
+    fn restarts_are_shared_with_clones() {
+        let heartbeat = Heartbeat::default();
+        heartbeat.clone().record_restart();
+        assert_eq!(heartbeat.restarts(), 1);
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use std::sync::mpsc;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use crate::history::{self, HistoryEntry, Retention};
+use crate::payload::ClipboardPayload;
+
+enum HistoryCommand {
+    Append(HistoryEntry),
+    Delete {
+        index: usize,
+        timestamp: u64,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+}
+
+/// Queues local history writes for a thread of their own. Encrypting and
+/// rewriting the history file can take a while for a big history, so the main
+/// loop never does it; writes still land in the order they were queued.
+pub struct HistoryWriter {
+    tx: mpsc::Sender<HistoryCommand>,
+    retention: Option<Retention>,
+}
+
+impl HistoryWriter {
+    /// Start the writer thread. With `retention` unset nothing is recorded,
+    /// but entries left from before can still be deleted.
+    pub fn spawn(identity: age::x25519::Identity, retention: Option<Retention>) -> Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("history".to_string())
+            .spawn(move || {
+                // Counted from the file when first needed, then kept up to
+                // date by each append
+                let mut stats = None;
+                for command in rx {
+                    match command {
+                        HistoryCommand::Append(entry) => {
+                            let Some(retention) = retention else {
+                                continue;
+                            };
+                            let file_stats = match stats.take().map_or_else(history::file_stats, Ok)
+                            {
+                                Ok(file_stats) => stats.insert(file_stats),
+                                Err(e) => {
+                                    warn!("Failed to read local history: {}", e);
+                                    continue;
+                                }
+                            };
+                            if let Err(e) =
+                                history::append(&entry, &identity, retention, file_stats)
+                            {
+                                warn!("Failed to write local history: {}", e);
+                                // The write may have got partway; count again
+                                stats = None;
+                            }
+                        }
+                        HistoryCommand::Delete {
+                            index,
+                            timestamp,
+                            reply,
+                        } => {
+                            let result = history::delete(&identity, index, timestamp)
+                                .map_err(|e| e.to_string());
+                            stats = None;
+                            let _ = reply.send(result);
+                        }
+                    }
+                }
+            })?;
+        Ok(Self { tx, retention })
+    }
+
+    /// Append a clip, and the app it was copied from if known, to the local
+    /// history if local history is enabled.
+    pub fn record(&self, payload: &ClipboardPayload, source_app: Option<String>) {
+        if self.retention.is_none() {
+            return;
+        }
+        // Timestamped now, not when the thread gets to it
+        let _ = self.tx.send(HistoryCommand::Append(HistoryEntry::new(
+            payload.clone(),
+            source_app,
+        )));
+    }
+
+    /// Remove a history entry once every append queued before it is written.
+    pub async fn delete(&self, index: usize, timestamp: u64) -> Result<(), String> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.tx
+            .send(HistoryCommand::Delete {
+                index,
+                timestamp,
+                reply,
+            })
+            .map_err(|_| "The history writer has stopped".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "The history writer has stopped".to_string())?
+    }
+}
@@ -1,29 +1,187 @@
+pub mod active_app;
 pub mod clipboard;
+pub mod health;
+pub mod history_writer;
+pub mod session;
 pub mod socket;
 pub mod spacetime;
+pub mod status_file;
 
 use anyhow::Result;
-use tokio::sync::{mpsc, oneshot, watch};
-use tracing::{error, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tracing::{debug, error, info, warn};
 
 use crate::config::{self, Config};
 use crate::crypto;
-use crate::module_bindings::ClipContentType;
-use crate::payload::ClipboardPayload;
-use crate::protocol::{DeviceInfo, Request, Response};
+use crate::module_bindings::{
+    ClipAck, ClipContentType, ClipOptions, CurrentClip, TeamMemberView, TeamView,
+};
+use crate::payload::{self, ClipboardPayload, ContentType};
+use crate::protocol::{
+    DeviceInfo, LocalClipboard, RecentClip, Request, Response, TeamInfo, WaitConfirm,
+    CLOCK_SKEW_WARN_SECS,
+};
 
-use self::clipboard::{ClipboardCommand, ClipboardEvent};
+use self::clipboard::{ClipboardCommand, ClipboardEvent, ClipboardTarget};
+use self::history_writer::HistoryWriter;
 use self::socket::SocketRequest;
 use self::spacetime::{SpacetimeCommand, SpacetimeEvent};
 
-pub async fn run_daemon(config: Config) -> Result<()> {
+/// Characters of clip content logged when `log_previews` is enabled.
+const LOG_PREVIEW_CHARS: usize = 40;
+
+/// Characters of a text clip shown by `clipsync paste --peek`.
+const PEEK_PREVIEW_CHARS: usize = 80;
+
+/// How long `clipsync recent --restore` waits for the restored clip to come
+/// back from the server.
+const RESTORE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long to wait for the clipboard thread to answer before giving up.
+const CLIPBOARD_REPLY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long to wait for SpacetimeDB to answer a consume, in case the
+/// connection stalls; the server's own errors come back as a reply.
+const CONSUME_REPLY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+const KEY_MISMATCH_MESSAGE: &str =
+    "This device's key doesn't match the one on the server, so synced clips would be unreadable. \
+     Run `clipsync setup` again to fetch the current key.";
+
+/// How long shutdown waits for the SpacetimeDB thread to close its connection.
+const SPACETIME_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Our own clip only measures clock skew if it comes back this soon after we
+/// sent it, so its server timestamp is close to the local time of arrival.
+const CLOCK_SKEW_SAMPLE_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Local time minus a server timestamp taken moments ago, in whole seconds.
+fn clock_skew_secs(server_micros: i64) -> i64 {
+    let local_micros = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0);
+    (local_micros - server_micros) / 1_000_000
+}
+
+/// The last clip this device synced, for `clipsync copy --dedup-window`.
+#[derive(Debug, Clone, Copy)]
+struct RecentSync {
+    hash: u64,
+    at: std::time::Instant,
+}
+
+impl RecentSync {
+    fn now(payload: &ClipboardPayload) -> Self {
+        Self {
+            hash: payload.content_hash(),
+            at: std::time::Instant::now(),
+        }
+    }
+
+    /// Whether `payload` repeats this sync within `window`.
+    fn is_duplicate(&self, payload: &ClipboardPayload, window: std::time::Duration) -> bool {
+        self.hash == payload.content_hash() && self.at.elapsed() < window
+    }
+}
+
+/// Whether incoming clips are kept off the local clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Freeze {
+    Off,
+    /// Frozen until `clipsync unfreeze`
+    Indefinite,
+    Until(std::time::Instant),
+}
+
+impl Freeze {
+    fn is_active(self, now: std::time::Instant) -> bool {
+        match self {
+            Freeze::Off => false,
+            Freeze::Indefinite => true,
+            Freeze::Until(until) => now < until,
+        }
+    }
+
+    /// Time left on a timed freeze, rounded up to whole seconds.
+    fn remaining_secs(self, now: std::time::Instant) -> Option<u64> {
+        match self {
+            Freeze::Until(until) if now < until => {
+                Some((until - now).as_millis().div_ceil(1000) as u64)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The main loop's state that socket requests read or change.
+struct DaemonState {
+    connected: bool,
+    user_id: Option<u64>,
+    cached_username: Option<String>,
+    freeze: Freeze,
+    /// Whether our key is the one the server has; None until checked after subscribing
+    key_matches_server: Option<bool>,
+    /// Last clip synced from this device and when, for copy's dedup window
+    recent_sync: Option<RecentSync>,
+    /// Local clock minus the server's, sampled from our own clips coming back
+    clock_skew: Option<i64>,
+    /// Latest clip this device accepted: its own, or one passing the trust,
+    /// primary and routing checks
+    latest_clip: Option<CurrentClip>,
+}
+
+/// What `handle_request` reads but never changes: settings fixed at startup,
+/// and flags the clipboard thread and apply tasks update.
+struct RequestContext<'a> {
+    device_id: &'a str,
+    watching: bool,
+    max_clip_bytes: usize,
+    min_sync_bytes: usize,
+    history: Option<&'a HistoryWriter>,
+    age_identity: Option<&'a age::x25519::Identity>,
+    health: &'a health::DaemonHealth,
+    clipboard_conflict: &'a AtomicBool,
+    last_apply_error: &'a Mutex<Option<String>>,
+}
+
+/// Handles to the daemon's other threads and tasks.
+struct DaemonChannels<'a> {
+    stdb_cmd_tx: &'a crossbeam_channel::Sender<SpacetimeCommand>,
+    clip_cmd_tx: &'a std::sync::mpsc::Sender<ClipboardCommand>,
+    shutdown_tx: &'a watch::Sender<bool>,
+}
+
+/// Whether the daemon runs until shut down or exits after its first sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    Persistent,
+    /// Exit after the first outbound sync or inbound clip, succeeding only if
+    /// it was processed without error. Useful for scripted end-to-end tests.
+    Once,
+}
+
+pub async fn run_daemon(config: Config, mode: RunMode) -> Result<()> {
     let device_id = config::load_device_id()?
         .ok_or_else(|| anyhow::anyhow!("Device not set up. Run `clipsync setup` first."))?;
     let token = config::load_token()?;
-    let user_id = config::load_user_id()?
-        .ok_or_else(|| anyhow::anyhow!("Not logged in. Run `clipsync setup` first."))?;
+    // A token without a user_id is a half-finished setup; the user_id is
+    // recovered from the server profile once connected
+    let user_id = config::load_user_id()?;
+    if user_id.is_none() && token.is_none() {
+        anyhow::bail!("Not logged in. Run `clipsync setup` first.");
+    }
+
+    // Single-instance guard; the socket check alone races when two daemons start together
+    let _lock = config::acquire_lock("daemon.lock")
+        .map_err(|_| anyhow::anyhow!("Another daemon is already running"))?;
 
-    info!("Starting daemon with device_id={}, user_id={}", device_id, user_id);
+    info!(device_id = %device_id, user_id = ?user_id, "Starting daemon");
+    if user_id.is_none() {
+        warn!("No user_id saved, will recover it from the server after connecting");
+    }
 
     // Channels for SpacetimeDB
     let (stdb_event_tx, mut stdb_event_rx) = mpsc::channel::<SpacetimeEvent>(32);
@@ -37,19 +195,91 @@ pub async fn run_daemon(config: Config) -> Result<()> {
     let (socket_req_tx, mut socket_req_rx) = mpsc::channel::<SocketRequest>(32);
 
     // Spawn SpacetimeDB connection thread
-    spacetime::spawn_spacetime_thread(&config, token, stdb_event_tx, stdb_cmd_rx)?;
+    let spacetime_heartbeat = health::Heartbeat::default();
+    let spacetime_thread = spacetime::spawn_spacetime_thread(
+        &config,
+        token,
+        stdb_event_tx,
+        stdb_cmd_rx,
+        spacetime_heartbeat.clone(),
+    )?;
 
     // Spawn clipboard watcher thread
-    clipboard::spawn_clipboard_watcher(config.poll_interval_ms, clip_event_tx, clip_cmd_rx)?;
+    let backoff = clipboard::PollBackoff::new(
+        config.poll_interval_ms,
+        config.poll_interval_max_ms,
+        config.poll_backoff_idle_cycles,
+    );
+    let clipboard_conflict = Arc::new(AtomicBool::new(false));
+    // Set by the tasks waiting on incoming clips' SetClipboard replies
+    let last_apply_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let clipboard_heartbeat = health::Heartbeat::default();
+    clipboard::spawn_clipboard_watcher(
+        config.clipboard_source.clone(),
+        backoff,
+        clip_event_tx,
+        clip_cmd_rx,
+        clipboard_conflict.clone(),
+        clipboard_heartbeat.clone(),
+        config.clipboard_read_retries,
+        config.share_source_app,
+    )?;
+    let health = health::DaemonHealth::new(
+        (config.clipboard_source != config::ClipboardSource::None).then(|| {
+            let slowest_poll = config.poll_interval_max_ms.max(config.poll_interval_ms);
+            (
+                clipboard_heartbeat,
+                std::time::Duration::from_millis(slowest_poll),
+            )
+        }),
+        spacetime_heartbeat,
+        spacetime::MAX_BACKOFF,
+    );
+    let max_clip_bytes = config.clip_size_limit();
+
+    // Session lock monitor, only when pausing on lock is configured
+    let (lock_tx, mut lock_rx) = mpsc::channel::<bool>(4);
+    if config.pause_when_locked {
+        session::spawn_lock_monitor(lock_tx)?;
+    }
 
     // Shutdown channel for graceful shutdown
     let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
 
     // Spawn socket server
-    let mut socket_handle = tokio::spawn(socket::run_socket_server(socket_req_tx));
+    let mut socket_handle = tokio::spawn(socket::run_socket_server(
+        socket_req_tx,
+        config.ipc_connection_limit(),
+    ));
 
     // State
-    let mut connected = false;
+    let mut state = DaemonState {
+        connected: false,
+        user_id,
+        cached_username: None,
+        freeze: Freeze::Off,
+        key_matches_server: None,
+        recent_sync: None,
+        clock_skew: None,
+        latest_clip: None,
+    };
+    // Session locked with `pause_when_locked` set: no syncing in either direction
+    let mut session_locked = false;
+    // Content hash of the clip the server currently holds as far as this device
+    // knows: the last one synced from here or received from elsewhere. Only an
+    // immediately repeated change is skipped, so copying X, then Y, then X again
+    // still syncs X the second time.
+    let mut last_synced_hash: Option<u64> = None;
+    // Server timestamp of the primary device's latest clip, if one is configured
+    let mut primary_last_micros: Option<i64> = None;
+    // Announces each clip this device accepts (see `DaemonState::latest_clip`)
+    // to `clipsync paste --wait` requests
+    let (paste_wait_tx, _) = broadcast::channel::<CurrentClip>(8);
+    // Every clip the server stores, for copy --wait-confirm and restore,
+    // which track the server's clip whoever sent it
+    let (clip_wait_tx, _) = broadcast::channel::<CurrentClip>(8);
+    // Other devices' acks of the current clip, for `clipsync copy --wait-confirm`
+    let (ack_wait_tx, _) = broadcast::channel::<ClipAck>(32);
 
     // Load encryption identity
     let age_identity = match crypto::load_private_key() {
@@ -60,18 +290,57 @@ pub async fn run_daemon(config: Config) -> Result<()> {
         }
     };
 
-    let watching = config.watch_clipboard;
+    // Without a clipboard source there is nothing to watch
+    let watching =
+        config.watch_clipboard && config.clipboard_source != config::ClipboardSource::None;
+    // History entries are encrypted, so there is no history without the key
+    let history = match &age_identity {
+        Some(id) => Some(HistoryWriter::spawn(
+            id.clone(),
+            config.local_history_retention(),
+        )?),
+        None => None,
+    };
+
+    // Outcome of the most recent sync/receive, used to end a `RunMode::Once` run
+    let mut sync_outcome: Option<Result<(), String>> = None;
+    let mut once_result = Ok(());
 
-    info!("Daemon main loop started (watching={})", watching);
+    let mut status_file = config.write_status_file.then(status_file::StatusFile::new);
+
+    info!(
+        "Daemon main loop started (watching={}, mode={:?})",
+        watching, mode
+    );
 
     loop {
+        if let Some(status_file) = &mut status_file {
+            status_file.update(status_file::Indicator {
+                connected: state.connected,
+                watching,
+                frozen: state.freeze.is_active(std::time::Instant::now()),
+                locked: session_locked,
+            });
+        }
+
+        if mode == RunMode::Once {
+            if let Some(outcome) = sync_outcome.take() {
+                match &outcome {
+                    Ok(()) => info!("Single sync completed, exiting"),
+                    Err(e) => error!("Single sync failed: {}", e),
+                }
+                once_result = outcome;
+                break;
+            }
+        }
+
         tokio::select! {
             // SpacetimeDB events
             Some(event) = stdb_event_rx.recv() => {
                 match event {
                     SpacetimeEvent::Connected { identity: id, token: tok } => {
-                        info!("Connected as {}", id.to_hex());
-                        connected = true;
+                        info!(identity = %id.to_hex(), "Connected to SpacetimeDB");
+                        state.connected = true;
 
                         // Save the token
                         if let Err(e) = config::save_token(&tok) {
@@ -86,32 +355,176 @@ pub async fn run_daemon(config: Config) -> Result<()> {
                     }
                     SpacetimeEvent::Disconnected => {
                         warn!("Disconnected from SpacetimeDB, auto-reconnect will be attempted");
-                        connected = false;
+                        state.connected = false;
+                    }
+                    SpacetimeEvent::AuthExpired => {
+                        error!(
+                            "Login expired or was revoked. \
+                             Re-run `clipsync setup` to sign in again."
+                        );
+                        state.connected = false;
+                        if state.user_id.is_none() {
+                            once_result =
+                                Err("Not logged in. Run `clipsync setup` first.".to_string());
+                            break;
+                        }
+                        sync_outcome =
+                            Some(Err("SpacetimeDB rejected the saved token".to_string()));
                     }
                     SpacetimeEvent::SubscriptionApplied => {
+                        if state.user_id.is_none() {
+                            state.user_id = recover_user_id(&stdb_cmd_tx).await;
+                            if state.user_id.is_none() {
+                                let message = "Not logged in: the server has no profile \
+                                               for the saved token. Run `clipsync setup` first.";
+                                once_result = Err(message.to_string());
+                                break;
+                            }
+                        }
                         info!("Subscription applied, ready to sync");
+                        state.key_matches_server =
+                            check_key_matches_server(&stdb_cmd_tx, age_identity.as_ref()).await;
+                    }
+                    SpacetimeEvent::ClipRemoved { updated_at } => {
+                        // Cleared, consumed or expired: no longer there to paste offline
+                        if state
+                            .latest_clip
+                            .as_ref()
+                            .is_some_and(|clip| clip.updated_at == updated_at)
+                        {
+                            state.latest_clip = None;
+                        }
+                    }
+                    SpacetimeEvent::ClipAcked(ack) => {
+                        debug!(device_id = %ack.device_id, "Clip acknowledged");
+                        let _ = ack_wait_tx.send(ack);
                     }
                     SpacetimeEvent::ClipUpdated(clip) => {
-                        // Ignore our own syncs from this device
+                        let updated_at = clip.updated_at.to_micros_since_unix_epoch();
+                        let _ = clip_wait_tx.send(clip.clone());
+                        // Tracked before the own-sync check so a primary device holds
+                        // off the others on its own machine too
+                        if config.is_primary_device(&clip.sender_device_id) {
+                            primary_last_micros = Some(updated_at);
+                        }
+
+                        // Ignore our own syncs from this device, after using fresh
+                        // ones to check the local clock against the server's
                         if clip.sender_device_id == device_id {
+                            if state
+                                .recent_sync
+                                .is_some_and(|recent| {
+                                    recent.at.elapsed() < CLOCK_SKEW_SAMPLE_WINDOW
+                                })
+                            {
+                                let skew = clock_skew_secs(updated_at);
+                                let was_skewed = state
+                                    .clock_skew
+                                    .is_some_and(|prev| prev.abs() >= CLOCK_SKEW_WARN_SECS);
+                                if skew.abs() >= CLOCK_SKEW_WARN_SECS && !was_skewed {
+                                    warn!(
+                                        "Local clock is {}s off from the server's; \
+                                         expiries and lockouts may look wrong",
+                                        skew
+                                    );
+                                }
+                                state.clock_skew = Some(skew);
+                            }
+                            state.latest_clip = Some(clip.clone());
+                            let _ = paste_wait_tx.send(clip);
+                            continue;
+                        }
+
+                        if !config.is_trusted_sender(&clip.sender_device_id) {
+                            debug!(
+                                "Ignoring clip from untrusted device {}",
+                                clip.sender_device_id
+                            );
+                            continue;
+                        }
+
+                        info!(
+                            sender_device_id = %clip.sender_device_id,
+                            size_bytes = clip.size_bytes,
+                            intended_device = ?clip.intended_device,
+                            source_app = ?clip.source_app,
+                            "Received clip update"
+                        );
+                        match &clip.intended_device {
+                            Some(name) if name.eq_ignore_ascii_case(&hostname()) => {
+                                info!("This clip was meant for this device");
+                            }
+                            Some(name) => info!("This clip was meant for {}", name),
+                            None => {}
+                        }
+
+                        if !config.is_primary_device(&clip.sender_device_id)
+                            && !config.allows_non_primary(updated_at, primary_last_micros)
+                        {
+                            info!(
+                                "Ignoring clip from device {} \
+                                 (too soon after the primary device's clip)",
+                                clip.sender_device_id
+                            );
+                            continue;
+                        }
+
+                        let content_type = content_type_str(&clip.content_type);
+                        if !config.route(content_type).allows_receive() {
+                            info!("Ignoring incoming {} clip (routing rule)", content_type);
                             continue;
                         }
 
-                        info!("Received clip update from device {}", clip.sender_device_id);
+                        // Only now can paste --wait hand the clip out
+                        state.latest_clip = Some(clip.clone());
+                        let _ = paste_wait_tx.send(clip.clone());
 
-                        if let Some(age_id) = &age_identity {
-                            match crypto::decrypt(&clip.encrypted_data, age_id) {
-                                Ok(plaintext) => {
-                                    match ClipboardPayload::deserialize(&plaintext) {
-                                        Ok(payload) => {
-                                            let _ = clip_cmd_tx.send(
-                                                ClipboardCommand::SetClipboard { payload },
-                                            );
+                        match decrypt_payload(&clip.encrypted_data, age_identity.as_ref()) {
+                            Ok(payload) => {
+                                last_synced_hash = Some(payload.content_hash());
+                                log_clip(&config, "Applying incoming clip", &payload);
+                                record_local_history(
+                                    history.as_ref(),
+                                    &payload,
+                                    clip.source_app.clone(),
+                                );
+                                if state.freeze.is_active(std::time::Instant::now()) {
+                                    info!("Clipboard is frozen, not applying incoming clip");
+                                } else if session_locked {
+                                    info!("Session is locked, not applying incoming clip");
+                                } else {
+                                    let (reply_tx, reply_rx) = oneshot::channel();
+                                    let _ = clip_cmd_tx.send(ClipboardCommand::SetClipboard {
+                                        payload,
+                                        reply: Some(reply_tx),
+                                    });
+                                    // Kept for status until the next incoming clip applies
+                                    let last_apply_error = last_apply_error.clone();
+                                    // Applied clips are acked for a sender waiting on
+                                    // --wait-confirm
+                                    let ack = SpacetimeCommand::AckClip {
+                                        device_id: device_id.clone(),
+                                        clip_updated_at: clip.updated_at,
+                                    };
+                                    let stdb_cmd_tx = stdb_cmd_tx.clone();
+                                    tokio::spawn(async move {
+                                        let result = reply_rx.await.unwrap_or_else(|_| {
+                                            Err("Clipboard thread not available".to_string())
+                                        });
+                                        if result.is_ok() {
+                                            let _ = stdb_cmd_tx.send(ack);
                                         }
-                                        Err(e) => error!("Failed to deserialize clip: {}", e),
-                                    }
+                                        let mut last_error = last_apply_error
+                                            .lock()
+                                            .unwrap_or_else(|e| e.into_inner());
+                                        *last_error = result.err();
+                                    });
                                 }
-                                Err(e) => error!("Failed to decrypt clip: {}", e),
+                                sync_outcome = Some(Ok(()));
+                            }
+                            Err(e) => {
+                                error!("{}", e);
+                                sync_outcome = Some(Err(e));
                             }
                         }
                     }
@@ -121,37 +534,220 @@ pub async fn run_daemon(config: Config) -> Result<()> {
             // Clipboard events (only process if watching is enabled)
             Some(event) = clip_event_rx.recv(), if watching => {
                 match event {
-                    ClipboardEvent::Changed { payload } => {
-                        if !connected {
+                    ClipboardEvent::Changed { payload, source_app } => {
+                        log_clip(&config, "Local clipboard changed", &payload);
+                        record_local_history(history.as_ref(), &payload, source_app.clone());
+
+                        if !state.connected {
+                            continue;
+                        }
+
+                        if session_locked {
+                            debug!("Not syncing local change while the session is locked");
+                            continue;
+                        }
+
+                        if state.key_matches_server == Some(false) {
+                            debug!(
+                                "Not syncing local change, \
+                                 this device's key doesn't match the server's"
+                            );
+                            continue;
+                        }
+
+                        if !config.route(payload.content_type_str()).allows_send() {
+                            debug!(
+                                "Not syncing local {} change (routing rule)",
+                                payload.content_type_str()
+                            );
+                            continue;
+                        }
+
+                        // Outside the configured size range is skipped, not an error
+                        let size = payload.serialized_size().unwrap_or(usize::MAX);
+                        let in_range = payload::check_clip_minimum(size, config.min_sync_bytes)
+                            .and_then(|()| payload::check_clip_limit(size, max_clip_bytes));
+                        if let Err(reason) = in_range {
+                            debug!("Not syncing local change: {}", reason);
+                            continue;
+                        }
+
+                        let hash = payload.content_hash();
+                        if last_synced_hash == Some(hash) {
+                            debug!("Not syncing local change identical to the last synced clip");
                             continue;
                         }
 
-                        if let Err(e) = encrypt_and_sync(
+                        let options = ClipOptions {
+                            intended_device: None,
+                            ttl_secs: None,
+                            source_app,
+                        };
+                        let result = encrypt_and_sync(
                             &payload,
                             &device_id,
+                            options,
+                            max_clip_bytes,
                             age_identity.as_ref(),
                             &stdb_cmd_tx,
-                        ) {
-                            error!("Failed to sync clipboard: {}", e);
+                        );
+                        match &result {
+                            Ok(()) => {
+                                last_synced_hash = Some(hash);
+                                state.recent_sync = Some(RecentSync::now(&payload));
+                            }
+                            Err(e) => error!("Failed to sync clipboard: {}", e),
                         }
+                        sync_outcome = Some(result);
                     }
                 }
             }
 
             // Socket requests from CLI
             Some(req) = socket_req_rx.recv() => {
-                let response = handle_request(
-                    req.request,
-                    connected,
-                    user_id,
-                    &device_id,
+                // Waiting for a clip must not hold up the main loop
+                if let Request::PasteWait { after_micros, timeout_secs } = req.request {
+                    if !state.connected {
+                        let _ = req.reply.send(Response::Error {
+                            message: "Not connected to SpacetimeDB".to_string(),
+                        });
+                        continue;
+                    }
+                    let current = state.latest_clip.as_ref().filter(|clip| {
+                        after_micros.is_some_and(|after| {
+                            clip.updated_at.to_micros_since_unix_epoch() > after
+                        })
+                    });
+                    match current {
+                        Some(clip) => {
+                            let _ = req.reply.send(decrypt_clip_response(
+                                &clip.encrypted_data,
+                                age_identity.as_ref(),
+                            ));
+                        }
+                        None => spawn_paste_waiter(
+                            paste_wait_tx.subscribe(),
+                            after_micros,
+                            timeout_secs,
+                            age_identity.clone(),
+                            req.reply,
+                        ),
+                    }
+                    continue;
+                }
+
+                // Offline, a plain paste falls back to the last clip this device
+                // accepted, unless another device sent it for someone else
+                if !state.connected && matches!(req.request, Request::Paste { consume: false }) {
+                    let cached = state.latest_clip.as_ref().filter(|clip| {
+                        let meant_for_us = clip.sender_device_id == device_id
+                            || clip
+                                .intended_device
+                                .as_ref()
+                                .is_none_or(|name| name.eq_ignore_ascii_case(&hostname()));
+                        meant_for_us && !spacetime::clip_expired(clip)
+                    });
+                    if let Some(clip) = cached {
+                        let decrypted =
+                            decrypt_clip_response(&clip.encrypted_data, age_identity.as_ref());
+                        let response = match decrypted {
+                            Response::ClipData {
+                                content_type, data, ..
+                            } => Response::ClipData {
+                                content_type,
+                                data,
+                                cached: true,
+                            },
+                            other => other,
+                        };
+                        let _ = req.reply.send(response);
+                        continue;
+                    }
+                }
+
+                // Restoring waits for the clip to come back through this loop
+                if let Request::RestoreDeviceClip { sender_device_id } = req.request {
+                    if !state.connected {
+                        let _ = req.reply.send(Response::Error {
+                            message: "Not connected to SpacetimeDB".to_string(),
+                        });
+                        continue;
+                    }
+                    // The server clip is about to change behind the dedup's back
+                    last_synced_hash = None;
+                    spawn_restore_waiter(
+                        clip_wait_tx.subscribe(),
+                        sender_device_id,
+                        device_id.clone(),
+                        age_identity.clone(),
+                        stdb_cmd_tx.clone(),
+                        clip_cmd_tx.clone(),
+                        req.reply,
+                    );
+                    continue;
+                }
+
+                let is_copy = matches!(req.request, Request::Copy { local_only: false, .. });
+                // Subscribed before the copy syncs, so its clip and acks can't be missed
+                let confirm = match &req.request {
+                    Request::Copy {
+                        local_only: false,
+                        wait_confirm: Some(wait),
+                        ..
+                    } => Some((*wait, clip_wait_tx.subscribe(), ack_wait_tx.subscribe())),
+                    _ => None,
+                };
+                let sets_server_clip = is_copy || matches!(req.request, Request::Restore { .. });
+                let context = RequestContext {
+                    device_id: &device_id,
                     watching,
-                    age_identity.as_ref(),
-                    &stdb_cmd_tx,
-                    &clip_cmd_tx,
-                    &shutdown_tx,
-                ).await;
-                let _ = req.reply.send(response);
+                    max_clip_bytes,
+                    min_sync_bytes: config.min_sync_bytes,
+                    history: history.as_ref(),
+                    age_identity: age_identity.as_ref(),
+                    health: &health,
+                    clipboard_conflict: &clipboard_conflict,
+                    last_apply_error: &last_apply_error,
+                };
+                let channels = DaemonChannels {
+                    stdb_cmd_tx: &stdb_cmd_tx,
+                    clip_cmd_tx: &clip_cmd_tx,
+                    shutdown_tx: &shutdown_tx,
+                };
+                let response = handle_request(req.request, &mut state, &context, &channels).await;
+                if sets_server_clip {
+                    // The server clip may have changed behind the dedup's back
+                    last_synced_hash = None;
+                }
+                if is_copy {
+                    sync_outcome = Some(match &response {
+                        Response::Ok
+                        | Response::NothingToCopy { .. }
+                        | Response::SkippedDuplicate => Ok(()),
+                        Response::Error { message } => Err(message.clone()),
+                        _ => Err("Unexpected response to copy".to_string()),
+                    });
+                }
+                match (confirm, response) {
+                    (Some((wait, clips, acks)), Response::Ok) => {
+                        spawn_confirm_waiter(clips, acks, device_id.clone(), wait, req.reply);
+                    }
+                    (_, response) => {
+                        let _ = req.reply.send(response);
+                    }
+                }
+            }
+
+            // Session lock changes
+            Some(locked) = lock_rx.recv() => {
+                if locked != session_locked {
+                    info!(
+                        "Session {}, {} clipboard sync",
+                        if locked { "locked" } else { "unlocked" },
+                        if locked { "pausing" } else { "resuming" }
+                    );
+                }
+                session_locked = locked;
             }
 
             // Graceful shutdown signal
@@ -174,11 +770,24 @@ pub async fn run_daemon(config: Config) -> Result<()> {
         }
     }
 
+    // Closing the command channel tells the SpacetimeDB thread to disconnect.
+    // Drop the event receiver too so its disconnect callback can't block.
+    drop(stdb_cmd_tx);
+    drop(stdb_event_rx);
+    let join = tokio::task::spawn_blocking(move || spacetime_thread.join());
+    match tokio::time::timeout(SPACETIME_SHUTDOWN_TIMEOUT, join).await {
+        Ok(_) => debug!("SpacetimeDB thread stopped"),
+        Err(_) => warn!("SpacetimeDB thread didn't stop in time, exiting anyway"),
+    }
+
     // Cleanup socket
     let path = config::socket_path();
     let _ = std::fs::remove_file(&path);
+    if let Some(status_file) = &status_file {
+        status_file.remove();
+    }
 
-    Ok(())
+    once_result.map_err(|e| anyhow::anyhow!(e))
 }
 
 /// Send a command to the SpacetimeDB thread and return the reply channel.
@@ -199,84 +808,329 @@ fn send_stdb_command<T>(
 
 async fn handle_request(
     request: Request,
-    connected: bool,
-    user_id: u64,
-    device_id: &str,
-    watching: bool,
-    age_identity: Option<&age::x25519::Identity>,
-    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
-    clip_cmd_tx: &std::sync::mpsc::Sender<ClipboardCommand>,
-    shutdown_tx: &watch::Sender<bool>,
+    state: &mut DaemonState,
+    context: &RequestContext<'_>,
+    channels: &DaemonChannels<'_>,
 ) -> Response {
+    let DaemonChannels {
+        stdb_cmd_tx,
+        clip_cmd_tx,
+        shutdown_tx,
+    } = *channels;
     match request {
-        Request::Status => {
-            // Look up username from SpacetimeDB
-            let username = if let Some(reply_rx) =
+        Request::Status {
+            socket_only,
+            local_size,
+        } => {
+            // Look up username from SpacetimeDB, unless only local state was asked for
+            let username = if socket_only {
+                state.cached_username.clone()
+            } else if let Some(reply_rx) =
                 send_stdb_command(stdb_cmd_tx, |reply| SpacetimeCommand::GetUsername { reply })
             {
-                reply_rx.await.ok().flatten()
+                let username = reply_rx.await.ok().flatten();
+                if username.is_some() {
+                    state.cached_username = username.clone();
+                }
+                username
+            } else {
+                None
+            };
+
+            let failed_login_attempts = if socket_only {
+                None
+            } else if let Some(reply_rx) = send_stdb_command(stdb_cmd_tx, |reply| {
+                SpacetimeCommand::GetFailedLoginAttempts { reply }
+            }) {
+                reply_rx.await.ok()
+            } else {
+                None
+            };
+
+            let local_clipboard = if local_size {
+                measure_local_clipboard(clip_cmd_tx).await
             } else {
                 None
             };
 
+            let now = std::time::Instant::now();
+            let latest_clip = state.latest_clip.as_ref();
             Response::Status {
-                connected,
+                connected: state.connected,
                 username,
-                user_id: Some(user_id),
-                device_id: device_id.to_string(),
-                watching,
+                user_id: state.user_id,
+                device_id: context.device_id.to_string(),
+                watching: context.watching,
+                clipboard_conflict: context.clipboard_conflict.load(Ordering::Acquire),
+                last_apply_error: context
+                    .last_apply_error
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone(),
+                frozen: state.freeze.is_active(now),
+                frozen_remaining_secs: state.freeze.remaining_secs(now),
+                last_clip_intended_device: latest_clip
+                    .and_then(|clip| clip.intended_device.clone()),
+                last_clip_source_app: latest_clip.and_then(|clip| clip.source_app.clone()),
+                key_matches_server: state.key_matches_server,
+                failed_login_attempts,
+                clock_skew_secs: state.clock_skew,
+                uptime_secs: Some(context.health.uptime_secs()),
+                threads: context.health.threads(),
+                local_clipboard,
             }
         }
 
-        Request::Copy { data } => {
-            let payload = if let Some(data) = data {
-                // Data provided (from stdin)
-                ClipboardPayload::Text(String::from_utf8_lossy(&data).into_owned())
+        Request::Freeze { for_secs } => {
+            state.freeze = match for_secs {
+                Some(secs) => {
+                    info!("Clipboard frozen for {}s", secs);
+                    Freeze::Until(std::time::Instant::now() + std::time::Duration::from_secs(secs))
+                }
+                None => {
+                    info!("Clipboard frozen");
+                    Freeze::Indefinite
+                }
+            };
+            Response::Ok
+        }
+
+        Request::ClearLocal => {
+            let _ = clip_cmd_tx.send(ClipboardCommand::Clear);
+            Response::Ok
+        }
+
+        Request::Unfreeze => {
+            state.freeze = Freeze::Off;
+            info!("Clipboard unfrozen");
+            Response::Ok
+        }
+
+        Request::Copy {
+            data,
+            append,
+            html,
+            local_only,
+            target,
+            allow_empty,
+            clear_on_empty,
+            target_device,
+            dedup_window_ms,
+            ttl_secs,
+            max_size,
+            apply_min_size,
+            wait_confirm: _,
+        } => {
+            if local_only && data.is_none() {
+                return Response::Error {
+                    message: "Local-only copy needs data piped on stdin".to_string(),
+                };
+            }
+
+            let mut payload = if html {
+                let html = match data.map(String::from_utf8) {
+                    Some(Ok(html)) => html,
+                    Some(Err(_)) => {
+                        return Response::Error {
+                            message: "HTML must be valid UTF-8".to_string(),
+                        };
+                    }
+                    None => {
+                        return Response::Error {
+                            message: "HTML copy needs data piped on stdin".to_string(),
+                        };
+                    }
+                };
+                let alt_text = payload::html_to_text(&html);
+                let _ = clip_cmd_tx.send(ClipboardCommand::SetHtml {
+                    html,
+                    alt_text: alt_text.clone(),
+                });
+                // Other devices only get the text; the HTML stays on this machine
+                ClipboardPayload::Text(alt_text)
             } else {
-                // Read from system clipboard
-                let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
-                if clip_cmd_tx
-                    .send(ClipboardCommand::ReadClipboard { reply: reply_tx })
-                    .is_err()
+                let target = match target
+                    .as_deref()
+                    .map(ClipboardTarget::from_mime)
+                    .transpose()
                 {
-                    return Response::Error {
-                        message: "Clipboard thread not available".to_string(),
-                    };
-                }
-                match reply_rx.await {
+                    Ok(target) => target,
+                    Err(message) => return Response::Error { message },
+                };
+                match read_copy_payload(data, target, clip_cmd_tx).await {
                     Ok(Some(p)) => p,
-                    Ok(None) => {
+                    Ok(None) if allow_empty || clear_on_empty => {
+                        return copy_nothing(clear_on_empty, state.connected, stdb_cmd_tx).await;
+                    }
+                    Ok(None) if target.is_some() => {
                         return Response::Error {
-                            message: "Clipboard is empty".to_string(),
-                        }
+                            message: "Clipboard has no content for the requested target"
+                                .to_string(),
+                        };
                     }
-                    Err(_) => {
+                    Ok(None) => {
                         return Response::Error {
-                            message: "Clipboard read failed".to_string(),
-                        }
+                            message: "Clipboard is empty".to_string(),
+                        };
                     }
+                    Err(message) => return Response::Error { message },
                 }
             };
 
-            if !connected {
+            if local_only {
+                // Goes through the clipboard thread like an incoming clip, so the
+                // watcher recognises it as our own write and doesn't sync it
+                if !html {
+                    let _ = clip_cmd_tx.send(ClipboardCommand::SetClipboard {
+                        payload: payload.clone(),
+                        reply: None,
+                    });
+                }
+                record_local_history(context.history, &payload, None);
+                return Response::Ok;
+            }
+
+            if append {
+                if !state.connected {
+                    return Response::Error {
+                        message: "Not connected to SpacetimeDB".to_string(),
+                    };
+                }
+                match fetch_current_payload(stdb_cmd_tx, context.age_identity).await {
+                    Ok(Some(current)) => match payload::append_text(&current, &payload) {
+                        Ok(joined) => payload = joined,
+                        Err(e) => {
+                            return Response::Error {
+                                message: format!(
+                                    "{} (current clip is {}, new content is {})",
+                                    e,
+                                    current.content_type_str(),
+                                    payload.content_type_str()
+                                ),
+                            }
+                        }
+                    },
+                    // Nothing to append to yet; the new text becomes the clip
+                    Ok(None) => {}
+                    Err(message) => return Response::Error { message },
+                }
+            }
+
+            let window = std::time::Duration::from_millis(dedup_window_ms);
+            if state
+                .recent_sync
+                .is_some_and(|recent| recent.is_duplicate(&payload, window))
+            {
+                debug!(
+                    "Not syncing copy identical to a clip synced in the last {}ms",
+                    dedup_window_ms
+                );
+                return Response::SkippedDuplicate;
+            }
+
+            record_local_history(context.history, &payload, None);
+
+            if !state.connected {
                 return Response::Error {
                     message: "Not connected to SpacetimeDB".to_string(),
                 };
             }
+            if state.key_matches_server == Some(false) {
+                return Response::Error {
+                    message: KEY_MISMATCH_MESSAGE.to_string(),
+                };
+            }
+
+            if apply_min_size {
+                let size = payload.serialized_size().unwrap_or(usize::MAX);
+                if let Err(message) = payload::check_clip_minimum(size, context.min_sync_bytes) {
+                    return Response::Error { message };
+                }
+            }
+
+            let max_bytes = max_size
+                .map(|size| (size as usize).min(payload::MAX_SYNC_SIZE))
+                .unwrap_or(context.max_clip_bytes);
+            let options = ClipOptions {
+                intended_device: target_device,
+                ttl_secs,
+                source_app: None,
+            };
+            match encrypt_and_sync(
+                &payload,
+                context.device_id,
+                options,
+                max_bytes,
+                context.age_identity,
+                stdb_cmd_tx,
+            ) {
+                Ok(()) => {
+                    state.recent_sync = Some(RecentSync::now(&payload));
+                    Response::Ok
+                }
+                Err(e) => Response::Error { message: e },
+            }
+        }
+
+        // The daemon is the history file's writer, so deletes go through it
+        // rather than racing its appends
+        Request::DeleteHistory { index, timestamp } => {
+            let Some(history) = context.history else {
+                return Response::Error {
+                    message: "No encryption key configured. Run `clipsync setup`.".to_string(),
+                };
+            };
+            match history.delete(index, timestamp).await {
+                Ok(()) => Response::Ok,
+                Err(message) => Response::Error { message },
+            }
+        }
+
+        Request::Restore { payload } => {
+            let _ = clip_cmd_tx.send(ClipboardCommand::SetClipboard {
+                payload: payload.clone(),
+                reply: None,
+            });
+
+            if !state.connected {
+                // The local clipboard is still restored; it just won't reach other devices.
+                return Response::Ok;
+            }
+            if state.key_matches_server == Some(false) {
+                return Response::Error {
+                    message: KEY_MISMATCH_MESSAGE.to_string(),
+                };
+            }
 
-            match encrypt_and_sync(&payload, device_id, age_identity, stdb_cmd_tx) {
+            let options = ClipOptions {
+                intended_device: None,
+                ttl_secs: None,
+                source_app: None,
+            };
+            match encrypt_and_sync(
+                &payload,
+                context.device_id,
+                options,
+                context.max_clip_bytes,
+                context.age_identity,
+                stdb_cmd_tx,
+            ) {
                 Ok(()) => Response::Ok,
                 Err(e) => Response::Error { message: e },
             }
         }
 
-        Request::Paste => {
-            if !connected {
+        Request::Paste { consume } => {
+            if !state.connected {
                 return Response::Error {
                     message: "Not connected to SpacetimeDB".to_string(),
                 };
             }
 
+            if consume {
+                return consume_current_clip(stdb_cmd_tx, context.age_identity).await;
+            }
+
             let reply_rx = match send_stdb_command(stdb_cmd_tx, |reply| {
                 SpacetimeCommand::GetCurrentClip { reply }
             }) {
@@ -289,36 +1143,7 @@ async fn handle_request(
             };
 
             match reply_rx.await {
-                Ok(Some(clip)) => {
-                    if let Some(age_id) = age_identity {
-                        match crypto::decrypt(&clip.encrypted_data, age_id) {
-                            Ok(plaintext) => match ClipboardPayload::deserialize(&plaintext) {
-                                Ok(payload) => {
-                                    let data = match &payload {
-                                        ClipboardPayload::Text(text) => text.as_bytes().to_vec(),
-                                        ClipboardPayload::Image { png_data, .. } => {
-                                            png_data.clone()
-                                        }
-                                    };
-                                    Response::ClipData {
-                                        content_type: payload.content_type_str().to_string(),
-                                        data,
-                                    }
-                                }
-                                Err(e) => Response::Error {
-                                    message: format!("Failed to deserialize clip: {}", e),
-                                },
-                            },
-                            Err(e) => Response::Error {
-                                message: format!("Failed to decrypt clip: {}", e),
-                            },
-                        }
-                    } else {
-                        Response::Error {
-                            message: "No encryption key configured".to_string(),
-                        }
-                    }
-                }
+                Ok(Some(clip)) => decrypt_clip_response(&clip.encrypted_data, context.age_identity),
                 Ok(None) => Response::Error {
                     message: "No clip available".to_string(),
                 },
@@ -328,49 +1153,132 @@ async fn handle_request(
             }
         }
 
-        Request::ListDevices => {
-            let reply_rx = match send_stdb_command(stdb_cmd_tx, |reply| {
-                SpacetimeCommand::ListDevices { reply }
-            }) {
-                Some(rx) => rx,
-                None => {
-                    return Response::Error {
-                        message: "SpacetimeDB thread is not running".to_string(),
-                    };
-                }
-            };
-
-            match reply_rx.await {
-                Ok(devices) => Response::Devices {
-                    devices: devices
-                        .into_iter()
-                        .map(|d| DeviceInfo {
-                            id: d.id,
-                            device_id: d.device_id,
-                            device_name: d.device_name,
-                        })
-                        .collect(),
-                },
-                Err(_) => Response::Error {
-                    message: "Failed to list devices".to_string(),
-                },
-            }
-        }
-
-        Request::CreateInvite { code } => {
-            if !connected {
+        Request::Peek => {
+            if !state.connected {
                 return Response::Error {
                     message: "Not connected to SpacetimeDB".to_string(),
                 };
             }
 
-            let reply_rx = match send_stdb_command(stdb_cmd_tx, |reply| {
-                SpacetimeCommand::CreateInviteCode {
-                    code: code.clone(),
-                    reply,
-                }
+            let clip = match send_stdb_command(stdb_cmd_tx, |reply| {
+                SpacetimeCommand::GetCurrentClip { reply }
             }) {
-                Some(rx) => rx,
+                Some(rx) => rx.await,
+                None => {
+                    return Response::Error {
+                        message: "SpacetimeDB thread is not running".to_string(),
+                    };
+                }
+            };
+            let clip = match clip {
+                Ok(Some(clip)) => clip,
+                Ok(None) => {
+                    return Response::Error {
+                        message: "No clip available".to_string(),
+                    };
+                }
+                Err(_) => {
+                    return Response::Error {
+                        message: "Failed to get clip from SpacetimeDB".to_string(),
+                    };
+                }
+            };
+
+            let devices = match send_stdb_command(stdb_cmd_tx, |reply| {
+                SpacetimeCommand::ListDevices { reply }
+            }) {
+                Some(rx) => rx.await.unwrap_or_default(),
+                None => Vec::new(),
+            };
+            let sender_device_name = devices
+                .into_iter()
+                .find(|d| d.device_id == clip.sender_device_id)
+                .map(|d| d.device_name);
+            let preview = match decrypt_payload(&clip.encrypted_data, context.age_identity) {
+                Ok(payload @ ClipboardPayload::Text(_)) => {
+                    Some(payload.preview(PEEK_PREVIEW_CHARS))
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Peek couldn't decrypt the current clip: {}", e);
+                    None
+                }
+            };
+            Response::ClipMeta {
+                content_type: content_type_str(&clip.content_type).to_string(),
+                size_bytes: clip.size_bytes,
+                sender_device_id: clip.sender_device_id,
+                sender_device_name,
+                updated_at: u64::try_from(clip.updated_at.to_micros_since_unix_epoch() / 1_000_000)
+                    .unwrap_or(0),
+                preview,
+                source_app: clip.source_app,
+            }
+        }
+
+        Request::RecentByDevice => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+
+            let Some(clips_rx) = send_stdb_command(stdb_cmd_tx, |reply| {
+                SpacetimeCommand::ListRecentByDevice { reply }
+            }) else {
+                return Response::Error {
+                    message: "SpacetimeDB thread is not running".to_string(),
+                };
+            };
+            let mut clips = clips_rx.await.unwrap_or_default();
+            clips.sort_by_key(|clip| std::cmp::Reverse(clip.updated_at));
+            let current = match send_stdb_command(stdb_cmd_tx, |reply| {
+                SpacetimeCommand::GetCurrentClip { reply }
+            }) {
+                Some(rx) => rx.await.ok().flatten(),
+                None => None,
+            };
+            let devices = match send_stdb_command(stdb_cmd_tx, |reply| {
+                SpacetimeCommand::ListDevices { reply }
+            }) {
+                Some(rx) => rx.await.unwrap_or_default(),
+                None => Vec::new(),
+            };
+
+            let clips = clips
+                .into_iter()
+                .map(|clip| RecentClip {
+                    sender_device_name: devices
+                        .iter()
+                        .find(|d| d.device_id == clip.sender_device_id)
+                        .map(|d| d.device_name.clone()),
+                    current: current.as_ref().is_some_and(|current| {
+                        current.sender_device_id == clip.sender_device_id
+                            && current.updated_at == clip.updated_at
+                    }),
+                    content_type: content_type_str(&clip.content_type).to_string(),
+                    size_bytes: clip.size_bytes,
+                    updated_at: u64::try_from(
+                        clip.updated_at.to_micros_since_unix_epoch() / 1_000_000,
+                    )
+                    .unwrap_or(0),
+                    sender_device_id: clip.sender_device_id,
+                })
+                .collect();
+            Response::RecentByDevice { clips }
+        }
+
+        Request::ServerStats => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+
+            let reply_rx = match send_stdb_command(stdb_cmd_tx, |reply| {
+                SpacetimeCommand::GetServerStats { reply }
+            }) {
+                Some(rx) => rx,
                 None => {
                     return Response::Error {
                         message: "SpacetimeDB thread is not running".to_string(),
@@ -378,6 +1286,119 @@ async fn handle_request(
                 }
             };
 
+            match reply_rx.await {
+                Ok(Some(stats)) => Response::ServerStats {
+                    total_users: stats.total_users,
+                    total_devices: stats.total_devices,
+                    outstanding_invite_codes: stats.outstanding_invite_codes,
+                    total_teams: stats.total_teams,
+                    total_clip_bytes: stats.total_clip_bytes,
+                    total_stored_bytes: stats.total_stored_bytes,
+                },
+                Ok(None) => Response::Error {
+                    message: "Server stats are only available to admins".to_string(),
+                },
+                Err(_) => Response::Error {
+                    message: "Failed to get server stats".to_string(),
+                },
+            }
+        }
+
+        Request::ListDevices { page } => {
+            let (devices, total) = match page {
+                // Paged server-side, so only the page is sent
+                Some(page) => {
+                    if let Err(message) = page.check() {
+                        return Response::Error { message };
+                    }
+                    let reply_rx = match send_stdb_command(stdb_cmd_tx, |reply| {
+                        SpacetimeCommand::ListDevicesPage {
+                            offset: page.offset as u64,
+                            limit: page.limit as u64,
+                            reply,
+                        }
+                    }) {
+                        Some(rx) => rx,
+                        None => {
+                            return Response::Error {
+                                message: "SpacetimeDB thread is not running".to_string(),
+                            };
+                        }
+                    };
+                    match reply_rx.await {
+                        Ok(Ok((devices, total))) => (devices, total as usize),
+                        Ok(Err(message)) => return Response::Error { message },
+                        Err(_) => {
+                            return Response::Error {
+                                message: "Failed to list devices".to_string(),
+                            };
+                        }
+                    }
+                }
+                None => {
+                    let reply_rx = match send_stdb_command(stdb_cmd_tx, |reply| {
+                        SpacetimeCommand::ListDevices { reply }
+                    }) {
+                        Some(rx) => rx,
+                        None => {
+                            return Response::Error {
+                                message: "SpacetimeDB thread is not running".to_string(),
+                            };
+                        }
+                    };
+                    match reply_rx.await {
+                        Ok(mut devices) => {
+                            // The subscription cache has no order of its own
+                            devices.sort_by_key(|d| d.id);
+                            let total = devices.len();
+                            (devices, total)
+                        }
+                        Err(_) => {
+                            return Response::Error {
+                                message: "Failed to list devices".to_string(),
+                            };
+                        }
+                    }
+                }
+            };
+
+            Response::Devices {
+                devices: devices
+                    .iter()
+                    .map(|d| DeviceInfo {
+                        id: d.id,
+                        device_id: d.device_id.clone(),
+                        device_name: d.device_name.clone(),
+                        registered_at: u64::try_from(
+                            d.registered_at.to_micros_since_unix_epoch() / 1_000_000,
+                        )
+                        .ok(),
+                    })
+                    .collect(),
+                total,
+            }
+        }
+
+        Request::CreateInvite { code } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+
+            let reply_rx =
+                match send_stdb_command(stdb_cmd_tx, |reply| SpacetimeCommand::CreateInviteCode {
+                    code: code.clone(),
+                    reply,
+                }) {
+                    Some(rx) => rx,
+                    None => {
+                        return Response::Error {
+                            message: "SpacetimeDB thread is not running".to_string(),
+                        };
+                    }
+                };
+
             match reply_rx.await {
                 Ok(Ok(())) => Response::InviteCreated { code },
                 Ok(Err(e)) => Response::Error { message: e },
@@ -387,6 +1408,200 @@ async fn handle_request(
             }
         }
 
+        Request::RenameUser {
+            new_username,
+            password,
+        } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+            let response = call_reducer(stdb_cmd_tx, |reply| SpacetimeCommand::RenameUser {
+                new_username: new_username.clone(),
+                password,
+                reply,
+            })
+            .await;
+            // Only once the server has committed the new name
+            if matches!(response, Response::Ok) {
+                state.cached_username = Some(new_username);
+            }
+            response
+        }
+
+        Request::RevokeSessions { password } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+            call_reducer(stdb_cmd_tx, |reply| SpacetimeCommand::RevokeAllIdentities {
+                password,
+                reply,
+            })
+            .await
+        }
+
+        Request::MergeDevice {
+            old_device_id,
+            new_device_id,
+        } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+            call_reducer(stdb_cmd_tx, |reply| SpacetimeCommand::MergeDevice {
+                old_device_id,
+                new_device_id,
+                reply,
+            })
+            .await
+        }
+
+        Request::CreateTeam { name } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+            call_reducer(stdb_cmd_tx, |reply| SpacetimeCommand::CreateTeam {
+                name,
+                reply,
+            })
+            .await
+        }
+
+        Request::AddTeamMember { team, username } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+            call_reducer(stdb_cmd_tx, |reply| SpacetimeCommand::AddTeamMember {
+                team,
+                username,
+                reply,
+            })
+            .await
+        }
+
+        Request::RemoveTeamMember { team, username } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+            call_reducer(stdb_cmd_tx, |reply| SpacetimeCommand::RemoveTeamMember {
+                team,
+                username,
+                reply,
+            })
+            .await
+        }
+
+        Request::ListTeams => {
+            let teams = match list_teams(stdb_cmd_tx).await {
+                Ok(teams) => teams,
+                Err(message) => return Response::Error { message },
+            };
+            Response::Teams {
+                teams: teams
+                    .into_iter()
+                    .map(|(team, members)| TeamInfo {
+                        owner: members
+                            .iter()
+                            .find(|m| m.user_id == team.owner_id)
+                            .map(|m| m.username.clone())
+                            .unwrap_or_default(),
+                        name: team.name,
+                        members: members.into_iter().map(|m| m.username).collect(),
+                    })
+                    .collect(),
+            }
+        }
+
+        Request::TeamCopy { team, data } => {
+            let payload = match read_copy_payload(data, None, clip_cmd_tx).await {
+                Ok(Some(p)) => p,
+                Ok(None) => {
+                    return Response::Error {
+                        message: "Clipboard is empty".to_string(),
+                    };
+                }
+                Err(message) => return Response::Error { message },
+            };
+
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+
+            let teams = match list_teams(stdb_cmd_tx).await {
+                Ok(teams) => teams,
+                Err(message) => return Response::Error { message },
+            };
+            let Some((team_view, members)) = teams.into_iter().find(|(t, _)| t.name == team) else {
+                return Response::Error {
+                    message: format!("Not a member of team {}", team),
+                };
+            };
+
+            match encrypt_and_sync_team(
+                &payload,
+                &team_view,
+                &members,
+                context.device_id,
+                context.max_clip_bytes,
+                stdb_cmd_tx,
+            ) {
+                Ok(()) => Response::Ok,
+                Err(e) => Response::Error { message: e },
+            }
+        }
+
+        Request::TeamPaste { team } => {
+            if !state.connected {
+                return Response::Error {
+                    message: "Not connected to SpacetimeDB".to_string(),
+                };
+            }
+
+            let reply_rx =
+                match send_stdb_command(stdb_cmd_tx, |reply| SpacetimeCommand::GetTeamClip {
+                    team: team.clone(),
+                    reply,
+                }) {
+                    Some(rx) => rx,
+                    None => {
+                        return Response::Error {
+                            message: "SpacetimeDB thread is not running".to_string(),
+                        };
+                    }
+                };
+
+            match reply_rx.await {
+                Ok(Some(clip)) => decrypt_clip_response(&clip.encrypted_data, context.age_identity),
+                Ok(None) => Response::Error {
+                    message: format!("No clip available for team {}", team),
+                },
+                Err(_) => Response::Error {
+                    message: "Failed to get team clip from SpacetimeDB".to_string(),
+                },
+            }
+        }
+
+        // Answered by the main loop, which owns the clip waiters
+        Request::PasteWait { .. } => Response::Error {
+            message: "Unexpected paste wait request".to_string(),
+        },
+        // Handled by the main loop, which waits for the restored clip
+        Request::RestoreDeviceClip { .. } => Response::Error {
+            message: "Unexpected restore request".to_string(),
+        },
+
         Request::Shutdown => {
             info!("Shutdown requested via socket");
             let _ = shutdown_tx.send(true);
@@ -395,32 +1610,515 @@ async fn handle_request(
     }
 }
 
+/// Look up the user_id of the logged-in account from its server profile and
+/// save it, for a setup that saved a token but not the user_id.
+async fn recover_user_id(stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>) -> Option<u64> {
+    let reply_rx = send_stdb_command(stdb_cmd_tx, |reply| SpacetimeCommand::GetUserId { reply })?;
+    let Some(user_id) = reply_rx.await.ok().flatten() else {
+        error!("Couldn't recover the user_id: the server has no profile for this token");
+        return None;
+    };
+    match config::save_user_id(user_id) {
+        Ok(()) => info!(
+            user_id,
+            "Recovered user_id from the server profile and saved it"
+        ),
+        Err(e) => warn!(
+            user_id,
+            "Recovered user_id from the server profile, but failed to save it: {}", e
+        ),
+    }
+    Some(user_id)
+}
+
+/// Compare the local identity with the public key the server has for this
+/// user, logging loudly on a mismatch. None if either side is unavailable.
+async fn check_key_matches_server(
+    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
+    age_identity: Option<&age::x25519::Identity>,
+) -> Option<bool> {
+    let age_id = age_identity?;
+    let reply_rx = send_stdb_command(stdb_cmd_tx, |reply| SpacetimeCommand::GetPublicKey {
+        reply,
+    })?;
+    let server_key = reply_rx.await.ok().flatten()?;
+    let matches = crypto::key_matches(age_id, &server_key);
+    if !matches {
+        error!("{} Outgoing sync is disabled.", KEY_MISMATCH_MESSAGE);
+    }
+    Some(matches)
+}
+
+/// Answer a copy that found the clipboard empty: succeed without syncing,
+/// and with `clear` also remove the synced clip from the server.
+async fn copy_nothing(
+    clear: bool,
+    connected: bool,
+    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
+) -> Response {
+    if !clear {
+        return Response::NothingToCopy { cleared: false };
+    }
+    if !connected {
+        return Response::Error {
+            message: "Not connected to SpacetimeDB".to_string(),
+        };
+    }
+    match call_reducer(stdb_cmd_tx, |reply| SpacetimeCommand::ClearCurrentClip {
+        reply,
+    })
+    .await
+    {
+        Response::Ok => Response::NothingToCopy { cleared: true },
+        other => other,
+    }
+}
+
+/// Resolve the payload for a copy: piped data if given, otherwise the system
+/// clipboard. None means the clipboard (or the requested target) is empty.
+async fn read_copy_payload(
+    data: Option<Vec<u8>>,
+    target: Option<ClipboardTarget>,
+    clip_cmd_tx: &std::sync::mpsc::Sender<ClipboardCommand>,
+) -> Result<Option<ClipboardPayload>, String> {
+    if let Some(data) = data {
+        // Data provided (from stdin)
+        return Ok(Some(ClipboardPayload::Text(
+            String::from_utf8_lossy(&data).into_owned(),
+        )));
+    }
+
+    // Read from system clipboard
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if clip_cmd_tx
+        .send(ClipboardCommand::ReadClipboard {
+            target,
+            reply: reply_tx,
+        })
+        .is_err()
+    {
+        return Err("Clipboard thread not available".to_string());
+    }
+    match tokio::time::timeout(CLIPBOARD_REPLY_TIMEOUT, reply_rx).await {
+        Ok(Ok(payload)) => Ok(payload),
+        Ok(Err(_)) => Err("Clipboard read failed".to_string()),
+        Err(_) => {
+            warn!(
+                "Clipboard thread did not reply within {:?}",
+                CLIPBOARD_REPLY_TIMEOUT
+            );
+            Err("Clipboard timed out (clipboard thread is unresponsive)".to_string())
+        }
+    }
+}
+
+/// Type and size of the local clipboard for `status --local-size`. None if
+/// it is empty or the clipboard thread can't read it in time.
+async fn measure_local_clipboard(
+    clip_cmd_tx: &std::sync::mpsc::Sender<ClipboardCommand>,
+) -> Option<LocalClipboard> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    clip_cmd_tx
+        .send(ClipboardCommand::Measure { reply: reply_tx })
+        .ok()?;
+    match tokio::time::timeout(CLIPBOARD_REPLY_TIMEOUT, reply_rx).await {
+        Ok(Ok(measured)) => measured.map(|(content_type, size)| LocalClipboard {
+            content_type: content_type.as_str().to_string(),
+            size_bytes: size as u64,
+        }),
+        Ok(Err(_)) => None,
+        Err(_) => {
+            warn!(
+                "Clipboard thread did not reply within {:?}",
+                CLIPBOARD_REPLY_TIMEOUT
+            );
+            None
+        }
+    }
+}
+
+fn decrypt_payload(
+    encrypted_data: &[u8],
+    age_identity: Option<&age::x25519::Identity>,
+) -> Result<ClipboardPayload, String> {
+    let age_id = age_identity.ok_or("No encryption key configured")?;
+    let plaintext = crypto::decrypt(encrypted_data, age_id)
+        .map_err(|e| format!("Failed to decrypt clip: {}", e))?;
+    ClipboardPayload::deserialize(&plaintext)
+        .map_err(|e| format!("Failed to deserialize clip: {}", e))
+}
+
+/// Answer a `PasteWait` request with the next clip newer than `after_micros`,
+/// or an error once `timeout_secs` passes.
+fn spawn_paste_waiter(
+    mut clips: broadcast::Receiver<CurrentClip>,
+    after_micros: Option<i64>,
+    timeout_secs: Option<u64>,
+    age_identity: Option<age::x25519::Identity>,
+    reply: oneshot::Sender<Response>,
+) {
+    tokio::spawn(async move {
+        let next_clip = async {
+            loop {
+                match clips.recv().await {
+                    Ok(clip)
+                        if after_micros.is_none_or(|after| {
+                            clip.updated_at.to_micros_since_unix_epoch() > after
+                        }) =>
+                    {
+                        return Some(clip);
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        };
+        let clip = match timeout_secs {
+            Some(secs) => tokio::time::timeout(std::time::Duration::from_secs(secs), next_clip)
+                .await
+                .map_err(|_| format!("Timed out after {}s waiting for a clip", secs)),
+            None => Ok(next_clip.await),
+        };
+        let response = match clip {
+            Ok(Some(clip)) => decrypt_clip_response(&clip.encrypted_data, age_identity.as_ref()),
+            Ok(None) => Response::Error {
+                message: "Daemon is shutting down".to_string(),
+            },
+            Err(message) => Response::Error { message },
+        };
+        let _ = reply.send(response);
+    });
+}
+
+/// Restore `sender_device_id`'s last kept clip, answering once it comes back
+/// from the server. Other devices apply it like any clip, but the main loop
+/// skips this device's own clips, so one it sent is applied here instead.
+fn spawn_restore_waiter(
+    mut clips: broadcast::Receiver<CurrentClip>,
+    sender_device_id: String,
+    device_id: String,
+    age_identity: Option<age::x25519::Identity>,
+    stdb_cmd_tx: crossbeam_channel::Sender<SpacetimeCommand>,
+    clip_cmd_tx: std::sync::mpsc::Sender<ClipboardCommand>,
+    reply: oneshot::Sender<Response>,
+) {
+    tokio::spawn(async move {
+        let response = call_reducer(&stdb_cmd_tx, |reply| SpacetimeCommand::RestoreDeviceClip {
+            sender_device_id: sender_device_id.clone(),
+            reply,
+        })
+        .await;
+        if !matches!(response, Response::Ok) {
+            let _ = reply.send(response);
+            return;
+        }
+
+        let restored = async {
+            loop {
+                match clips.recv().await {
+                    Ok(clip) if clip.sender_device_id == sender_device_id => return Some(clip),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        };
+        let response = match tokio::time::timeout(RESTORE_TIMEOUT, restored).await {
+            Ok(Some(clip)) if clip.sender_device_id == device_id => {
+                match decrypt_payload(&clip.encrypted_data, age_identity.as_ref()) {
+                    Ok(payload) => {
+                        let _ = clip_cmd_tx.send(ClipboardCommand::SetClipboard {
+                            payload,
+                            reply: None,
+                        });
+                        Response::Ok
+                    }
+                    Err(message) => Response::Error { message },
+                }
+            }
+            Ok(Some(_)) => Response::Ok,
+            Ok(None) => Response::Error {
+                message: "Daemon is shutting down".to_string(),
+            },
+            Err(_) => Response::Error {
+                message: format!(
+                    "The server didn't restore a clip from {} within {}s",
+                    sender_device_id,
+                    RESTORE_TIMEOUT.as_secs()
+                ),
+            },
+        };
+        let _ = reply.send(response);
+    });
+}
+
+/// Answer a `copy --wait-confirm` once `wait.devices` other devices have
+/// acked the clip this device syncs next. Fails on timeout, or if another
+/// clip replaces it first.
+fn spawn_confirm_waiter(
+    mut clips: broadcast::Receiver<CurrentClip>,
+    mut acks: broadcast::Receiver<ClipAck>,
+    device_id: String,
+    wait: WaitConfirm,
+    reply: oneshot::Sender<Response>,
+) {
+    tokio::spawn(async move {
+        let confirmed = async {
+            // The copy coming back from the server gives the version to wait on
+            let version = loop {
+                match clips.recv().await {
+                    Ok(clip) if clip.sender_device_id == device_id => break clip.updated_at,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err("Daemon is shutting down".to_string());
+                    }
+                }
+            };
+            let mut acked: Vec<String> = Vec::new();
+            while acked.len() < wait.devices {
+                tokio::select! {
+                    clip = clips.recv() => match clip {
+                        Ok(clip) if clip.updated_at != version => {
+                            return Err(
+                                "Another clip replaced this one before it was confirmed".to_string()
+                            );
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => {
+                            return Err("Daemon is shutting down".to_string());
+                        }
+                    },
+                    ack = acks.recv() => match ack {
+                        Ok(ack)
+                            if ack.clip_updated_at == version && !acked.contains(&ack.device_id) =>
+                        {
+                            acked.push(ack.device_id);
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => {
+                            return Err("Daemon is shutting down".to_string());
+                        }
+                    },
+                }
+            }
+            Ok(acked)
+        };
+        let timeout = std::time::Duration::from_secs(wait.timeout_secs);
+        let response = match tokio::time::timeout(timeout, confirmed).await {
+            Ok(Ok(device_ids)) => Response::Confirmed { device_ids },
+            Ok(Err(message)) => Response::Error { message },
+            Err(_) => Response::Error {
+                message: format!(
+                    "Clip was synced, but {} device(s) didn't confirm it within {}s",
+                    wait.devices, wait.timeout_secs
+                ),
+            },
+        };
+        let _ = reply.send(response);
+    });
+}
+
+/// Decrypt a synced clip and package it as a `ClipData` response for the CLI.
+fn decrypt_clip_response(
+    encrypted_data: &[u8],
+    age_identity: Option<&age::x25519::Identity>,
+) -> Response {
+    match decrypt_payload(encrypted_data, age_identity) {
+        Ok(payload) => {
+            let data = match &payload {
+                ClipboardPayload::Text(text) => text.as_bytes().to_vec(),
+                ClipboardPayload::Image { png_data, .. } => png_data.clone(),
+            };
+            Response::ClipData {
+                content_type: payload.content_type_str().to_string(),
+                data,
+                cached: false,
+            }
+        }
+        Err(message) => Response::Error { message },
+    }
+}
+
+/// Fetch and decrypt the current synced clip, if there is one.
+async fn fetch_current_payload(
+    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
+    age_identity: Option<&age::x25519::Identity>,
+) -> Result<Option<ClipboardPayload>, String> {
+    let reply_rx = send_stdb_command(stdb_cmd_tx, |reply| SpacetimeCommand::GetCurrentClip {
+        reply,
+    })
+    .ok_or("SpacetimeDB thread is not running")?;
+    match reply_rx.await {
+        Ok(Some(clip)) => decrypt_payload(&clip.encrypted_data, age_identity).map(Some),
+        Ok(None) => Ok(None),
+        Err(_) => Err("Failed to get clip from SpacetimeDB".to_string()),
+    }
+}
+
+/// Take the current clip off the server in one transaction and decrypt it.
+async fn consume_current_clip(
+    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
+    age_identity: Option<&age::x25519::Identity>,
+) -> Response {
+    let Some(reply_rx) = send_stdb_command(stdb_cmd_tx, |reply| {
+        SpacetimeCommand::ConsumeCurrentClip { reply }
+    }) else {
+        return Response::Error {
+            message: "SpacetimeDB thread is not running".to_string(),
+        };
+    };
+    match tokio::time::timeout(CONSUME_REPLY_TIMEOUT, reply_rx).await {
+        Ok(Ok(Ok(clip))) => decrypt_clip_response(&clip.encrypted_data, age_identity),
+        Ok(Ok(Err(message))) => Response::Error { message },
+        Ok(Err(_)) => Response::Error {
+            message: "Failed to consume clip from SpacetimeDB".to_string(),
+        },
+        Err(_) => Response::Error {
+            message: format!(
+                "SpacetimeDB didn't answer the consume within {}s",
+                CONSUME_REPLY_TIMEOUT.as_secs()
+            ),
+        },
+    }
+}
+
+/// Send a reducer-calling command whose reply is `Result<(), String>` and map it to a response.
+async fn call_reducer(
+    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
+    make_cmd: impl FnOnce(oneshot::Sender<Result<(), String>>) -> SpacetimeCommand,
+) -> Response {
+    let Some(reply_rx) = send_stdb_command(stdb_cmd_tx, make_cmd) else {
+        return Response::Error {
+            message: "SpacetimeDB thread is not running".to_string(),
+        };
+    };
+    match reply_rx.await {
+        Ok(Ok(())) => Response::Ok,
+        Ok(Err(e)) => Response::Error { message: e },
+        Err(_) => Response::Error {
+            message: "SpacetimeDB request failed".to_string(),
+        },
+    }
+}
+
+async fn list_teams(
+    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
+) -> Result<Vec<(TeamView, Vec<TeamMemberView>)>, String> {
+    let reply_rx = send_stdb_command(stdb_cmd_tx, |reply| SpacetimeCommand::ListTeams { reply })
+        .ok_or("SpacetimeDB thread is not running")?;
+    reply_rx
+        .await
+        .map_err(|_| "Failed to list teams".to_string())
+}
+
+fn content_type_str(content_type: &ClipContentType) -> &'static str {
+    match content_type {
+        ClipContentType::Text => ContentType::Text.as_str(),
+        ClipContentType::Image => ContentType::Image.as_str(),
+    }
+}
+
+fn clip_content_type(payload: &ClipboardPayload) -> ClipContentType {
+    match payload {
+        ClipboardPayload::Text(_) => ClipContentType::Text,
+        ClipboardPayload::Image { .. } => ClipContentType::Image,
+    }
+}
+
+/// Encrypt a clip to every current member of a team and sync it.
+fn encrypt_and_sync_team(
+    payload: &ClipboardPayload,
+    team: &TeamView,
+    members: &[TeamMemberView],
+    device_id: &str,
+    max_bytes: usize,
+    stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
+) -> Result<(), String> {
+    let recipients = members
+        .iter()
+        .map(|m| {
+            crypto::recipient_from_bytes(&m.public_key)
+                .map_err(|e| format!("Invalid public key for {}: {}", m.username, e))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if recipients.is_empty() {
+        return Err(format!("Team {} has no members", team.name));
+    }
+    payload::check_sync_size(payload)?;
+    let data = payload
+        .serialize()
+        .map_err(|e| format!("Serialization failed: {}", e))?;
+    payload::check_clip_limit(data.len(), max_bytes)?;
+    let size_bytes = data.len() as u64;
+    let encrypted =
+        crypto::encrypt(&data, &recipients).map_err(|e| format!("Encryption failed: {}", e))?;
+    let _ = stdb_cmd_tx.send(SpacetimeCommand::SyncTeamClip {
+        team_id: team.team_id,
+        members_version: team.members_version,
+        device_id: device_id.to_string(),
+        content_type: clip_content_type(payload),
+        encrypted_data: encrypted,
+        size_bytes,
+    });
+    Ok(())
+}
+
 fn encrypt_and_sync(
     payload: &ClipboardPayload,
     device_id: &str,
+    options: ClipOptions,
+    max_bytes: usize,
     age_identity: Option<&age::x25519::Identity>,
     stdb_cmd_tx: &crossbeam_channel::Sender<SpacetimeCommand>,
 ) -> Result<(), String> {
     let age_id = age_identity.ok_or("No encryption key configured. Run `clipsync setup`.")?;
+    payload::check_sync_size(payload)?;
     let recipient = age_id.to_public();
-    let data = payload.serialize().map_err(|e| format!("Serialization failed: {}", e))?;
+    let data = payload
+        .serialize()
+        .map_err(|e| format!("Serialization failed: {}", e))?;
+    payload::check_clip_limit(data.len(), max_bytes)?;
     let size_bytes = data.len() as u64;
-    let encrypted = crypto::encrypt(&data, &[recipient]).map_err(|e| format!("Encryption failed: {}", e))?;
-    let content_type = match payload {
-        ClipboardPayload::Text(_) => ClipContentType::Text,
-        ClipboardPayload::Image { .. } => ClipContentType::Image,
-    };
+    let encrypted =
+        crypto::encrypt(&data, &[recipient]).map_err(|e| format!("Encryption failed: {}", e))?;
     let _ = stdb_cmd_tx.send(SpacetimeCommand::SyncClip {
         device_id: device_id.to_string(),
-        content_type,
+        content_type: clip_content_type(payload),
         encrypted_data: encrypted,
         size_bytes,
+        options,
     });
     Ok(())
 }
 
+/// Log a clip event. Only type and size are logged unless `log_previews` is on.
+fn log_clip(config: &Config, what: &str, payload: &ClipboardPayload) {
+    let content_type = payload.content_type_str();
+    let size_bytes = payload.data_len();
+    if config.log_previews {
+        debug!(
+            content_type,
+            size_bytes,
+            preview = ?payload.preview(LOG_PREVIEW_CHARS),
+            "{}",
+            what
+        );
+    } else {
+        debug!(content_type, size_bytes, "{}", what);
+    }
+}
+
+/// Queue a clip for the local history file. Needs the encryption key, so
+/// without one nothing is recorded.
+fn record_local_history(
+    history: Option<&HistoryWriter>,
+    payload: &ClipboardPayload,
+    source_app: Option<String>,
+) {
+    if let Some(history) = history {
+        history.record(payload, source_app);
+    }
+}
+
 fn hostname() -> String {
-    gethostname::gethostname()
-        .to_string_lossy()
-        .into_owned()
+    gethostname::gethostname().to_string_lossy().into_owned()
 }
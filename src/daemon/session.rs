@@ -0,0 +1,79 @@
+use anyhow::Result;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+/// How often the session lock state is checked.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Parse `loginctl show-session -p LockedHint --value` output.
+fn parse_locked_hint(output: &str) -> Option<bool> {
+    match output.trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether the graphical session is locked, via logind's `LockedHint`.
+/// None when it can't be determined (no logind, or a session without one).
+#[cfg(target_os = "linux")]
+fn session_locked() -> Option<bool> {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+    let output = std::process::Command::new("loginctl")
+        .args(["show-session", &session, "-p", "LockedHint", "--value"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_locked_hint(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn session_locked() -> Option<bool> {
+    None
+}
+
+/// Watch the session lock state for `pause_when_locked`, sending the new state
+/// on every change (and the initial state once). Where the state can't be
+/// determined the thread logs once and exits, so the feature is a no-op.
+pub fn spawn_lock_monitor(event_tx: mpsc::Sender<bool>) -> Result<()> {
+    std::thread::Builder::new()
+        .name("session-lock".to_string())
+        .spawn(move || {
+            let Some(mut locked) = session_locked() else {
+                info!("Session lock state is unavailable, pause_when_locked has no effect");
+                return;
+            };
+            if event_tx.blocking_send(locked).is_err() {
+                return;
+            }
+            loop {
+                std::thread::sleep(LOCK_POLL_INTERVAL);
+                match session_locked() {
+                    Some(now_locked) if now_locked != locked => {
+                        locked = now_locked;
+                        if event_tx.blocking_send(locked).is_err() {
+                            return;
+                        }
+                    }
+                    Some(_) => {}
+                    None => debug!("Failed to read session lock state"),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_locked_hint() {
+        assert_eq!(parse_locked_hint("yes\n"), Some(true));
+        assert_eq!(parse_locked_hint("no\n"), Some(false));
+        assert_eq!(parse_locked_hint(""), None);
+    }
+}
@@ -10,11 +10,10 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use tracing::{debug, error, info, warn};
 
 use crate::config::socket_path;
-use crate::protocol::{Request, Response, MAX_IPC_FRAME_SIZE};
+use crate::protocol::{check_frame_size, Request, Response, MAX_IPC_FRAME_SIZE};
 
 use futures::StreamExt;
 
-const MAX_CONCURRENT_CONNECTIONS: usize = 16;
 const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct SocketRequest {
@@ -22,7 +21,10 @@ pub struct SocketRequest {
     pub reply: oneshot::Sender<Response>,
 }
 
-pub async fn run_socket_server(request_tx: mpsc::Sender<SocketRequest>) -> Result<()> {
+pub async fn run_socket_server(
+    request_tx: mpsc::Sender<SocketRequest>,
+    max_connections: usize,
+) -> Result<()> {
     let path = socket_path();
 
     // Ensure parent directory exists
@@ -80,21 +82,27 @@ pub async fn run_socket_server(request_tx: mpsc::Sender<SocketRequest>) -> Resul
 
     info!("Socket server listening at {}", path.display());
 
-    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_CONNECTIONS));
+    serve(listener, request_tx, max_connections).await
+}
+
+/// Accept and serve connections. A permit is taken before each `accept()`, so
+/// a burst of clients waits in the listen backlog rather than being accepted
+/// and holding open descriptors while queued for a permit. At most
+/// `max_connections` connections are accepted at once.
+async fn serve(
+    listener: UnixListener,
+    request_tx: mpsc::Sender<SocketRequest>,
+    max_connections: usize,
+) -> Result<()> {
+    let semaphore = Arc::new(Semaphore::new(max_connections));
 
     loop {
+        let permit = semaphore.clone().acquire_owned().await?;
         let (stream, _) = listener.accept().await?;
         let request_tx = request_tx.clone();
-        let semaphore = semaphore.clone();
 
         tokio::spawn(async move {
-            let _permit = match semaphore.acquire().await {
-                Ok(permit) => permit,
-                Err(_) => {
-                    warn!("Connection semaphore closed");
-                    return;
-                }
-            };
+            let _permit = permit;
 
             // Verify peer UID matches our UID
             #[cfg(unix)]
@@ -124,15 +132,15 @@ pub async fn run_socket_server(request_tx: mpsc::Sender<SocketRequest>) -> Resul
             let mut framed = Framed::new(stream, codec);
 
             loop {
-                let result = match tokio::time::timeout(CONNECTION_IDLE_TIMEOUT, framed.next()).await
-                {
-                    Ok(Some(result)) => result,
-                    Ok(None) => break,
-                    Err(_) => {
-                        debug!("Connection idle timeout reached");
-                        break;
-                    }
-                };
+                let result =
+                    match tokio::time::timeout(CONNECTION_IDLE_TIMEOUT, framed.next()).await {
+                        Ok(Some(result)) => result,
+                        Ok(None) => break,
+                        Err(_) => {
+                            debug!("Connection idle timeout reached");
+                            break;
+                        }
+                    };
 
                 match result {
                     Ok(data) => {
@@ -155,7 +163,8 @@ pub async fn run_socket_server(request_tx: mpsc::Sender<SocketRequest>) -> Resul
                             }
                         };
 
-                        debug!("Received request: {:?}", request);
+                        // Requests can carry clip data, so only the variant is logged
+                        debug!("Received request: {}", request.name());
 
                         let (reply_tx, reply_rx) = oneshot::channel();
                         if request_tx
@@ -178,6 +187,21 @@ pub async fn run_socket_server(request_tx: mpsc::Sender<SocketRequest>) -> Resul
                                         break;
                                     }
                                 };
+                                // A clip too large for one frame becomes an error
+                                // response rather than a dropped connection
+                                let resp_bytes = match check_frame_size(resp_bytes.len()) {
+                                    Ok(()) => resp_bytes,
+                                    Err(message) => {
+                                        warn!("Response too large: {}", message);
+                                        match serde_json::to_vec(&Response::Error { message }) {
+                                            Ok(b) => b,
+                                            Err(e) => {
+                                                error!("Failed to serialize response: {}", e);
+                                                break;
+                                            }
+                                        }
+                                    }
+                                };
                                 if framed
                                     .send(BytesMut::from(&resp_bytes[..]).freeze())
                                     .await
@@ -198,3 +222,43 @@ pub async fn run_socket_server(request_tx: mpsc::Sender<SocketRequest>) -> Resul
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn burst_of_clients_all_get_answers() {
+        let path = std::env::temp_dir().join(format!("clipsync-{}.sock", uuid::Uuid::new_v4()));
+        let listener = UnixListener::bind(&path).unwrap();
+        let (request_tx, mut request_rx) = mpsc::channel::<SocketRequest>(32);
+        let max_connections = 4;
+        tokio::spawn(serve(listener, request_tx, max_connections));
+        tokio::spawn(async move {
+            while let Some(req) = request_rx.recv().await {
+                let _ = req.reply.send(Response::Ok);
+            }
+        });
+
+        let clients: Vec<_> = (0..max_connections * 16)
+            .map(|_| {
+                let path = path.clone();
+                tokio::spawn(async move {
+                    crate::cli::send_request_at(
+                        &path,
+                        Request::Status {
+                            socket_only: true,
+                            local_size: false,
+                        },
+                    )
+                    .await
+                })
+            })
+            .collect();
+        for client in clients {
+            let response = client.await.unwrap().unwrap();
+            assert!(matches!(response, Response::Ok));
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
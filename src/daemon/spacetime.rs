@@ -1,23 +1,41 @@
 use anyhow::Result;
-use spacetimedb_sdk::{DbContext, Identity, Table};
+use spacetimedb_sdk::{DbContext, Identity, Table, Timestamp};
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
 use tracing::{error, info, warn};
 
+use super::health::Heartbeat;
 use crate::config::{self, Config};
 use crate::module_bindings::*;
 
 // Import reducer extension traits
+use crate::module_bindings::ack_clip_reducer::ack_clip;
+use crate::module_bindings::add_team_member_reducer::add_team_member;
+use crate::module_bindings::clear_current_clip_reducer::clear_current_clip;
+use crate::module_bindings::consume_current_clip_reducer::consume_current_clip;
 use crate::module_bindings::create_invite_code_reducer::create_invite_code;
+use crate::module_bindings::create_team_reducer::create_team;
+use crate::module_bindings::get_devices_page_reducer::get_devices_page;
+use crate::module_bindings::merge_device_reducer::merge_device;
 use crate::module_bindings::register_device_reducer::register_device;
+use crate::module_bindings::remove_team_member_reducer::remove_team_member;
+use crate::module_bindings::rename_user_reducer::rename_user;
+use crate::module_bindings::restore_device_clip_reducer::restore_device_clip;
+use crate::module_bindings::revoke_all_identities_reducer::revoke_all_identities;
 use crate::module_bindings::sync_clip_reducer::sync_clip;
+use crate::module_bindings::sync_team_clip_reducer::sync_team_clip;
 
 const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
-const MAX_BACKOFF: Duration = Duration::from_secs(60);
+pub(super) const MAX_BACKOFF: Duration = Duration::from_secs(60);
 const DISCONNECT_CHECK_INTERVAL: Duration = Duration::from_millis(250);
 
+/// Consume requests waiting for their row to show up in `my_consumed_clip`,
+/// or for the reducer's error, keyed by the request id passed to the reducer.
+type PendingConsumes = Arc<Mutex<HashMap<String, oneshot::Sender<Result<ConsumedClip, String>>>>>;
+
 // Events sent from SpacetimeDB thread to main loop
 #[derive(Debug)]
 pub enum SpacetimeEvent {
@@ -26,8 +44,17 @@ pub enum SpacetimeEvent {
         token: String,
     },
     Disconnected,
+    /// The server rejected the saved token; the thread has stopped reconnecting
+    AuthExpired,
     SubscriptionApplied,
     ClipUpdated(CurrentClip),
+    /// The clip synced at `updated_at` is no longer the current clip, whether
+    /// replaced or removed
+    ClipRemoved {
+        updated_at: Timestamp,
+    },
+    /// Another device confirmed it applied the current clip
+    ClipAcked(ClipAck),
 }
 
 // Commands sent from main loop to SpacetimeDB thread
@@ -37,24 +64,111 @@ pub enum SpacetimeCommand {
         content_type: ClipContentType,
         encrypted_data: Vec<u8>,
         size_bytes: u64,
+        /// Intended device, TTL and source app, each optional
+        options: ClipOptions,
     },
     RegisterDevice {
         device_id: String,
         device_name: String,
     },
+    /// Tell the sender this device applied the clip stored at `clip_updated_at`
+    AckClip {
+        device_id: String,
+        clip_updated_at: Timestamp,
+    },
     ListDevices {
         reply: oneshot::Sender<Vec<DeviceView>>,
     },
+    /// One page of the account's devices, ordered by id, with the total count
+    ListDevicesPage {
+        offset: u64,
+        limit: u64,
+        reply: oneshot::Sender<Result<(Vec<DeviceView>, u64), String>>,
+    },
+    MergeDevice {
+        old_device_id: String,
+        new_device_id: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
     GetCurrentClip {
         reply: oneshot::Sender<Option<CurrentClip>>,
     },
+    /// The last clip each of the user's devices synced, without its data
+    ListRecentByDevice {
+        reply: oneshot::Sender<Vec<DeviceClipView>>,
+    },
+    RestoreDeviceClip {
+        sender_device_id: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
     GetUsername {
         reply: oneshot::Sender<Option<String>>,
     },
+    GetUserId {
+        reply: oneshot::Sender<Option<u64>>,
+    },
+    /// The public key the server has for this user
+    GetPublicKey {
+        reply: oneshot::Sender<Option<Vec<u8>>>,
+    },
+    GetServerStats {
+        reply: oneshot::Sender<Option<ServerStats>>,
+    },
+    /// Failed login attempts recorded against this user's account
+    GetFailedLoginAttempts {
+        reply: oneshot::Sender<u32>,
+    },
+    /// Atomically take the current clip. The reply is sent once the consumed
+    /// clip arrives, or with the server's error if the reducer failed (e.g.
+    /// another device consumed the clip first).
+    ConsumeCurrentClip {
+        reply: oneshot::Sender<Result<ConsumedClip, String>>,
+    },
     CreateInviteCode {
         code: String,
         reply: oneshot::Sender<Result<(), String>>,
     },
+    RenameUser {
+        new_username: String,
+        password: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    ClearCurrentClip {
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    RevokeAllIdentities {
+        password: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    CreateTeam {
+        name: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    AddTeamMember {
+        team: String,
+        username: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    RemoveTeamMember {
+        team: String,
+        username: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    ListTeams {
+        reply: oneshot::Sender<Vec<(TeamView, Vec<TeamMemberView>)>>,
+    },
+    SyncTeamClip {
+        team_id: u64,
+        members_version: u64,
+        device_id: String,
+        content_type: ClipContentType,
+        encrypted_data: Vec<u8>,
+        size_bytes: u64,
+    },
+    GetTeamClip {
+        team: String,
+        reply: oneshot::Sender<Option<TeamClip>>,
+    },
 }
 
 pub fn spawn_spacetime_thread(
@@ -62,15 +176,25 @@ pub fn spawn_spacetime_thread(
     token: Option<String>,
     event_tx: mpsc::Sender<SpacetimeEvent>,
     command_rx: crossbeam_channel::Receiver<SpacetimeCommand>,
-) -> Result<()> {
+    heartbeat: Heartbeat,
+) -> Result<std::thread::JoinHandle<()>> {
     let server_url = config.server_url.clone();
     let database_name = config.database_name.clone();
+    let server_fingerprint = config.server_fingerprint.clone();
 
-    std::thread::Builder::new()
+    let handle = std::thread::Builder::new()
         .name("spacetimedb".to_string())
         .spawn(move || {
             let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                spacetime_thread_main(server_url, database_name, token, &event_tx, &command_rx);
+                spacetime_thread_main(
+                    server_url,
+                    database_name,
+                    server_fingerprint,
+                    token,
+                    &event_tx,
+                    &command_rx,
+                    &heartbeat,
+                );
             }));
 
             if let Err(panic_info) = result {
@@ -87,26 +211,31 @@ pub fn spawn_spacetime_thread(
             }
         })?;
 
-    Ok(())
+    Ok(handle)
 }
 
 fn spacetime_thread_main(
     server_url: String,
     database_name: String,
+    server_fingerprint: Option<String>,
     mut token: Option<String>,
     event_tx: &mpsc::Sender<SpacetimeEvent>,
     command_rx: &crossbeam_channel::Receiver<SpacetimeCommand>,
+    heartbeat: &Heartbeat,
 ) {
     let mut backoff = INITIAL_BACKOFF;
     let mut first_attempt = true;
+    let pending_consumes: PendingConsumes = Arc::new(Mutex::new(HashMap::new()));
+    // Set when the server fails the `server_fingerprint` check; never reconnect after that
+    let fingerprint_rejected = Arc::new(AtomicBool::new(false));
+    // Set when the server rejects the token; retrying with the same one is pointless
+    let auth_rejected = Arc::new(AtomicBool::new(false));
 
     // Outer reconnection loop
     loop {
+        heartbeat.tick();
         if !first_attempt {
-            info!(
-                "Reconnecting to SpacetimeDB in {}s...",
-                backoff.as_secs()
-            );
+            info!("Reconnecting to SpacetimeDB in {}s...", backoff.as_secs());
             std::thread::sleep(backoff);
 
             // Reload token in case on_connect saved a newer one
@@ -120,6 +249,8 @@ fn spacetime_thread_main(
         // Drain any commands that queued up during reconnect backoff,
         // replying with errors so callers don't hang
         drain_pending_commands(command_rx);
+        // Consumes sent on the old connection will never be answered
+        pending_consumes.lock().unwrap().clear();
 
         let disconnected = Arc::new(AtomicBool::new(false));
 
@@ -128,44 +259,106 @@ fn spacetime_thread_main(
         let event_tx_sub = event_tx.clone();
         let event_tx_clip = event_tx.clone();
         let disconnected_cb = disconnected.clone();
+        let pending_consumes_cb = pending_consumes.clone();
+        let server_fingerprint_cb = server_fingerprint.clone();
+        let fingerprint_rejected_cb = fingerprint_rejected.clone();
+        let auth_rejected_cb = auth_rejected.clone();
 
         let conn = DbConnection::builder()
             .with_uri(&server_url)
             .with_database_name(&database_name)
             .with_token(token.clone())
-            .on_connect(move |conn: &DbConnection, identity: Identity, token: &str| {
-                info!("Connected to SpacetimeDB as {:?}", identity);
+            .on_connect(
+                move |conn: &DbConnection, identity: Identity, token: &str| {
+                    info!("Connected to SpacetimeDB as {:?}", identity);
 
-                let _ = event_tx_connect.blocking_send(SpacetimeEvent::Connected {
-                    identity,
-                    token: token.to_string(),
-                });
+                    // Subscribe to all tables (views are scoped to the current user)
+                    let event_tx_for_connect = event_tx_connect.clone();
+                    let event_tx_for_sub = event_tx_sub.clone();
+                    let event_tx_for_clip = event_tx_clip.clone();
+                    let pending_consumes_for_sub = pending_consumes_cb.clone();
+                    let server_fingerprint = server_fingerprint_cb.clone();
+                    let fingerprint_rejected = fingerprint_rejected_cb.clone();
+                    let token = token.to_string();
+
+                    conn.subscription_builder()
+                        .on_applied(move |ctx: &SubscriptionEventContext| {
+                            // The server's identity is only readable once subscribed, so
+                            // the connection isn't announced (and the token isn't saved)
+                            // until it has been checked
+                            let reported = ctx
+                                .db
+                                .server_identity()
+                                .iter()
+                                .next()
+                                .map(|info| info.module_identity.to_hex().to_string());
+                            if let Err(e) = config::check_server_fingerprint(
+                                server_fingerprint.as_deref(),
+                                reported.as_deref(),
+                            ) {
+                                error!("{}", e);
+                                fingerprint_rejected.store(true, Ordering::Release);
+                                let _ = ctx.disconnect();
+                                return;
+                            }
 
-                // Subscribe to all tables (views are scoped to the current user)
-                let event_tx_for_sub = event_tx_sub.clone();
-                let event_tx_for_clip = event_tx_clip.clone();
-
-                conn.subscription_builder()
-                    .on_applied(move |ctx: &SubscriptionEventContext| {
-                        info!("Subscription applied");
-                        let _ = event_tx_for_sub
-                            .blocking_send(SpacetimeEvent::SubscriptionApplied);
-
-                        let tx = event_tx_for_clip.clone();
-                        ctx.db.my_current_clip().on_insert(
-                            move |_ctx: &EventContext, row: &CurrentClip| {
-                                let _ = tx.blocking_send(SpacetimeEvent::ClipUpdated(
-                                    row.clone(),
-                                ));
-                            },
-                        );
-                    })
-                    .subscribe_to_all_tables();
-            })
+                            let _ = event_tx_for_connect.blocking_send(SpacetimeEvent::Connected {
+                                identity,
+                                token: token.clone(),
+                            });
+
+                            info!("Subscription applied");
+                            let _ =
+                                event_tx_for_sub.blocking_send(SpacetimeEvent::SubscriptionApplied);
+
+                            let tx = event_tx_for_clip.clone();
+                            ctx.db.my_current_clip().on_insert(
+                                move |_ctx: &EventContext, row: &CurrentClip| {
+                                    if clip_expired(row) {
+                                        return;
+                                    }
+                                    let _ =
+                                        tx.blocking_send(SpacetimeEvent::ClipUpdated(row.clone()));
+                                },
+                            );
+
+                            let tx = event_tx_for_clip.clone();
+                            ctx.db.my_current_clip().on_delete(
+                                move |_ctx: &EventContext, row: &CurrentClip| {
+                                    let _ = tx.blocking_send(SpacetimeEvent::ClipRemoved {
+                                        updated_at: row.updated_at,
+                                    });
+                                },
+                            );
+
+                            let tx = event_tx_for_clip.clone();
+                            ctx.db.my_clip_acks().on_insert(
+                                move |_ctx: &EventContext, row: &ClipAck| {
+                                    let _ =
+                                        tx.blocking_send(SpacetimeEvent::ClipAcked(row.clone()));
+                                },
+                            );
+
+                            let pending = pending_consumes_for_sub.clone();
+                            ctx.db.my_consumed_clip().on_insert(
+                                move |_ctx: &EventContext, row: &ConsumedClip| {
+                                    let waiter = pending.lock().unwrap().remove(&row.request_id);
+                                    if let Some(reply) = waiter {
+                                        let _ = reply.send(Ok(row.clone()));
+                                    }
+                                },
+                            );
+                        })
+                        .subscribe_to_all_tables();
+                },
+            )
             .on_disconnect(
                 move |_ctx: &ErrorContext, err: Option<spacetimedb_sdk::Error>| {
                     if let Some(e) = err {
                         warn!("Disconnected from SpacetimeDB: {:?}", e);
+                        if is_auth_error(&format!("{:?}", e)) {
+                            auth_rejected_cb.store(true, Ordering::Release);
+                        }
                     } else {
                         info!("Disconnected from SpacetimeDB");
                     }
@@ -179,6 +372,10 @@ fn spacetime_thread_main(
             Ok(c) => c,
             Err(e) => {
                 error!("Failed to connect to SpacetimeDB: {}", e);
+                if is_auth_error(&format!("{:?}", e)) {
+                    report_auth_expired(event_tx);
+                    return;
+                }
                 backoff = (backoff * 2).min(MAX_BACKOFF);
                 continue;
             }
@@ -188,16 +385,25 @@ fn spacetime_thread_main(
 
         // Run the connection on a background thread
         let conn_for_run = conn.clone();
-        let _handle = conn_for_run.run_threaded();
+        let run_handle = conn_for_run.run_threaded();
 
         // Reset backoff on successful connection build
         backoff = INITIAL_BACKOFF;
 
         // Inner command processing loop
         loop {
+            heartbeat.tick();
             match command_rx.recv_timeout(DISCONNECT_CHECK_INTERVAL) {
-                Ok(cmd) => handle_command(&conn, cmd),
+                Ok(cmd) => handle_command(&conn, &pending_consumes, cmd),
                 Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                    if fingerprint_rejected.load(Ordering::Acquire) {
+                        error!("Server failed the server_fingerprint check, not reconnecting");
+                        return;
+                    }
+                    if auth_rejected.load(Ordering::Acquire) {
+                        report_auth_expired(event_tx);
+                        return;
+                    }
                     if disconnected.load(Ordering::Acquire) {
                         info!("Disconnect detected, will attempt reconnect");
                         break;
@@ -205,6 +411,12 @@ fn spacetime_thread_main(
                 }
                 Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
                     info!("Command channel closed, shutting down SpacetimeDB thread");
+                    // Close the connection so the server drops this client
+                    // now rather than when the socket times out
+                    if !disconnected.load(Ordering::Acquire) {
+                        let _ = conn.disconnect();
+                        let _ = run_handle.join();
+                    }
                     return;
                 }
             }
@@ -213,6 +425,37 @@ fn spacetime_thread_main(
     }
 }
 
+/// Whether a clip synced with a TTL has expired. The server's sweep deletes
+/// it at that time, but the row can linger briefly until it runs.
+pub fn clip_expired(clip: &CurrentClip) -> bool {
+    let now_micros = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0);
+    clip.expires_at
+        .is_some_and(|expires_at| expires_at.to_micros_since_unix_epoch() <= now_micros)
+}
+
+/// Whether a connection error means the server refused our token, as opposed
+/// to a network problem worth retrying.
+fn is_auth_error(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    [
+        "401",
+        "unauthorized",
+        "invalid token",
+        "token expired",
+        "expired token",
+    ]
+    .iter()
+    .any(|needle| message.contains(needle))
+}
+
+fn report_auth_expired(event_tx: &mpsc::Sender<SpacetimeEvent>) {
+    error!("SpacetimeDB rejected the saved token, not reconnecting");
+    let _ = event_tx.blocking_send(SpacetimeEvent::AuthExpired);
+}
+
 /// Drain any commands that accumulated in the channel during reconnect backoff.
 /// Commands with reply channels get their senders dropped, which signals an error
 /// to the caller rather than leaving them hanging indefinitely.
@@ -223,25 +466,51 @@ fn drain_pending_commands(command_rx: &crossbeam_channel::Receiver<SpacetimeComm
         // Reply senders are dropped here, causing callers to get Err(Canceled)
     }
     if drained > 0 {
-        warn!(
-            "Drained {} commands that queued during reconnect",
-            drained
-        );
+        warn!("Drained {} commands that queued during reconnect", drained);
     }
 }
 
-fn handle_command(conn: &DbConnection, cmd: SpacetimeCommand) {
+/// Callback for a reducer's `_then` call that answers `reply` once the server
+/// has run the reducer: Ok only after its changes committed, otherwise the
+/// reducer's own error (e.g. a wrong password).
+fn reply_with_outcome<E: std::fmt::Display + 'static>(
+    reply: oneshot::Sender<Result<(), String>>,
+) -> impl FnOnce(&ReducerEventContext, Result<Result<(), String>, E>) + Send + 'static {
+    move |_ctx: &ReducerEventContext, outcome: Result<Result<(), String>, E>| {
+        let _ = reply.send(outcome.unwrap_or_else(|e| Err(e.to_string())));
+    }
+}
+
+/// Like `reply_with_outcome`, for reducers that check the account password. A
+/// wrong password still commits (so it counts toward the lockout), with the
+/// refusal reported in `my_auth_result` instead of as the reducer's error.
+fn reply_with_auth_outcome<E: std::fmt::Display + 'static>(
+    reply: oneshot::Sender<Result<(), String>>,
+) -> impl FnOnce(&ReducerEventContext, Result<Result<(), String>, E>) + Send + 'static {
+    move |ctx: &ReducerEventContext, outcome: Result<Result<(), String>, E>| {
+        let result = outcome.unwrap_or_else(|e| Err(e.to_string()));
+        let refused = ctx.db.my_auth_result().iter().next().and_then(|r| r.error);
+        let _ = reply.send(result.and_then(|()| refused.map_or(Ok(()), Err)));
+    }
+}
+
+fn handle_command(conn: &DbConnection, pending_consumes: &PendingConsumes, cmd: SpacetimeCommand) {
     match cmd {
         SpacetimeCommand::SyncClip {
             device_id,
             content_type,
             encrypted_data,
             size_bytes,
+            options,
         } => {
-            if let Err(e) = conn
-                .reducers
-                .sync_clip(device_id, content_type, encrypted_data, size_bytes)
-            {
+            let result = conn.reducers.sync_clip(
+                device_id,
+                content_type,
+                encrypted_data,
+                size_bytes,
+                options,
+            );
+            if let Err(e) = result {
                 error!("Failed to call sync_clip: {}", e);
             }
         }
@@ -253,14 +522,68 @@ fn handle_command(conn: &DbConnection, cmd: SpacetimeCommand) {
                 error!("Failed to call register_device: {}", e);
             }
         }
+        SpacetimeCommand::AckClip {
+            device_id,
+            clip_updated_at,
+        } => {
+            if let Err(e) = conn.reducers.ack_clip(device_id, clip_updated_at) {
+                warn!("Failed to call ack_clip: {}", e);
+            }
+        }
         SpacetimeCommand::ListDevices { reply } => {
             let devices: Vec<DeviceView> = conn.db.my_devices().iter().collect();
             let _ = reply.send(devices);
         }
+        SpacetimeCommand::ListDevicesPage {
+            offset,
+            limit,
+            reply,
+        } => {
+            // The server cuts the page, so only it crosses the wire
+            let result = conn.reducers.get_devices_page_then(
+                offset,
+                limit,
+                move |ctx: &ReducerEventContext, outcome| {
+                    let result = outcome
+                        .unwrap_or_else(|e| Err(e.to_string()))
+                        .and_then(|()| {
+                            ctx.db
+                                .my_device_page()
+                                .iter()
+                                .next()
+                                .map(|page| (page.devices, page.total))
+                                .ok_or_else(|| "The server returned no device page".to_string())
+                        });
+                    let _ = reply.send(result);
+                },
+            );
+            if let Err(e) = result {
+                error!("Failed to call get_devices_page: {}", e);
+            }
+        }
         SpacetimeCommand::GetCurrentClip { reply } => {
-            let clip = conn.db.my_current_clip().iter().next();
+            let clip = conn
+                .db
+                .my_current_clip()
+                .iter()
+                .find(|clip| !clip_expired(clip));
             let _ = reply.send(clip);
         }
+        SpacetimeCommand::ListRecentByDevice { reply } => {
+            let clips: Vec<DeviceClipView> = conn.db.my_recent_by_device().iter().collect();
+            let _ = reply.send(clips);
+        }
+        SpacetimeCommand::RestoreDeviceClip {
+            sender_device_id,
+            reply,
+        } => {
+            if let Err(e) = conn
+                .reducers
+                .restore_device_clip_then(sender_device_id, reply_with_outcome(reply))
+            {
+                error!("Failed to call restore_device_clip: {}", e);
+            }
+        }
         SpacetimeCommand::GetUsername { reply } => {
             let username = conn
                 .db
@@ -270,12 +593,200 @@ fn handle_command(conn: &DbConnection, cmd: SpacetimeCommand) {
                 .map(|p| p.username.clone());
             let _ = reply.send(username);
         }
+        SpacetimeCommand::GetUserId { reply } => {
+            let user_id = conn.db.my_profile().iter().next().map(|p| p.user_id);
+            let _ = reply.send(user_id);
+        }
+        SpacetimeCommand::GetPublicKey { reply } => {
+            let public_key = conn
+                .db
+                .my_profile()
+                .iter()
+                .next()
+                .map(|p| p.public_key.clone());
+            let _ = reply.send(public_key);
+        }
+        SpacetimeCommand::GetFailedLoginAttempts { reply } => {
+            let attempts = conn
+                .db
+                .my_failed_login()
+                .iter()
+                .next()
+                .map_or(0, |record| record.attempt_count);
+            let _ = reply.send(attempts);
+        }
         SpacetimeCommand::CreateInviteCode { code, reply } => {
-            if let Err(e) = conn.reducers.create_invite_code(code) {
-                let _ = reply.send(Err(format!("{}", e)));
-            } else {
-                let _ = reply.send(Ok(()));
+            if let Err(e) = conn
+                .reducers
+                .create_invite_code_then(code, reply_with_outcome(reply))
+            {
+                error!("Failed to call create_invite_code: {}", e);
+            }
+        }
+        SpacetimeCommand::GetServerStats { reply } => {
+            let stats = conn.db.server_stats().iter().next();
+            let _ = reply.send(stats);
+        }
+        SpacetimeCommand::ConsumeCurrentClip { reply } => {
+            let request_id = uuid::Uuid::new_v4().to_string();
+            {
+                let mut pending = pending_consumes.lock().unwrap();
+                // Forget consumes whose caller gave up (e.g. another device won the race)
+                pending.retain(|_, waiter| !waiter.is_closed());
+                pending.insert(request_id.clone(), reply);
+            }
+            let pending = pending_consumes.clone();
+            let waiting_id = request_id.clone();
+            let on_done = move |ctx: &ReducerEventContext,
+                                outcome: Result<Result<(), String>, _>| {
+                // Normally the row's on_insert has already answered
+                let Some(reply) = pending.lock().unwrap().remove(&waiting_id) else {
+                    return;
+                };
+                let result = match outcome {
+                    Ok(Ok(())) => ctx
+                        .db
+                        .my_consumed_clip()
+                        .iter()
+                        .find(|clip| clip.request_id == waiting_id)
+                        .ok_or_else(|| "The consumed clip was not received".to_string()),
+                    Ok(Err(e)) => Err(e),
+                    Err(e) => Err(e.to_string()),
+                };
+                let _ = reply.send(result);
+            };
+            if let Err(e) = conn
+                .reducers
+                .consume_current_clip_then(request_id.clone(), on_done)
+            {
+                error!("Failed to call consume_current_clip: {}", e);
+                // Dropping the reply signals the failure to the caller
+                pending_consumes.lock().unwrap().remove(&request_id);
+            }
+        }
+        SpacetimeCommand::RenameUser {
+            new_username,
+            password,
+            reply,
+        } => {
+            // Dropping the reply signals a failed call to the caller
+            if let Err(e) = conn.reducers.rename_user_then(
+                new_username,
+                password,
+                reply_with_auth_outcome(reply),
+            ) {
+                error!("Failed to call rename_user: {}", e);
+            }
+        }
+        SpacetimeCommand::MergeDevice {
+            old_device_id,
+            new_device_id,
+            reply,
+        } => {
+            if let Err(e) = conn.reducers.merge_device_then(
+                old_device_id,
+                new_device_id,
+                reply_with_outcome(reply),
+            ) {
+                error!("Failed to call merge_device: {}", e);
+            }
+        }
+        SpacetimeCommand::ClearCurrentClip { reply } => {
+            let result = conn
+                .reducers
+                .clear_current_clip()
+                .map_err(|e| format!("{}", e));
+            let _ = reply.send(result);
+        }
+        SpacetimeCommand::RevokeAllIdentities { password, reply } => {
+            if let Err(e) = conn
+                .reducers
+                .revoke_all_identities_then(password, reply_with_auth_outcome(reply))
+            {
+                error!("Failed to call revoke_all_identities: {}", e);
             }
         }
+        SpacetimeCommand::CreateTeam { name, reply } => {
+            if let Err(e) = conn
+                .reducers
+                .create_team_then(name, reply_with_outcome(reply))
+            {
+                error!("Failed to call create_team: {}", e);
+            }
+        }
+        SpacetimeCommand::AddTeamMember {
+            team,
+            username,
+            reply,
+        } => {
+            if let Err(e) =
+                conn.reducers
+                    .add_team_member_then(team, username, reply_with_outcome(reply))
+            {
+                error!("Failed to call add_team_member: {}", e);
+            }
+        }
+        SpacetimeCommand::RemoveTeamMember {
+            team,
+            username,
+            reply,
+        } => {
+            if let Err(e) =
+                conn.reducers
+                    .remove_team_member_then(team, username, reply_with_outcome(reply))
+            {
+                error!("Failed to call remove_team_member: {}", e);
+            }
+        }
+        SpacetimeCommand::ListTeams { reply } => {
+            let members: Vec<TeamMemberView> = conn.db.my_team_members().iter().collect();
+            let teams = conn
+                .db
+                .my_teams()
+                .iter()
+                .map(|team| {
+                    let team_members = members
+                        .iter()
+                        .filter(|m| m.team_id == team.team_id)
+                        .cloned()
+                        .collect();
+                    (team, team_members)
+                })
+                .collect();
+            let _ = reply.send(teams);
+        }
+        SpacetimeCommand::SyncTeamClip {
+            team_id,
+            members_version,
+            device_id,
+            content_type,
+            encrypted_data,
+            size_bytes,
+        } => {
+            if let Err(e) = conn.reducers.sync_team_clip(
+                team_id,
+                members_version,
+                device_id,
+                content_type,
+                encrypted_data,
+                size_bytes,
+            ) {
+                error!("Failed to call sync_team_clip: {}", e);
+            }
+        }
+        SpacetimeCommand::GetTeamClip { team, reply } => {
+            let clip = conn
+                .db
+                .my_teams()
+                .iter()
+                .find(|t| t.name == team)
+                .and_then(|t| {
+                    conn.db
+                        .my_team_clips()
+                        .iter()
+                        .find(|c| c.team_id == t.team_id)
+                });
+            let _ = reply.send(clip);
+        }
     }
 }
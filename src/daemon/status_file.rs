@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+use tracing::warn;
+
+use crate::config;
+
+/// The daemon state a status bar shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Indicator {
+    pub connected: bool,
+    pub watching: bool,
+    pub frozen: bool,
+    /// Syncing paused because the session is locked
+    pub locked: bool,
+}
+
+impl Indicator {
+    /// One word for bars that only show one, then every flag as `key=value`,
+    /// e.g. `state=connected connected=true watching=true frozen=false locked=false`.
+    fn line(&self) -> String {
+        let state = if !self.connected {
+            "disconnected"
+        } else if self.locked {
+            "locked"
+        } else if self.frozen {
+            "frozen"
+        } else {
+            "connected"
+        };
+        format!(
+            "state={} connected={} watching={} frozen={} locked={}\n",
+            state, self.connected, self.watching, self.frozen, self.locked
+        )
+    }
+}
+
+/// The `write_status_file` file, rewritten (atomically) only when the
+/// indicator changes, so status bars can `cat` it as often as they like.
+pub struct StatusFile {
+    path: PathBuf,
+    written: Option<Indicator>,
+}
+
+impl StatusFile {
+    pub fn new() -> Self {
+        Self {
+            path: config::status_file_path(),
+            written: None,
+        }
+    }
+
+    pub fn update(&mut self, indicator: Indicator) {
+        if self.written == Some(indicator) {
+            return;
+        }
+        // Not retried until the state changes again, so a failing write
+        // warns once rather than on every loop iteration
+        self.written = Some(indicator);
+        if let Err(e) = config::write_private_file(&self.path, indicator.line().as_bytes()) {
+            warn!("Failed to write the status file: {:#}", e);
+        }
+    }
+
+    /// Remove the file on shutdown, so a stale state isn't shown.
+    pub fn remove(&self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indicator(connected: bool, frozen: bool, locked: bool) -> Indicator {
+        Indicator {
+            connected,
+            watching: true,
+            frozen,
+            locked,
+        }
+    }
+
+    #[test]
+    fn line_lists_every_flag() {
+        assert_eq!(
+            indicator(true, false, false).line(),
+            "state=connected connected=true watching=true frozen=false locked=false\n"
+        );
+    }
+
+    #[test]
+    fn state_shows_the_most_important_flag() {
+        assert!(indicator(false, true, true)
+            .line()
+            .starts_with("state=disconnected "));
+        assert!(indicator(true, true, true)
+            .line()
+            .starts_with("state=locked "));
+        assert!(indicator(true, true, false)
+            .line()
+            .starts_with("state=frozen "));
+    }
+}
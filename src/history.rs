@@ -0,0 +1,576 @@
+use age::x25519;
+use anyhow::{Context, Result};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::crypto;
+use crate::payload::ClipboardPayload;
+
+/// Upper bound on the on-disk size of the local history file (256 MB).
+pub const MAX_HISTORY_FILE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Maximum size of a single serialized history entry (64 MB).
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How much local history to keep. The oldest entries are dropped first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub max_entries: usize,
+    /// Total size of the stored (encrypted) entries, capped at
+    /// `MAX_HISTORY_FILE_SIZE`
+    pub max_bytes: usize,
+}
+
+impl Retention {
+    /// The bounds a compaction prunes down to: a tenth below the limits, so
+    /// the appends that follow don't each rewrite the file again.
+    fn compaction_target(self) -> Self {
+        let max_bytes = self.max_bytes.min(MAX_HISTORY_FILE_SIZE);
+        Self {
+            max_entries: self.max_entries - self.max_entries / 10,
+            max_bytes: max_bytes - max_bytes / 10,
+        }
+    }
+}
+
+/// Record count and total size of the history file. The history writer
+/// keeps these up to date between appends, so it only reads the file when
+/// it compacts it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileStats {
+    pub records: usize,
+    pub bytes: usize,
+}
+
+impl FileStats {
+    fn of(records: &[&[u8]]) -> Self {
+        Self {
+            records: records.len(),
+            bytes: records.iter().map(|record| record.len() + 4).sum(),
+        }
+    }
+}
+
+/// A single clipboard change recorded in the local history file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub payload: ClipboardPayload,
+    /// The app the clip was copied from, where known
+    pub source_app: Option<String>,
+}
+
+/// The record layout from before entries carried `source_app`. bincode isn't
+/// self-describing, so those records are read with this instead.
+#[derive(Serialize, Deserialize)]
+struct LegacyHistoryEntry {
+    timestamp: u64,
+    payload: ClipboardPayload,
+}
+
+impl HistoryEntry {
+    pub fn new(payload: ClipboardPayload, source_app: Option<String>) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            timestamp,
+            payload,
+            source_app,
+        }
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        bincode::DefaultOptions::new()
+            .with_limit(MAX_ENTRY_SIZE)
+            .serialize(self)
+            .with_context(|| "Failed to serialize history entry")
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self> {
+        let options = bincode::DefaultOptions::new().with_limit(MAX_ENTRY_SIZE);
+        // A legacy record ends where `source_app` would start, so it never
+        // reads as the current layout
+        options
+            .deserialize(data)
+            .or_else(|_| {
+                options
+                    .deserialize(data)
+                    .map(|legacy: LegacyHistoryEntry| Self {
+                        timestamp: legacy.timestamp,
+                        payload: legacy.payload,
+                        source_app: None,
+                    })
+            })
+            .with_context(|| "Failed to deserialize history entry")
+    }
+}
+
+pub fn history_file_path() -> Result<PathBuf> {
+    Ok(config::config_dir()?.join("local_history"))
+}
+
+/// Split the history file into its length-prefixed records. A truncated
+/// trailing record (e.g. from a crash mid-append) is ignored.
+fn split_records(data: &[u8]) -> Vec<&[u8]> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 4 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() - 4 < len {
+            break;
+        }
+        records.push(&rest[4..4 + len]);
+        rest = &rest[4 + len..];
+    }
+    records
+}
+
+/// Where one record's data sits in the history file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RecordSpan {
+    offset: u64,
+    len: usize,
+}
+
+/// Locate the history file's records by reading only their length prefixes,
+/// seeking over the data. A truncated trailing record is ignored, as in
+/// `split_records`.
+fn scan_records(file: &mut (impl Read + Seek)) -> Result<Vec<RecordSpan>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    let mut prefix = [0u8; 4];
+    while file_len - pos >= 4 {
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut prefix)?;
+        let len = u32::from_be_bytes(prefix) as u64;
+        if file_len - pos - 4 < len {
+            break;
+        }
+        spans.push(RecordSpan {
+            offset: pos + 4,
+            len: len as usize,
+        });
+        pos += 4 + len;
+    }
+    Ok(spans)
+}
+
+/// Read and decrypt the record at `span`. `Ok(None)` means the record is
+/// there but doesn't decrypt (e.g. written under an old key).
+fn read_entry(
+    file: &mut (impl Read + Seek),
+    span: RecordSpan,
+    identity: &x25519::Identity,
+) -> Result<Option<HistoryEntry>> {
+    let mut record = vec![0u8; span.len];
+    file.seek(SeekFrom::Start(span.offset))?;
+    file.read_exact(&mut record)?;
+    Ok(crypto::decrypt(&record, identity)
+        .ok()
+        .and_then(|plaintext| HistoryEntry::deserialize(&plaintext).ok()))
+}
+
+fn frame_record(buf: &mut Vec<u8>, record: &[u8]) {
+    buf.extend_from_slice(&(record.len() as u32).to_be_bytes());
+    buf.extend_from_slice(record);
+}
+
+/// Drop the oldest records until both the count and total size bounds hold.
+/// Counts back from the newest record, so a few large images evict as many
+/// older entries as it takes in one pass.
+fn prune_records(records: &mut Vec<&[u8]>, retention: Retention) {
+    let max_bytes = retention.max_bytes.min(MAX_HISTORY_FILE_SIZE);
+    let mut total = 0;
+    let kept = records
+        .iter()
+        .rev()
+        .take(retention.max_entries)
+        .take_while(|record| {
+            total += record.len() + 4;
+            total <= max_bytes
+        })
+        .count();
+    records.drain(..records.len() - kept);
+}
+
+#[cfg(unix)]
+fn open_options() -> std::fs::OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut options = std::fs::OpenOptions::new();
+    options.mode(0o600);
+    options
+}
+
+#[cfg(not(unix))]
+fn open_options() -> std::fs::OpenOptions {
+    std::fs::OpenOptions::new()
+}
+
+/// Existing history files may predate the 0600 mode set on creation, or have
+/// been loosened since; clip contents stay encrypted either way, but the file
+/// shouldn't be readable by other users at all.
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| "Failed to restrict local history permissions")?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Encrypt an entry to this device's own key. Only the record length is
+/// stored in the clear.
+fn encrypt_entry(entry: &HistoryEntry, identity: &x25519::Identity) -> Result<Vec<u8>> {
+    crypto::encrypt(&entry.serialize()?, &[identity.to_public()])
+}
+
+/// Count the records in the history file from their length prefixes.
+pub fn file_stats() -> Result<FileStats> {
+    let path = history_file_path()?;
+    if !path.exists() {
+        return Ok(FileStats::default());
+    }
+    let mut file = File::open(&path).with_context(|| "Failed to read local history")?;
+    let spans = scan_records(&mut file).with_context(|| "Failed to read local history")?;
+    Ok(FileStats {
+        records: spans.len(),
+        bytes: spans.iter().map(|span| span.len + 4).sum(),
+    })
+}
+
+/// Encrypt an entry to our own key and append it to the history file, whose
+/// current size is `stats`. Only when the entry would exceed either
+/// `retention` bound is the file read, and then it is compacted to a little
+/// under them.
+pub fn append(
+    entry: &HistoryEntry,
+    identity: &x25519::Identity,
+    retention: Retention,
+    stats: &mut FileStats,
+) -> Result<()> {
+    let encrypted = encrypt_entry(entry, identity)?;
+
+    config::ensure_config_dir()?;
+    let path = history_file_path()?;
+    let needs_compaction = stats.records >= retention.max_entries
+        || stats.bytes + encrypted.len() + 4 > retention.max_bytes.min(MAX_HISTORY_FILE_SIZE);
+
+    if needs_compaction {
+        let existing = if path.exists() {
+            std::fs::read(&path).with_context(|| "Failed to read local history")?
+        } else {
+            Vec::new()
+        };
+        let mut records = split_records(&existing);
+        records.push(encrypted.as_slice());
+        prune_records(&mut records, retention.compaction_target());
+        rewrite(&path, &records)?;
+        *stats = FileStats::of(&records);
+    } else {
+        let mut buf = Vec::with_capacity(encrypted.len() + 4);
+        frame_record(&mut buf, &encrypted);
+        open_options()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(&buf))
+            .with_context(|| "Failed to append to local history")?;
+        restrict_permissions(&path)?;
+        stats.records += 1;
+        stats.bytes += buf.len();
+    }
+
+    Ok(())
+}
+
+/// Replace the history file with `records` via a temp file and rename.
+fn rewrite(path: &Path, records: &[&[u8]]) -> Result<()> {
+    let mut buf = Vec::new();
+    for record in records {
+        frame_record(&mut buf, record);
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    open_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)
+        .and_then(|mut f| f.write_all(&buf))
+        .with_context(|| "Failed to write local history")?;
+    std::fs::rename(&tmp_path, path).with_context(|| "Failed to replace local history")?;
+    Ok(())
+}
+
+/// Position in `records` (oldest first) of the entry at history `index`
+/// (newest first), checking that it has the expected timestamp.
+fn find_record(
+    records: &[&[u8]],
+    identity: &x25519::Identity,
+    index: usize,
+    timestamp: u64,
+) -> Result<usize> {
+    let no_entry = || anyhow::anyhow!("No local history entry at index {}", index);
+    let position = records.len().checked_sub(index + 1).ok_or_else(no_entry)?;
+    let entry = crypto::decrypt(records[position], identity)
+        .ok()
+        .and_then(|plaintext| HistoryEntry::deserialize(&plaintext).ok())
+        .ok_or_else(no_entry)?;
+    if entry.timestamp != timestamp {
+        anyhow::bail!("Local history changed since it was read; reload and try again");
+    }
+    Ok(position)
+}
+
+/// Remove the entry at history `index`. `timestamp` must match that
+/// entry, so a clip appended in the meantime can't shift the wrong one out.
+pub fn delete(identity: &x25519::Identity, index: usize, timestamp: u64) -> Result<()> {
+    let path = history_file_path()?;
+    let data = std::fs::read(&path).with_context(|| "Failed to read local history")?;
+    let mut records = split_records(&data);
+    let position = find_record(&records, identity, index, timestamp)?;
+    records.remove(position);
+    rewrite(&path, &records)
+}
+
+/// Decrypt `limit` entries of the local history starting at history index
+/// `offset`, along with the number of records in the file. History indexes
+/// count records newest first, and only the records on the page are read and
+/// decrypted. Entries that fail to decrypt (e.g. written under an old key)
+/// are skipped but keep their index, so indexes don't depend on the page.
+pub fn load_page(
+    identity: &x25519::Identity,
+    offset: usize,
+    limit: usize,
+) -> Result<(Vec<(usize, HistoryEntry)>, usize)> {
+    let path = history_file_path()?;
+    if !path.exists() {
+        return Ok((Vec::new(), 0));
+    }
+    let mut file = File::open(&path).with_context(|| "Failed to read local history")?;
+    let spans = scan_records(&mut file).with_context(|| "Failed to read local history")?;
+
+    let mut entries = Vec::new();
+    for (index, span) in spans.iter().rev().enumerate().skip(offset).take(limit) {
+        let entry = read_entry(&mut file, *span, identity)
+            .with_context(|| "Failed to read local history")?;
+        entries.extend(entry.map(|entry| (index, entry)));
+    }
+    Ok((entries, spans.len()))
+}
+
+/// Load and decrypt every entry in the local history with its index, newest
+/// first.
+pub fn load(identity: &x25519::Identity) -> Result<Vec<(usize, HistoryEntry)>> {
+    Ok(load_page(identity, 0, usize::MAX)?.0)
+}
+
+/// Decrypt only the entry at history `index`.
+pub fn get(identity: &x25519::Identity, index: usize) -> Result<HistoryEntry> {
+    load_page(identity, index, 1)?
+        .0
+        .pop()
+        .map(|(_, entry)| entry)
+        .ok_or_else(|| anyhow::anyhow!("No local history entry at index {}", index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_records_round_trip() {
+        let mut buf = Vec::new();
+        frame_record(&mut buf, b"first");
+        frame_record(&mut buf, b"");
+        frame_record(&mut buf, b"third");
+        assert_eq!(
+            split_records(&buf),
+            vec![&b"first"[..], &b""[..], &b"third"[..]]
+        );
+    }
+
+    #[test]
+    fn split_records_ignores_truncated_tail() {
+        let mut buf = Vec::new();
+        frame_record(&mut buf, b"complete");
+        frame_record(&mut buf, b"partial");
+        buf.truncate(buf.len() - 3);
+        assert_eq!(split_records(&buf), vec![&b"complete"[..]]);
+    }
+
+    #[test]
+    fn prune_records_keeps_newest() {
+        let mut records: Vec<&[u8]> = vec![&b"a"[..], &b"b"[..], &b"c"[..], &b"d"[..]];
+        let retention = Retention {
+            max_entries: 2,
+            max_bytes: MAX_HISTORY_FILE_SIZE,
+        };
+        prune_records(&mut records, retention);
+        assert_eq!(records, vec![&b"c"[..], &b"d"[..]]);
+    }
+
+    #[test]
+    fn prune_records_respects_byte_cap() {
+        let large = vec![0u8; 1000];
+        let small = vec![1u8; 10];
+        // Oldest first: small, large, small, large, small
+        let mut records: Vec<&[u8]> = vec![&small, &large, &small, &large, &small];
+        let retention = Retention {
+            max_entries: 10,
+            max_bytes: 1100,
+        };
+        prune_records(&mut records, retention);
+        // The newest large entry fits with the two smalls around it; the
+        // older large one would push the total over the cap
+        assert_eq!(records, vec![&small[..], &large[..], &small[..]]);
+        let total: usize = records.iter().map(|r| r.len() + 4).sum();
+        assert!(total <= retention.max_bytes);
+
+        // An entry larger than the cap on its own isn't kept
+        let mut records: Vec<&[u8]> = vec![&small, &large];
+        prune_records(
+            &mut records,
+            Retention {
+                max_entries: 10,
+                max_bytes: 100,
+            },
+        );
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn compaction_leaves_slack() {
+        let records: Vec<Vec<u8>> = (0..=100u8).map(|i| vec![i; 10]).collect();
+        let mut records: Vec<&[u8]> = records.iter().map(Vec::as_slice).collect();
+        let retention = Retention {
+            max_entries: 100,
+            max_bytes: MAX_HISTORY_FILE_SIZE,
+        };
+        prune_records(&mut records, retention.compaction_target());
+        assert_eq!(records.len(), 90);
+        assert_eq!(records[89], &[100u8; 10][..]);
+        assert_eq!(
+            FileStats::of(&records),
+            FileStats {
+                records: 90,
+                bytes: 90 * 14,
+            }
+        );
+
+        // Byte-bound compactions leave room too
+        let target = Retention {
+            max_entries: 100,
+            max_bytes: 1000,
+        }
+        .compaction_target();
+        assert_eq!(target.max_bytes, 900);
+    }
+
+    #[test]
+    fn stored_record_does_not_contain_plaintext() {
+        let (identity, _) = crypto::generate_keypair();
+        let secret = "correct horse battery staple";
+        let entry = HistoryEntry::new(ClipboardPayload::Text(secret.to_string()), None);
+
+        let mut on_disk = Vec::new();
+        frame_record(&mut on_disk, &encrypt_entry(&entry, &identity).unwrap());
+        assert!(!on_disk
+            .windows(secret.len())
+            .any(|window| window == secret.as_bytes()));
+
+        let record = split_records(&on_disk)[0];
+        let recovered =
+            HistoryEntry::deserialize(&crypto::decrypt(record, &identity).unwrap()).unwrap();
+        assert!(matches!(recovered.payload, ClipboardPayload::Text(s) if s == secret));
+    }
+
+    #[test]
+    fn find_record_counts_newest_first() {
+        let (identity, recipient) = crypto::generate_keypair();
+        let encrypt = |timestamp: u64| {
+            let entry = HistoryEntry {
+                timestamp,
+                payload: ClipboardPayload::Text(timestamp.to_string()),
+                source_app: None,
+            };
+            crypto::encrypt(&entry.serialize().unwrap(), &[recipient.clone()]).unwrap()
+        };
+        let (oldest, newest) = (encrypt(1), encrypt(3));
+        let records = vec![
+            oldest.as_slice(),
+            b"not encrypted".as_slice(),
+            newest.as_slice(),
+        ];
+
+        assert_eq!(find_record(&records, &identity, 0, 3).unwrap(), 2);
+        assert_eq!(find_record(&records, &identity, 2, 1).unwrap(), 0);
+        assert!(find_record(&records, &identity, 0, 1).is_err());
+        // The unreadable record keeps its index
+        assert!(find_record(&records, &identity, 1, 1).is_err());
+        assert!(find_record(&records, &identity, 3, 1).is_err());
+    }
+
+    #[test]
+    fn scan_records_matches_split_records() {
+        let mut buf = Vec::new();
+        frame_record(&mut buf, b"first");
+        frame_record(&mut buf, b"");
+        frame_record(&mut buf, b"third");
+        frame_record(&mut buf, b"partial");
+        buf.truncate(buf.len() - 3);
+
+        let spans = scan_records(&mut std::io::Cursor::new(&buf)).unwrap();
+        let scanned: Vec<&[u8]> = spans
+            .iter()
+            .map(|span| &buf[span.offset as usize..span.offset as usize + span.len])
+            .collect();
+        assert_eq!(scanned, split_records(&buf));
+    }
+
+    #[test]
+    fn entry_encrypt_decrypt_round_trip() {
+        let (identity, recipient) = crypto::generate_keypair();
+        let entry = HistoryEntry::new(
+            ClipboardPayload::Text("hello".to_string()),
+            Some("firefox".to_string()),
+        );
+        let encrypted = crypto::encrypt(&entry.serialize().unwrap(), &[recipient]).unwrap();
+        let decrypted = crypto::decrypt(&encrypted, &identity).unwrap();
+        let recovered = HistoryEntry::deserialize(&decrypted).unwrap();
+        assert_eq!(recovered.timestamp, entry.timestamp);
+        assert_eq!(recovered.source_app.as_deref(), Some("firefox"));
+        match recovered.payload {
+            ClipboardPayload::Text(s) => assert_eq!(s, "hello"),
+            _ => panic!("Expected Text variant"),
+        }
+    }
+
+    #[test]
+    fn reads_entries_from_before_source_app() {
+        let legacy = LegacyHistoryEntry {
+            timestamp: 42,
+            payload: ClipboardPayload::Text("old".to_string()),
+        };
+        let data = bincode::DefaultOptions::new().serialize(&legacy).unwrap();
+        let recovered = HistoryEntry::deserialize(&data).unwrap();
+        assert_eq!(recovered.timestamp, 42);
+        assert!(recovered.source_app.is_none());
+        assert!(matches!(recovered.payload, ClipboardPayload::Text(s) if s == "old"));
+    }
+}
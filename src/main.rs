@@ -2,15 +2,20 @@ mod cli;
 mod config;
 mod crypto;
 mod daemon;
+mod history;
 mod module_bindings;
 mod payload;
 mod protocol;
 
+use anyhow::Context;
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "clipsync", about = "Clipboard sync across machines")]
 struct Cli {
+    /// Only print errors and the command's output, not progress or hints
+    #[arg(short, long, global = true)]
+    quiet: bool,
     #[command(subcommand)]
     command: Command,
 }
@@ -18,7 +23,14 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
     /// Start the daemon (foreground)
-    Daemon,
+    Daemon {
+        /// Exit after the first clip is synced or received (exit code reflects the outcome)
+        #[arg(long)]
+        once: bool,
+        /// Log as JSON lines (for journald, Loki and other collectors)
+        #[arg(long)]
+        log_json: bool,
+    },
     /// Set up this device (creates account or logs in)
     Setup {
         /// Username
@@ -26,28 +38,267 @@ enum Command {
         /// Invite code (required for new accounts, not needed for first user or login)
         #[arg(long)]
         invite_code: Option<String>,
+        /// Print a password-encrypted recovery string for the private key
+        #[arg(long)]
+        print_recovery: bool,
+    },
+    /// Restore the private key from a recovery string printed by `setup --print-recovery`
+    ImportKey {
+        /// Recovery string (prompted for if omitted)
+        recovery: Option<String>,
+        /// Replace a different key that is already installed
+        #[arg(long)]
+        force: bool,
+    },
+    /// Move this account (same keypair) to a different SpacetimeDB instance
+    MigrateServer {
+        /// Server to migrate from (default: the configured server_url)
+        #[arg(long)]
+        from: Option<String>,
+        /// Server to migrate to
+        #[arg(long)]
+        to: String,
+        /// Database name on the new server (default: the configured database_name)
+        #[arg(long)]
+        database: Option<String>,
+        /// Invite code, if the new server already has users
+        #[arg(long)]
+        invite_code: Option<String>,
     },
     /// Sync clipboard content to SpacetimeDB
-    Copy,
+    ///
+    /// Reads `-` (stdin) or `--clipboard` (the system clipboard) when given;
+    /// otherwise piped stdin, falling back to the system clipboard when stdin
+    /// is a terminal.
+    Copy {
+        /// `-` to read stdin even when it is a terminal
+        #[arg(value_name = "-", value_parser = ["-"])]
+        input: Option<String>,
+        /// Read the system clipboard even when stdin is piped
+        #[arg(long, conflicts_with = "input")]
+        clipboard: bool,
+        /// Append to the current text clip (separated by a newline) instead of replacing it
+        #[arg(long)]
+        append: bool,
+        /// Treat stdin as HTML: the local clipboard gets HTML plus a plain-text
+        /// fallback, other devices get the plain text
+        #[arg(long, conflicts_with = "append")]
+        html: bool,
+        /// Only set this machine's clipboard from stdin, without syncing to the server
+        #[arg(long, visible_alias = "no-sync", conflicts_with = "append")]
+        local_only: bool,
+        /// Read this representation from the clipboard instead of text-then-image
+        /// (text/plain, text/html or image/png)
+        #[arg(long, value_name = "MIME", conflicts_with_all = ["input", "html", "local_only"])]
+        from_clipboard_target: Option<String>,
+        /// Exit successfully without syncing when the clipboard is empty
+        /// (also `allow_empty_copy` in the config)
+        #[arg(long)]
+        allow_empty: bool,
+        /// When the clipboard is empty, remove the synced clip from the server
+        #[arg(long, conflicts_with_all = ["append", "local_only"])]
+        clear_on_empty: bool,
+        /// Note which device (by name) the clip is meant for. Informational
+        /// only: every device can still paste it
+        #[arg(long, value_name = "NAME", conflicts_with = "local_only")]
+        target_device: Option<String>,
+        /// Skip the sync if identical content was synced less than this many
+        /// seconds ago (0 disables; default from `copy_dedup_window_ms`)
+        #[arg(long, value_name = "SECS", value_parser = cli::copy::parse_dedup_window)]
+        dedup_window: Option<std::time::Duration>,
+        /// Have the server delete the clip after this long, e.g. 30s or 5m
+        #[arg(long, value_parser = cli::freeze::parse_duration, conflicts_with = "local_only")]
+        ttl: Option<std::time::Duration>,
+        /// Size limit for just this copy instead of `max_clip_bytes`, e.g. 50M
+        /// (at most the server's 55M)
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = cli::copy::parse_max_size,
+            conflicts_with = "local_only"
+        )]
+        max_size: Option<usize>,
+        /// Skip content smaller than `min_sync_bytes`, as the clipboard
+        /// watcher does (an explicit copy otherwise syncs any size)
+        #[arg(long, conflicts_with = "local_only")]
+        apply_min_size: bool,
+        /// Wait until another device (or N of them) has applied the clip
+        #[arg(
+            long,
+            value_name = "N",
+            num_args = 0..=1,
+            default_missing_value = "1",
+            value_parser = clap::value_parser!(usize).range(1..),
+            conflicts_with = "local_only"
+        )]
+        wait_confirm: Option<usize>,
+        /// With --wait-confirm, give up after this long (e.g. 30s, 5m)
+        #[arg(
+            long,
+            requires = "wait_confirm",
+            value_parser = cli::freeze::parse_duration,
+            default_value = "30s"
+        )]
+        timeout: std::time::Duration,
+    },
+    /// Empty the clipboard
+    Clear {
+        /// Clear this machine's system clipboard (the synced clip is left alone)
+        #[arg(long, required = true)]
+        local: bool,
+    },
     /// Get latest clip from SpacetimeDB
     Paste {
         /// Only print the content type (text, image, files) instead of the data
         #[arg(long)]
         r#type: bool,
+        /// Take the clip off the server so no other device can paste it
+        #[arg(long, conflicts_with = "type")]
+        consume: bool,
+        /// Gzip-encode the output
+        #[arg(long, conflicts_with = "type")]
+        gzip: bool,
+        /// Write the clip to this file instead of stdout
+        #[arg(long, value_name = "PATH", conflicts_with = "type")]
+        to_file: Option<std::path::PathBuf>,
+        /// Block until the next clip arrives instead of pasting the current one
+        #[arg(long, conflicts_with = "consume")]
+        wait: bool,
+        /// With --wait, return at once if the current clip is newer than this
+        /// Unix time (seconds)
+        #[arg(long, value_name = "UNIX_SECS", requires = "wait")]
+        after: Option<i64>,
+        /// With --wait, give up after this long (e.g. 30s, 5m)
+        #[arg(long, requires = "wait", value_parser = cli::freeze::parse_duration)]
+        timeout: Option<std::time::Duration>,
+        /// Output nothing and exit with code 3 if the clip is the one this flag
+        /// last output (tracked in the config directory)
+        #[arg(long, conflicts_with = "consume")]
+        only_new: bool,
+        /// Output the clip's bytes whatever its content type, including types
+        /// this version of clipsync doesn't know
+        #[arg(long, conflicts_with = "type")]
+        raw: bool,
+        /// Encoding for stdout: raw bytes, or hex/base64 text that survives
+        /// text-only channels
+        #[arg(
+            long,
+            value_name = "raw|hex|base64",
+            default_value = "raw",
+            conflicts_with_all = ["type", "gzip", "to_file"]
+        )]
+        format: cli::paste::PasteFormat,
+        /// Show the clip's type, size, sender and (for text) a short preview
+        /// instead of its content
+        #[arg(
+            long,
+            conflicts_with_all = [
+                "type", "consume", "gzip", "to_file", "wait", "only_new", "raw", "format"
+            ]
+        )]
+        peek: bool,
+    },
+    /// Push the current clipboard to every device now, even if watching is off
+    Send {
+        /// Broadcast to all of this account's devices
+        #[arg(long, required = true)]
+        to_all: bool,
+    },
+    /// Show the current text clip as a QR code
+    Qr,
+    /// Measure local compression and encryption throughput (no daemon or server needed)
+    Bench,
+    /// Time clipboard reads on this machine and suggest a poll_interval_ms to match
+    Tune {
+        /// Share of one CPU core polling may use
+        #[arg(long, default_value_t = 1.0)]
+        cpu_percent: f64,
+    },
+    /// Check the local setup (config dir, keys, daemon socket) for common problems
+    Doctor {
+        /// Repair what can be repaired safely: permissions, a stale socket,
+        /// a missing config or device_id
+        #[arg(long)]
+        fix: bool,
     },
     /// Show daemon status
-    Status,
-    /// List registered devices
-    Devices,
+    Status {
+        /// Answer from the daemon's local state only, without querying SpacetimeDB
+        #[arg(long)]
+        socket_only: bool,
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+        /// Also show the type and size of what's on the local clipboard
+        /// (reads the clipboard)
+        #[arg(long)]
+        local_size: bool,
+    },
+    /// Stop incoming clips from other devices overwriting the local clipboard
+    Freeze {
+        /// Unfreeze automatically after this long (e.g. 30s, 5m, 1h)
+        #[arg(long = "for", value_parser = cli::freeze::parse_duration)]
+        duration: Option<std::time::Duration>,
+    },
+    /// Apply incoming clips to the local clipboard again
+    Unfreeze,
+    /// List registered devices (this device is marked with *)
+    Devices {
+        /// Only show this device
+        #[arg(long)]
+        me: bool,
+        /// Remove this duplicate device (by device ID), moving what it sent
+        /// over to the --into device
+        #[arg(long, value_name = "DEVICE_ID", conflicts_with = "me")]
+        merge: Option<String>,
+        /// Device ID to merge into (default: this device)
+        #[arg(long, value_name = "DEVICE_ID", requires = "merge")]
+        into: Option<String>,
+        /// Only list this page of devices (from 1), for accounts with many
+        #[arg(long, value_name = "N", conflicts_with_all = ["me", "merge"])]
+        page: Option<usize>,
+    },
     /// Get or set config values
     Config {
-        /// Config key (watch_clipboard, poll_interval_ms, server_url, database_name)
+        /// Config key (omit to list all keys and values)
         key: Option<String>,
         /// Value to set (omit to read current value)
         value: Option<String>,
+        /// Print the config (or the given key) as JSON
+        #[arg(long, conflicts_with = "value")]
+        json: bool,
+    },
+    /// Print this machine's settings as TOML for `import-config`. Secrets
+    /// (token, keys, user_id, device_id, password_command) are never included
+    ExportConfig,
+    /// Replace config.toml with settings printed by `export-config`
+    ImportConfig {
+        /// File to read (default: stdin)
+        file: Option<std::path::PathBuf>,
+    },
+    /// Change this account's username (keys and devices are kept)
+    RenameUser {
+        /// New username
+        new_username: String,
+    },
+    /// Sign out every device on this account, including this one
+    RevokeSessions,
+    /// Generate invite codes (admin only, daemon must be running)
+    Invite {
+        /// Number of codes to generate
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+    },
+    /// Instance administration (admin only)
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Manage and use shared team clipboards
+    Team {
+        #[command(subcommand)]
+        command: TeamCommand,
     },
-    /// Generate an invite code (admin only, daemon must be running)
-    Invite,
     /// Behave like xclip, backed by clipsync (for use as: alias xclip='clipsync xclip')
     #[command(trailing_var_arg = true, allow_hyphen_values = true)]
     Xclip {
@@ -63,42 +314,259 @@ enum Command {
         #[arg(short = 'n', long)]
         lines: Option<u32>,
     },
+    /// Show clipboard history
+    History {
+        /// Read the encrypted local history file instead of the server
+        #[arg(long)]
+        local: bool,
+        /// Delete the entry at this index (as listed) instead of listing
+        #[arg(long, value_name = "INDEX")]
+        delete: Option<usize>,
+        /// Only list this page of entries (from 1); indexes stay as in the full list
+        #[arg(long, value_name = "N", conflicts_with = "delete")]
+        page: Option<usize>,
+    },
+    /// Show the last clip each device synced, to recover one that another
+    /// device's clip overwrote
+    Recent {
+        /// Make this device's last clip (by ID or name) the current clip again
+        #[arg(long, value_name = "DEVICE")]
+        restore: Option<String>,
+    },
+    /// Browse local history interactively: restore or delete entries
+    #[cfg(feature = "tui")]
+    Tui,
+    /// Restore a clip from history to the clipboard
+    Restore {
+        /// History index to restore (0 is the most recent)
+        #[arg(default_value_t = 0)]
+        index: usize,
+        /// Restore from the encrypted local history file instead of the server
+        #[arg(long)]
+        local: bool,
+    },
     /// Restart the daemon service
     Restart,
     /// Install as a system service
     Install,
     /// Remove the system service
-    Uninstall,
+    Uninstall {
+        /// Also delete all local data: config, token, keys, device id, socket and xclip shims
+        #[arg(long)]
+        purge: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// Show server-wide usage stats
+    Stats,
+}
+
+#[derive(Subcommand)]
+enum TeamCommand {
+    /// Create a team (admin only)
+    Create {
+        /// Team name
+        name: String,
+    },
+    /// Add a user to a team (team owner or admin)
+    Add {
+        /// Team name
+        team: String,
+        /// Username to add
+        username: String,
+    },
+    /// Remove a user from a team (team owner or admin)
+    Remove {
+        /// Team name
+        team: String,
+        /// Username to remove
+        username: String,
+    },
+    /// List the teams you belong to
+    List,
+    /// Sync clipboard content to a team's shared clipboard
+    Copy {
+        /// Team name
+        team: String,
+    },
+    /// Get the latest clip from a team's shared clipboard
+    Paste {
+        /// Team name
+        team: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let quiet = cli.quiet;
 
     match cli.command {
-        Command::Daemon => {
-            tracing_subscriber::fmt()
-                .with_env_filter(
-                    tracing_subscriber::EnvFilter::try_from_default_env()
-                        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-                )
-                .init();
+        Command::Daemon { once, log_json } => {
+            let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+            if log_json {
+                tracing_subscriber::fmt()
+                    .json()
+                    .with_env_filter(filter)
+                    .init();
+            } else {
+                tracing_subscriber::fmt().with_env_filter(filter).init();
+            }
 
-            let config = config::Config::load().unwrap_or_default();
-            daemon::run_daemon(config).await?;
+            // Running against the default server because of a typo is worse
+            // than not running at all
+            let config = config::Config::load()
+                .context("Fix or remove config.toml before starting the daemon")?;
+            let mode = if once {
+                daemon::RunMode::Once
+            } else {
+                daemon::RunMode::Persistent
+            };
+            daemon::run_daemon(config, mode).await?;
+        }
+        Command::Setup {
+            username,
+            invite_code,
+            print_recovery,
+        } => cli::setup::run(username, invite_code, print_recovery).await?,
+        Command::ImportKey { recovery, force } => cli::import_key::run(recovery, force)?,
+        Command::MigrateServer {
+            from,
+            to,
+            database,
+            invite_code,
+        } => cli::migrate::run(from, to, database, invite_code).await?,
+        Command::Copy {
+            input,
+            clipboard,
+            append,
+            html,
+            local_only,
+            from_clipboard_target,
+            allow_empty,
+            clear_on_empty,
+            target_device,
+            dedup_window,
+            ttl,
+            max_size,
+            apply_min_size,
+            wait_confirm,
+            timeout,
+        } => {
+            let source = if input.is_some() {
+                cli::copy::CopySource::Stdin
+            } else if clipboard {
+                cli::copy::CopySource::Clipboard
+            } else {
+                cli::copy::CopySource::Auto
+            };
+            cli::copy::run(cli::copy::CopyOptions {
+                source,
+                append,
+                html,
+                local_only,
+                target: from_clipboard_target,
+                allow_empty,
+                clear_on_empty,
+                target_device,
+                dedup_window,
+                ttl,
+                max_size,
+                apply_min_size,
+                wait_confirm,
+                confirm_timeout: timeout,
+                quiet,
+            })
+            .await?
+        }
+        Command::Clear { local: _ } => cli::clear::run(quiet).await?,
+        Command::Paste { peek: true, .. } => cli::paste::peek().await?,
+        Command::Paste {
+            r#type,
+            consume,
+            gzip,
+            to_file,
+            wait,
+            after,
+            timeout,
+            only_new,
+            raw,
+            format,
+            peek: _,
+        } => {
+            cli::paste::run(cli::paste::PasteOptions {
+                type_only: r#type,
+                consume,
+                gzip,
+                to_file,
+                wait,
+                after,
+                timeout,
+                only_new,
+                raw,
+                format,
+                quiet,
+            })
+            .await?
         }
-        Command::Setup { username, invite_code } => cli::setup::run(username, invite_code).await?,
-        Command::Copy => cli::copy::run().await?,
-        Command::Paste { r#type } => cli::paste::run(r#type).await?,
-        Command::Status => cli::status::run().await?,
-        Command::Devices => cli::devices::run().await?,
-        Command::Config { key, value } => cli::config::run(key, value)?,
-        Command::Invite => cli::invite::run().await?,
+        Command::Send { .. } => cli::send::to_all(quiet).await?,
+        Command::Qr => cli::qr::run().await?,
+        Command::Bench => cli::bench::run()?,
+        Command::Tune { cpu_percent } => cli::tune::run(cpu_percent)?,
+        Command::Doctor { fix } => cli::doctor::run(fix)?,
+        Command::Status {
+            socket_only,
+            json,
+            local_size,
+        } => cli::status::run(socket_only, json, local_size).await?,
+        Command::Freeze { duration } => cli::freeze::freeze(duration, quiet).await?,
+        Command::Unfreeze => cli::freeze::unfreeze(quiet).await?,
+        Command::Devices {
+            merge: Some(old),
+            into,
+            ..
+        } => cli::devices::merge(old, into).await?,
+        Command::Devices { me, page, .. } => cli::devices::run(me, page).await?,
+        Command::Config { key, value, json } => cli::config::run(key, value, json)?,
+        Command::ExportConfig => cli::config::export()?,
+        Command::ImportConfig { file } => cli::config::import(file)?,
+        Command::RenameUser { new_username } => cli::rename_user::run(new_username, quiet).await?,
+        Command::RevokeSessions => cli::revoke_sessions::run(quiet).await?,
+        Command::Invite { count } => cli::invite::run(count).await?,
+        Command::Admin { command } => match command {
+            AdminCommand::Stats => cli::admin::stats().await?,
+        },
+        Command::Team { command } => match command {
+            TeamCommand::Create { name } => cli::team::create(name, quiet).await?,
+            TeamCommand::Add { team, username } => cli::team::add(team, username, quiet).await?,
+            TeamCommand::Remove { team, username } => {
+                cli::team::remove(team, username, quiet).await?
+            }
+            TeamCommand::List => cli::team::list().await?,
+            TeamCommand::Copy { team } => cli::team::copy(team, quiet).await?,
+            TeamCommand::Paste { team } => cli::team::paste(team, quiet).await?,
+        },
         Command::Xclip { args } => cli::xclip::run(args).await?,
         Command::Logs { follow, lines } => cli::logs::run(follow, lines)?,
+        Command::History {
+            local,
+            delete: Some(index),
+            ..
+        } => cli::history::delete(index, local, quiet).await?,
+        Command::History { local, page, .. } => cli::history::run(local, page)?,
+        Command::Restore { index, local } => cli::history::restore(index, local, quiet).await?,
+        Command::Recent {
+            restore: Some(device),
+        } => cli::recent::restore(device, quiet).await?,
+        Command::Recent { restore: None } => cli::recent::run().await?,
+        #[cfg(feature = "tui")]
+        Command::Tui => cli::tui::run().await?,
         Command::Restart => cli::restart::run()?,
         Command::Install => cli::install::install().await?,
-        Command::Uninstall => cli::install::uninstall().await?,
+        Command::Uninstall { purge } => cli::install::uninstall(purge).await?,
     }
 
     Ok(())
@@ -1,10 +1,59 @@
 use anyhow::{Context, Result};
 use bincode::Options;
 use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
 
 /// Maximum payload size for bincode serialization (64 MB).
 const MAX_PAYLOAD_SIZE: u64 = 64 * 1024 * 1024;
 
+/// Largest encrypted clip the server accepts (its `MAX_ENCRYPTED_SIZE`).
+pub const MAX_SYNC_SIZE: usize = 55 * 1024 * 1024;
+
+/// First bytes of every PNG file, used to tell images from text in raw bytes.
+pub const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Clip content types by the names the daemon reports them under (e.g. in
+/// `Response::ClipData`). Adding a type starts here; a newer daemon may
+/// report names this build doesn't know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Text,
+    Image,
+}
+
+impl ContentType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentType::Text => "text",
+            ContentType::Image => "image",
+        }
+    }
+
+    /// MIME type offered for the clip, e.g. to X11 clients asking for TARGETS.
+    pub fn mime(self) -> &'static str {
+        match self {
+            ContentType::Text => "text/plain",
+            ContentType::Image => "image/png",
+        }
+    }
+}
+
+impl std::str::FromStr for ContentType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ContentType::Text),
+            "image" => Ok(ContentType::Image),
+            other => Err(format!(
+                "Unsupported content type '{}': upgrade clipsync, \
+                 or use `clipsync paste --raw` to output the bytes as-is",
+                other
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClipboardPayload {
     Text(String),
@@ -23,6 +72,15 @@ impl ClipboardPayload {
             .with_context(|| "Failed to serialize clipboard payload")
     }
 
+    /// Length of `serialize()`'s output, without building it.
+    pub fn serialized_size(&self) -> Result<usize> {
+        bincode::DefaultOptions::new()
+            .with_limit(MAX_PAYLOAD_SIZE)
+            .serialized_size(self)
+            .map(|size| size as usize)
+            .with_context(|| "Failed to size clipboard payload")
+    }
+
     pub fn deserialize(data: &[u8]) -> Result<Self> {
         bincode::DefaultOptions::new()
             .with_limit(MAX_PAYLOAD_SIZE)
@@ -30,14 +88,146 @@ impl ClipboardPayload {
             .with_context(|| "Failed to deserialize clipboard payload")
     }
 
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            ClipboardPayload::Text(_) => ContentType::Text,
+            ClipboardPayload::Image { .. } => ContentType::Image,
+        }
+    }
+
     pub fn content_type_str(&self) -> &'static str {
+        self.content_type().as_str()
+    }
+
+    /// Hash of the clip content, for cheap "is this the same clip" checks.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        match self {
+            ClipboardPayload::Text(text) => text.hash(&mut hasher),
+            ClipboardPayload::Image { png_data, .. } => png_data.hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+
+    /// Size of the clip content (text bytes or PNG bytes).
+    pub fn data_len(&self) -> usize {
+        match self {
+            ClipboardPayload::Text(text) => text.len(),
+            ClipboardPayload::Image { png_data, .. } => png_data.len(),
+        }
+    }
+
+    /// Content-free description (type and size only), safe to log.
+    pub fn summary(&self) -> String {
         match self {
-            ClipboardPayload::Text(_) => "text",
-            ClipboardPayload::Image { .. } => "image",
+            ClipboardPayload::Text(text) => format!("text, {} bytes", text.len()),
+            ClipboardPayload::Image {
+                width,
+                height,
+                png_data,
+            } => format!("image {}x{}, {} bytes", width, height, png_data.len()),
+        }
+    }
+
+    /// Single-line preview of the content, truncated to `max_chars`.
+    /// Images are described rather than previewed.
+    pub fn preview(&self, max_chars: usize) -> String {
+        match self {
+            ClipboardPayload::Text(text) => {
+                let line: String = text
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .take(max_chars)
+                    .collect();
+                if text.chars().count() > max_chars {
+                    format!("{}...", line)
+                } else {
+                    line
+                }
+            }
+            ClipboardPayload::Image { .. } => format!("[{}]", self.summary()),
         }
     }
 }
 
+/// Join `addition` onto the end of `current`, separated by a newline.
+/// Both payloads must be text.
+pub fn append_text(
+    current: &ClipboardPayload,
+    addition: &ClipboardPayload,
+) -> Result<ClipboardPayload> {
+    let (ClipboardPayload::Text(current), ClipboardPayload::Text(addition)) = (current, addition)
+    else {
+        anyhow::bail!("Can only append text to a text clip");
+    };
+    let mut joined = current.clone();
+    if !joined.is_empty() && !joined.ends_with('\n') {
+        joined.push('\n');
+    }
+    joined.push_str(addition);
+    Ok(ClipboardPayload::Text(joined))
+}
+
+/// Plain-text rendering of an HTML fragment, used as the fallback format when
+/// HTML is put on the clipboard. Tags are dropped and common entities decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Reject clips that can't fit under the server's limit before spending time
+/// compressing and encrypting them. PNG data barely compresses further, so an
+/// image whose PNG alone is over the limit can never sync. Text is left to
+/// the server, since zstd can shrink it a lot.
+pub fn check_sync_size(payload: &ClipboardPayload) -> Result<(), String> {
+    match payload {
+        ClipboardPayload::Image { png_data, .. } if png_data.len() > MAX_SYNC_SIZE => Err(format!(
+            "Image too large to sync: {} MB as PNG, limit is {} MB",
+            png_data.len() / (1024 * 1024),
+            MAX_SYNC_SIZE / (1024 * 1024)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Enforce `max_clip_bytes` (or a `copy --max-size` override) on a
+/// serialized clip.
+pub fn check_clip_limit(size: usize, max_bytes: usize) -> Result<(), String> {
+    if size > max_bytes {
+        return Err(format!(
+            "Clip is {} bytes, over the {} byte limit \
+             (raise max_clip_bytes, or use copy --max-size)",
+            size, max_bytes
+        ));
+    }
+    Ok(())
+}
+
+/// Enforce `min_sync_bytes` on a serialized clip.
+pub fn check_clip_minimum(size: usize, min_bytes: usize) -> Result<(), String> {
+    if size < min_bytes {
+        return Err(format!(
+            "Clip is {} bytes, under the {} byte minimum (min_sync_bytes)",
+            size, min_bytes
+        ));
+    }
+    Ok(())
+}
+
 /// Convert raw RGBA pixel data to PNG bytes.
 pub fn rgba_to_png(rgba: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
     let img = image::RgbaImage::from_raw(width, height, rgba.to_vec())
@@ -48,6 +238,13 @@ pub fn rgba_to_png(rgba: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
     Ok(buf.into_inner())
 }
 
+/// Read a PNG's dimensions from its header without decoding the pixels.
+pub fn png_dimensions(png_data: &[u8]) -> Result<(u32, u32)> {
+    image::ImageReader::with_format(std::io::Cursor::new(png_data), image::ImageFormat::Png)
+        .into_dimensions()
+        .with_context(|| "Failed to read PNG header")
+}
+
 /// Convert PNG bytes back to raw RGBA pixel data, returning (width, height, rgba_bytes).
 pub fn png_to_rgba(png_data: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
     let img = image::load_from_memory_with_format(png_data, image::ImageFormat::Png)
@@ -57,6 +254,23 @@ pub fn png_to_rgba(png_data: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
     Ok((width, height, rgba.into_raw()))
 }
 
+/// Downscale PNG bytes to fit within `max_side` pixels, keeping the aspect
+/// ratio, and return the result as PNG. Small images are left at their size.
+pub fn thumbnail_png(png_data: &[u8], max_side: u32) -> Result<Vec<u8>> {
+    let img = image::load_from_memory_with_format(png_data, image::ImageFormat::Png)
+        .with_context(|| "Failed to decode PNG")?;
+    let thumb = if img.width() > max_side || img.height() > max_side {
+        img.thumbnail(max_side, max_side)
+    } else {
+        img
+    };
+    let mut buf = std::io::Cursor::new(Vec::new());
+    thumb
+        .write_to(&mut buf, image::ImageFormat::Png)
+        .with_context(|| "Failed to encode PNG")?;
+    Ok(buf.into_inner())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,6 +286,71 @@ mod tests {
         }
     }
 
+    #[test]
+    fn content_hash_tracks_content() {
+        let a = ClipboardPayload::Text("same".to_string());
+        let b = ClipboardPayload::Text("same".to_string());
+        let c = ClipboardPayload::Text("different".to_string());
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_ne!(a.content_hash(), c.content_hash());
+    }
+
+    #[test]
+    fn html_to_text_strips_tags_and_entities() {
+        assert_eq!(
+            html_to_text("<p>Fish &amp; <b>chips</b> &lt;3</p>"),
+            "Fish & chips <3"
+        );
+        assert_eq!(html_to_text("plain"), "plain");
+        assert_eq!(html_to_text("&amp;lt;"), "&lt;");
+    }
+
+    #[test]
+    fn check_sync_size_rejects_oversized_images() {
+        let image = |len: usize| ClipboardPayload::Image {
+            width: 1,
+            height: 1,
+            png_data: vec![0; len],
+        };
+        assert!(check_sync_size(&image(MAX_SYNC_SIZE)).is_ok());
+        assert!(check_sync_size(&image(MAX_SYNC_SIZE + 1)).is_err());
+        assert!(check_sync_size(&ClipboardPayload::Text("a".repeat(MAX_SYNC_SIZE + 1))).is_ok());
+    }
+
+    #[test]
+    fn check_clip_limit_allows_exact_size() {
+        assert!(check_clip_limit(1024, 1024).is_ok());
+        assert!(check_clip_limit(1025, 1024).is_err());
+    }
+
+    #[test]
+    fn check_clip_minimum_allows_exact_size() {
+        assert!(check_clip_minimum(2, 2).is_ok());
+        assert!(check_clip_minimum(1, 2).is_err());
+        assert!(check_clip_minimum(0, 0).is_ok());
+    }
+
+    #[test]
+    fn serialized_size_matches_serialize() {
+        let payload = ClipboardPayload::Text("hello".to_string());
+        assert_eq!(
+            payload.serialized_size().unwrap(),
+            payload.serialize().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn thumbnail_keeps_aspect_ratio() {
+        let png = rgba_to_png(&vec![255; 64 * 16 * 4], 64, 16).unwrap();
+        let thumb = thumbnail_png(&png, 32).unwrap();
+        let (width, height, _) = png_to_rgba(&thumb).unwrap();
+        assert_eq!((width, height), (32, 8));
+
+        let small = rgba_to_png(&[0; 4 * 4 * 4], 4, 4).unwrap();
+        let (width, height, _) = png_to_rgba(&thumbnail_png(&small, 32).unwrap()).unwrap();
+        assert_eq!((width, height), (4, 4));
+    }
+
     #[test]
     fn serialize_deserialize_image() {
         let payload = ClipboardPayload::Image {
@@ -118,6 +397,48 @@ mod tests {
         assert_eq!(recovered_rgba, rgba);
     }
 
+    #[test]
+    fn append_text_joins_with_newline() {
+        let current = ClipboardPayload::Text("first".to_string());
+        let addition = ClipboardPayload::Text("second".to_string());
+        match append_text(&current, &addition).unwrap() {
+            ClipboardPayload::Text(s) => assert_eq!(s, "first\nsecond"),
+            _ => panic!("Expected Text variant"),
+        }
+
+        let current = ClipboardPayload::Text("first\n".to_string());
+        match append_text(&current, &addition).unwrap() {
+            ClipboardPayload::Text(s) => assert_eq!(s, "first\nsecond"),
+            _ => panic!("Expected Text variant"),
+        }
+    }
+
+    #[test]
+    fn append_text_rejects_images() {
+        let image = ClipboardPayload::Image {
+            width: 1,
+            height: 1,
+            png_data: vec![],
+        };
+        let text = ClipboardPayload::Text("text".to_string());
+        assert!(append_text(&image, &text).is_err());
+        assert!(append_text(&text, &image).is_err());
+    }
+
+    #[test]
+    fn summary_has_no_content() {
+        let payload = ClipboardPayload::Text("hunter2".to_string());
+        assert_eq!(payload.summary(), "text, 7 bytes");
+        assert!(!payload.summary().contains("hunter2"));
+    }
+
+    #[test]
+    fn preview_truncates_and_flattens() {
+        let payload = ClipboardPayload::Text("line one\nline two".to_string());
+        assert_eq!(payload.preview(8), "line one...");
+        assert_eq!(payload.preview(100), "line one line two");
+    }
+
     #[test]
     fn content_type_str() {
         assert_eq!(
@@ -134,4 +455,14 @@ mod tests {
             "image"
         );
     }
+
+    #[test]
+    fn content_type_names_round_trip() {
+        for content_type in [ContentType::Text, ContentType::Image] {
+            assert_eq!(content_type.as_str().parse(), Ok(content_type));
+        }
+        let err = "files".parse::<ContentType>().unwrap_err();
+        assert!(err.contains("Unsupported content type 'files'"));
+        assert!(err.contains("--raw"));
+    }
 }
@@ -1,23 +1,283 @@
 use serde::{Deserialize, Serialize};
 
+use crate::payload::ClipboardPayload;
+
 /// Maximum IPC frame size (64 MB).
 pub const MAX_IPC_FRAME_SIZE: usize = 64 * 1024 * 1024;
 
+/// Clock skew against the server, in seconds, beyond which it is reported.
+pub const CLOCK_SKEW_WARN_SECS: i64 = 60;
+
+/// Check that `len` bytes fit in one IPC frame. The codec would otherwise
+/// reject the frame with an opaque framing error, so both sides check first.
+pub fn check_frame_size(len: usize) -> Result<(), String> {
+    if len > MAX_IPC_FRAME_SIZE {
+        return Err(format!(
+            "Input exceeds IPC limit: {} bytes (max {})",
+            len, MAX_IPC_FRAME_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// Most items one paged list request may ask for.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// A window onto a list that may be too long to send at once: `limit`
+/// items starting at `offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Page {
+    /// The `number`th page (from 1) of `size` items.
+    pub fn numbered(number: usize, size: usize) -> Self {
+        Self {
+            offset: number.saturating_sub(1).saturating_mul(size),
+            limit: size,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if !(1..=MAX_PAGE_SIZE).contains(&self.limit) {
+            return Err(format!(
+                "Page size must be from 1 to {} (got {})",
+                MAX_PAGE_SIZE, self.limit
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How a copy waits for other devices to confirm they applied it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WaitConfirm {
+    /// Distinct other devices that must confirm
+    pub devices: usize,
+    pub timeout_secs: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
-    Status,
-    Copy { data: Option<Vec<u8>> },
-    Paste,
-    ListDevices,
-    CreateInvite { code: String },
+    Status {
+        /// Answer from local daemon state only (cached username, no SpacetimeDB lookup)
+        #[serde(default)]
+        socket_only: bool,
+        /// Also measure the local clipboard's content (a clipboard read)
+        #[serde(default)]
+        local_size: bool,
+    },
+    Copy {
+        data: Option<Vec<u8>>,
+        /// Append to the current text clip instead of replacing it
+        #[serde(default)]
+        append: bool,
+        /// `data` is HTML: put it on the local clipboard as HTML with a
+        /// plain-text fallback, and sync the plain text
+        #[serde(default)]
+        html: bool,
+        /// Only set the local clipboard; don't sync to the server
+        #[serde(default)]
+        local_only: bool,
+        /// MIME type of the clipboard representation to read when `data` is
+        /// None (e.g. "text/html"); None is text, falling back to image
+        #[serde(default)]
+        target: Option<String>,
+        /// An empty clipboard is a successful no-op rather than an error
+        #[serde(default)]
+        allow_empty: bool,
+        /// Like `allow_empty`, but also remove the synced clip from the server
+        #[serde(default)]
+        clear_on_empty: bool,
+        /// Device name the clip is meant for; informational, every device can
+        /// still decrypt it
+        #[serde(default)]
+        target_device: Option<String>,
+        /// Skip the sync if the same content was synced within this many
+        /// milliseconds (0 never skips)
+        #[serde(default)]
+        dedup_window_ms: u64,
+        /// Have the server delete the clip after this many seconds
+        #[serde(default)]
+        ttl_secs: Option<u64>,
+        /// Size limit in bytes for this copy instead of `max_clip_bytes`;
+        /// capped at the server's limit
+        #[serde(default)]
+        max_size: Option<u64>,
+        /// Refuse content under `min_sync_bytes`, as the clipboard watcher
+        /// skips it; an explicit copy otherwise syncs any size
+        #[serde(default)]
+        apply_min_size: bool,
+        /// Only answer once other devices confirm they applied the clip
+        #[serde(default)]
+        wait_confirm: Option<WaitConfirm>,
+    },
+    Paste {
+        /// Atomically take the clip off the server so no other device can paste it
+        #[serde(default)]
+        consume: bool,
+    },
+    /// Describe the current clip without sending its content
+    Peek,
+    /// Wait for the next clip to arrive, or return at once if the current one
+    /// is newer than `after_micros` (server time, microseconds since the epoch)
+    PasteWait {
+        after_micros: Option<i64>,
+        timeout_secs: Option<u64>,
+    },
+    /// Empty the local system clipboard
+    ClearLocal,
+    /// All of the account's devices, or one page of them ordered by id
+    ListDevices {
+        #[serde(default)]
+        page: Option<Page>,
+    },
+    /// Fold a duplicate device into another of the account's devices and remove it
+    MergeDevice {
+        old_device_id: String,
+        new_device_id: String,
+    },
+    CreateInvite {
+        code: String,
+    },
+    Restore {
+        payload: ClipboardPayload,
+    },
+    /// The last clip each device synced, newest first
+    RecentByDevice,
+    /// Make a device's last kept clip the current clip again
+    RestoreDeviceClip {
+        sender_device_id: String,
+    },
+    /// Remove a local history entry. `index` is newest first, as listed, and
+    /// `timestamp` must match that entry
+    DeleteHistory {
+        index: usize,
+        timestamp: u64,
+    },
+    /// Change the account's username; the password is re-checked by the server
+    RenameUser {
+        new_username: String,
+        password: String,
+    },
+    /// Sign out every device on the account; the password is re-checked by the server
+    RevokeSessions {
+        password: String,
+    },
+    CreateTeam {
+        name: String,
+    },
+    AddTeamMember {
+        team: String,
+        username: String,
+    },
+    RemoveTeamMember {
+        team: String,
+        username: String,
+    },
+    ListTeams,
+    ServerStats,
+    TeamCopy {
+        team: String,
+        data: Option<Vec<u8>>,
+    },
+    TeamPaste {
+        team: String,
+    },
+    /// Stop applying incoming clips to the local clipboard, optionally for a limited time
+    Freeze {
+        for_secs: Option<u64>,
+    },
+    Unfreeze,
     Shutdown,
 }
 
+impl Request {
+    /// Variant name without any payload, safe to log.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Status { .. } => "Status",
+            Request::Copy { .. } => "Copy",
+            Request::Paste { .. } => "Paste",
+            Request::Peek => "Peek",
+            Request::PasteWait { .. } => "PasteWait",
+            Request::ClearLocal => "ClearLocal",
+            Request::ListDevices { .. } => "ListDevices",
+            Request::MergeDevice { .. } => "MergeDevice",
+            Request::CreateInvite { .. } => "CreateInvite",
+            Request::Restore { .. } => "Restore",
+            Request::RecentByDevice => "RecentByDevice",
+            Request::RestoreDeviceClip { .. } => "RestoreDeviceClip",
+            Request::DeleteHistory { .. } => "DeleteHistory",
+            Request::RenameUser { .. } => "RenameUser",
+            Request::RevokeSessions { .. } => "RevokeSessions",
+            Request::CreateTeam { .. } => "CreateTeam",
+            Request::AddTeamMember { .. } => "AddTeamMember",
+            Request::RemoveTeamMember { .. } => "RemoveTeamMember",
+            Request::ListTeams => "ListTeams",
+            Request::ServerStats => "ServerStats",
+            Request::TeamCopy { .. } => "TeamCopy",
+            Request::TeamPaste { .. } => "TeamPaste",
+            Request::Freeze { .. } => "Freeze",
+            Request::Unfreeze => "Unfreeze",
+            Request::Shutdown => "Shutdown",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceInfo {
     pub id: u64,
     pub device_id: String,
     pub device_name: String,
+    /// Unix seconds of the device's last registration, which it repeats on
+    /// every connect
+    #[serde(default)]
+    pub registered_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamInfo {
+    pub name: String,
+    pub owner: String,
+    pub members: Vec<String>,
+}
+
+/// The last clip a device synced, as kept by the server for restoring.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentClip {
+    pub sender_device_id: String,
+    /// None if the sender is no longer one of the account's devices
+    pub sender_device_name: Option<String>,
+    pub content_type: String,
+    pub size_bytes: u64,
+    /// Seconds since the Unix epoch, server time
+    pub updated_at: u64,
+    /// Whether this is still the current clip
+    pub current: bool,
+}
+
+/// What is on the local clipboard, without the content itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalClipboard {
+    pub content_type: String,
+    /// Bytes a copy would sync (text bytes or PNG bytes)
+    pub size_bytes: u64,
+}
+
+/// Liveness of one of the daemon's worker threads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadHealth {
+    pub name: String,
+    /// Seconds since the thread last went round its loop; None if it never did
+    pub last_tick_secs: Option<u64>,
+    /// False if the thread never started or has been quiet for too long
+    pub healthy: bool,
+    /// Times the thread was restarted after a panic
+    #[serde(default)]
+    pub restarts: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,18 +289,158 @@ pub enum Response {
         user_id: Option<u64>,
         device_id: String,
         watching: bool,
+        /// Local changes are being ignored because another clipboard manager
+        /// seems to be fighting over the clipboard
+        #[serde(default)]
+        clipboard_conflict: bool,
+        /// Incoming clips are not being applied to the local clipboard
+        #[serde(default)]
+        frozen: bool,
+        /// Time left on a timed freeze
+        #[serde(default)]
+        frozen_remaining_secs: Option<u64>,
+        /// Device name the latest received clip was meant for, if any
+        #[serde(default)]
+        last_clip_intended_device: Option<String>,
+        /// App the latest received clip was copied from, if its sender shared it
+        #[serde(default)]
+        last_clip_source_app: Option<String>,
+        /// Whether this device's key matches the server's; None until checked
+        #[serde(default)]
+        key_matches_server: Option<bool>,
+        /// Failed logins recorded against this account; None if not looked up
+        #[serde(default)]
+        failed_login_attempts: Option<u32>,
+        /// Local clock minus the server's, in seconds; None until measured
+        #[serde(default)]
+        clock_skew_secs: Option<i64>,
+        /// Why the last incoming clip couldn't be put on the local clipboard;
+        /// None once one applies
+        #[serde(default)]
+        last_apply_error: Option<String>,
+        #[serde(default)]
+        uptime_secs: Option<u64>,
+        /// Liveness of the daemon's worker threads
+        #[serde(default)]
+        threads: Vec<ThreadHealth>,
+        /// Asked for with `local_size`; None if not asked, empty or unreadable
+        #[serde(default)]
+        local_clipboard: Option<LocalClipboard>,
     },
     ClipData {
         content_type: String,
         data: Vec<u8>,
+        /// Served from the daemon's copy of the last clip it received,
+        /// because it is offline
+        #[serde(default)]
+        cached: bool,
+    },
+    /// What `Peek` found: the current clip's metadata and, for text, a preview
+    ClipMeta {
+        content_type: String,
+        /// Plaintext size as reported by the sender
+        size_bytes: u64,
+        sender_device_id: String,
+        /// None if the sender is no longer one of the account's devices
+        sender_device_name: Option<String>,
+        /// Seconds since the Unix epoch, server time
+        updated_at: u64,
+        /// Start of a text clip; None for images or if it can't be decrypted
+        preview: Option<String>,
+        /// App the clip was copied from, if the sender shared it
+        #[serde(default)]
+        source_app: Option<String>,
+    },
+    RecentByDevice {
+        clips: Vec<RecentClip>,
+    },
+    /// A copy repeated the last sync within its dedup window and was skipped
+    SkippedDuplicate,
+    /// A `wait_confirm` copy was applied by these other devices
+    Confirmed {
+        device_ids: Vec<String>,
+    },
+    /// A copy found the clipboard empty and `allow_empty` was set
+    NothingToCopy {
+        /// The synced clip was removed (`clear_on_empty`)
+        cleared: bool,
     },
     Devices {
         devices: Vec<DeviceInfo>,
+        /// Devices on the account, of which `devices` may be one page
+        #[serde(default)]
+        total: usize,
     },
     InviteCreated {
         code: String,
     },
+    Teams {
+        teams: Vec<TeamInfo>,
+    },
+    ServerStats {
+        total_users: u64,
+        total_devices: u64,
+        outstanding_invite_codes: u64,
+        total_teams: u64,
+        total_clip_bytes: u64,
+        #[serde(default)]
+        total_stored_bytes: u64,
+    },
     Error {
         message: String,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_size_within_limit() {
+        assert!(check_frame_size(0).is_ok());
+        assert!(check_frame_size(MAX_IPC_FRAME_SIZE).is_ok());
+    }
+
+    #[test]
+    fn oversized_frame_rejected() {
+        let err = check_frame_size(MAX_IPC_FRAME_SIZE + 1).unwrap_err();
+        assert!(err.contains("exceeds IPC limit"));
+    }
+
+    #[test]
+    fn oversized_copy_request_rejected() {
+        let request = Request::Copy {
+            data: Some(vec![b'x'; MAX_IPC_FRAME_SIZE / 3]),
+            append: false,
+            html: false,
+            local_only: false,
+            target: None,
+            allow_empty: false,
+            clear_on_empty: false,
+            target_device: None,
+            dedup_window_ms: 0,
+            ttl_secs: None,
+            max_size: None,
+            apply_min_size: false,
+            wait_confirm: None,
+        };
+        // Bytes serialize as a JSON array, so the encoded request is well
+        // over the limit even though the raw input is under it
+        let encoded = serde_json::to_vec(&request).unwrap();
+        assert!(check_frame_size(encoded.len()).is_err());
+    }
+
+    #[test]
+    fn page_limit_is_bounded() {
+        assert!(Page::numbered(1, MAX_PAGE_SIZE).check().is_ok());
+        assert!(Page::numbered(1, 0).check().is_err());
+        assert!(Page::numbered(1, MAX_PAGE_SIZE + 1).check().is_err());
+    }
+
+    #[test]
+    fn page_numbers_start_at_one() {
+        assert_eq!(Page::numbered(1, 10).offset, 0);
+        assert_eq!(Page::numbered(3, 10).offset, 20);
+        assert_eq!(Page::numbered(0, 10), Page::numbered(1, 10));
+    }
+}